@@ -1,432 +1,572 @@
 use crate::animgraph_definition::*;
-use crate::edges::*;
-use crate::node_definitions::GenericNodeDefinition;
-use crate::nodes::*;
+use crate::compiled_animgraph::CompiledAnimGraph;
+use crate::events::*;
+use crate::export::*;
+use crate::ik::*;
+use crate::layer_definitions::LayerBlendMode;
+use crate::layers::*;
+use crate::parameter_definitions::ParameterValue;
+use crate::parameters::*;
+use crate::root_motion::*;
+use crate::shared::*;
+use crate::state_machine::StateMachine;
 use anyhow::anyhow;
-use mapgraph::aliases::SlotMapGraph;
-use mapgraph::map::slotmap::EdgeIndex;
 use mapgraph::map::slotmap::NodeIndex;
 use ozz_animation_rs::*;
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::collections::HashSet;
-use std::collections::VecDeque;
-use std::rc::Rc;
-
-pub struct AnimGraph {
-    skeleton: Rc<Skeleton>,
-    graph: SlotMapGraph<GenericNode, TransitionIndex>,
-    samplers: SamplerNodesContainer<SamplerNode>,
-    blend_trees_one_dim: BlendTreeOneDimNodesContainer<BlendTreeOneDimNode>,
-    transitions: TransitionsContainer<Transition>,
-    root_node_idx: NodeIndex,
-    current_node_idx: Option<NodeIndex>,
-    current_edge_idx: Option<EdgeIndex>,
-    target: NodeIndex,
-    on_a_transition: bool,
-    path: VecDeque<EdgeIndex>,
-    dfs_node_under_evaluation: Option<NodeIndex>,
-    dfs_temp_edges_stack: Vec<EdgeIndex>,
-    dfs_visited: HashSet<NodeIndex>,
-    node_names: HashMap<String, NodeIndex>,
-    local_to_model_job: LocalToModelJobRc,
-    ozz_outputs: Rc<RefCell<Vec<SoaTransform>>>
+
+// One character playing a CompiledAnimGraph. Only holds what changes as it plays: where each state machine is, the
+// seeks, weights and parameter values, and the jobs and buffers the poses are computed in.
+pub struct AnimGraphInstance {
+    compiled: Shared<CompiledAnimGraph>,
+    base: StateMachine,
+    layers: Vec<AnimationLayer>,
+    // Blends the layers over the base. Unused without layers.
+    layer_blend_job: SharedBlendingJob,
+    blackboard: Blackboard,
+    events: Vec<AnimationEvent>,
+    // Root motion accumulated since the last take_root_motion().
+    root_motion: RootMotion,
+    two_bone_ik: Vec<TwoBoneIkChain>,
+    aim_ik: Vec<AimIkChain>,
+    local_to_model_job: SharedLocalToModelJob,
+    model_matrices: Buffer<glam::Mat4>,
+    ozz_outputs: Buffer<SoaTransform>
 }
 
-impl AnimGraph {
+// For a single character, AnimGraph::new() compiles a definition for the instance alone.
+pub type AnimGraph = AnimGraphInstance;
+
+impl AnimGraphInstance {
+    // Compiles the definition for this instance alone. Characters playing the same definition should rather share a
+    // CompiledAnimGraph through from_compiled().
     pub fn new(
-        skeleton: Rc<Skeleton>,
+        skeleton: Shared<Skeleton>,
         animgraph_definition: &AnimGraphDefinition,
-        animations_by_name: &HashMap<String, Rc<Animation>>,
+        animations_by_name: &HashMap<String, Shared<Animation>>,
     ) -> Result<Self, anyhow::Error> {
-        match animgraph_definition.root {
-            Some(val) => {
-                let node_opt = animgraph_definition.graph.node(val);
-                match node_opt {
-                    Some(_) => {}
-                    None => {
-                        return Err(anyhow!("Invalid root node in animgraph definition"));
-                    }
-                }
-            }
-            None => {
-                return Err(anyhow!("No root node found in animgraph definition"));
-            }
-        }
-        let mut graph = SlotMapGraph::<GenericNode, TransitionIndex>::with_capacities(
-            animgraph_definition.graph.nodes_count(),
-            animgraph_definition.graph.edges_count(),
-        );
-        let mut samplers = SamplerNodesContainer::<SamplerNode>::new();
-        // Go over each node in the animgraph's definition and add it to the final graph, saving its definition node/final node pair in a map
-        let mut node_mappings = HashMap::<NodeIndex, NodeIndex>::new();
-        let mut node_names = HashMap::<String, NodeIndex>::new();
-        for (node_definition_idx, node_definition) in animgraph_definition.graph.node_weights() {
-            match node_definition {
-                GenericNodeDefinition::Sampler(val) => {
-                    if !animations_by_name.contains_key(&val.animation_name) {
-                        return Err(anyhow!(
-                            "Could not find animation name {}",
-                            &val.animation_name
-                        ));
-                    }
-                    if node_names.contains_key(&val.name) {
-                        return Err(anyhow!("Duplicate node name: {}", &val.name));
-                    }
-                    let animation = &animations_by_name[&val.animation_name];
-                    let sampler_node =
-                        SamplerNode::new(skeleton.clone(), animation.clone(), val.looping);
-                    let sampler_idx = samplers.push(sampler_node);
-                    let node_idx =
-                        graph.add_node(GenericNode::Sampler(SamplerNodeIndex::from(sampler_idx)));
-                    node_names.insert(val.name.clone(), node_idx);
-                    node_mappings.insert(node_definition_idx, node_idx);
-                }
-                GenericNodeDefinition::BlendTreeOneDim(val) => {} // DO LATER
-            }
-        }
-        // TODO
-        let blend_trees_one_dim = BlendTreeOneDimNodesContainer::<BlendTreeOneDimNode>::new();
-
-        let mut transitions = TransitionsContainer::<Transition>::new();
-
-        // Go over each edge in the animgraph's definition and add it to the final graph, using the node mapping to find the appropriate final node.
-        for (edge_definition_idx, _) in animgraph_definition.graph.edge_weights() {
-            let edge_definition = animgraph_definition.graph.edge(edge_definition_idx);
-            match edge_definition {
-                Some(val) => {
-                    if !node_mappings.contains_key(&val.from()) {
-                        return Err(anyhow!("Invalid \"from\" node in edge"));
-                    }
-                    if !node_mappings.contains_key(&val.to()) {
-                        return Err(anyhow!("Invalid \"to\" node in edge"));
-                    }
-                    let from_idx = node_mappings[&val.from()];
-                    let from_node = graph.node(from_idx).unwrap().weight();
-                    let from_output: Rc<RefCell<Vec<SoaTransform>>>;
-                    match from_node {
-                        GenericNode::Sampler(val) => {
-                            from_output = samplers[*val].output.clone();
-                        }
-                        GenericNode::BlendTreeOneDim(val) => {
-                            from_output = blend_trees_one_dim[*val].output.clone();
-                        }
-                    }
-                    let to_idx = node_mappings[&val.to()];
-                    let to_node = graph.node(to_idx).unwrap().weight();
-                    let to_output: Rc<RefCell<Vec<SoaTransform>>>;
-                    match to_node {
-                        GenericNode::Sampler(val) => {
-                            to_output = samplers[*val].output.clone();
-                        }
-                        GenericNode::BlendTreeOneDim(val) => {
-                            to_output = blend_trees_one_dim[*val].output.clone();
-                        }
-                    }
-                    let transition = Transition::new(
-                        skeleton.clone(),
-                        val.weight().duration,
-                        from_output,
-                        to_output,
-                    );
-                    let transition_idx = transitions.push(transition);
-                    let _ = graph.add_edge(transition_idx, from_idx, to_idx);
-                }
-                None => {
-                    return Err(anyhow!("Invalid edge found in graph definition"));
-                }
+        let compiled = CompiledAnimGraph::new(skeleton, animgraph_definition, animations_by_name)?;
+        Ok(Self::from_compiled(&Shared::new(compiled)))
+    }
+
+    pub fn from_compiled(compiled: &Shared<CompiledAnimGraph>) -> Self {
+        let skeleton = compiled.skeleton.clone();
+        let base = StateMachine::new(skeleton.clone(), compiled.base.clone());
+        let layers = compiled
+            .layers
+            .iter()
+            .map(|layer| AnimationLayer::new(skeleton.clone(), layer.clone()))
+            .collect::<Vec<AnimationLayer>>();
+
+        let mut layer_blend_job = SharedBlendingJob::default();
+        layer_blend_job.set_skeleton(skeleton.clone());
+        let joint_weights = vec![glam::Vec4::ONE; skeleton.num_soa_joints()];
+        layer_blend_job
+            .layers_mut()
+            .push(BlendingLayer::with_joint_weights(base.output(), joint_weights.clone()));
+        for layer in &layers {
+            let blend_layer = BlendingLayer::with_joint_weights(layer.state_machine.output(), joint_weights.clone());
+            match layer.blend_mode() {
+                LayerBlendMode::Override => layer_blend_job.layers_mut().push(blend_layer),
+                LayerBlendMode::Additive => layer_blend_job.additive_layers_mut().push(blend_layer),
             }
         }
-        let root_node_idx = node_mappings[&animgraph_definition.root.unwrap()];
-        let current_node_idx = Some(root_node_idx);
-        let target = root_node_idx;
-        let dfs_node_under_evaluation = None;
-        let path = VecDeque::<EdgeIndex>::new();
+        let layer_blend_output = new_buffer(vec![SoaTransform::default(); skeleton.num_soa_joints()]);
+        layer_blend_job.set_output(layer_blend_output.clone());
 
-        let mut local_to_model_job = LocalToModelJobRc::default();
+        let two_bone_ik = compiled
+            .two_bone_ik
+            .iter()
+            .map(|layout| TwoBoneIkChain::new(layout.clone()))
+            .collect::<Vec<TwoBoneIkChain>>();
+        let aim_ik = compiled
+            .aim_ik
+            .iter()
+            .map(|layout| AimIkChain::new(layout.clone()))
+            .collect::<Vec<AimIkChain>>();
+        // IK edits the pose in place, so it gets a buffer of its own even without layers.
+        let ozz_outputs = if layers.is_empty() && two_bone_ik.is_empty() && aim_ik.is_empty() {
+            base.output()
+        } else {
+            layer_blend_output
+        };
+
+        let mut local_to_model_job = SharedLocalToModelJob::default();
         local_to_model_job.set_skeleton(skeleton.clone());
-        let current_node = graph.node(root_node_idx).unwrap().weight();
-        let ozz_outputs: Rc<RefCell<Vec<SoaTransform>>>;
-        match current_node {
-            GenericNode::Sampler(val) => {
-                local_to_model_job.set_input(samplers[*val].output.clone());
-                ozz_outputs = samplers[*val].output.clone();
-            }
-            GenericNode::BlendTreeOneDim(val) => {
-                local_to_model_job.set_input(blend_trees_one_dim[*val].output.clone());
-                ozz_outputs = blend_trees_one_dim[*val].output.clone();
-            }
-        }
+        local_to_model_job.set_input(ozz_outputs.clone());
+        let model_matrices = new_buffer(vec![glam::Mat4::IDENTITY; skeleton.num_joints()]);
+        local_to_model_job.set_output(model_matrices.clone());
 
-        Ok(AnimGraph {
-            skeleton: skeleton.clone(),
-            graph,
-            samplers,
-            blend_trees_one_dim,
-            transitions,
-            root_node_idx,
-            current_node_idx,
-            current_edge_idx: None,
-            target,
-            path,
-            on_a_transition: false,
-            dfs_temp_edges_stack: Vec::<EdgeIndex>::new(),
-            dfs_visited: HashSet::<NodeIndex>::new(),
-            dfs_node_under_evaluation,
-            node_names,
+        AnimGraphInstance {
+            compiled: compiled.clone(),
+            base,
+            layers,
+            layer_blend_job,
+            blackboard: compiled.blackboard.clone(),
+            events: Vec::new(),
+            root_motion: NO_ROOT_MOTION,
+            two_bone_ik,
+            aim_ik,
             local_to_model_job,
+            model_matrices,
             ozz_outputs
-        })
+        }
+    }
+
+    pub fn compiled(&self) -> &Shared<CompiledAnimGraph> {
+        &self.compiled
     }
 
     pub fn evaluate(&mut self, dt: web_time::Duration) -> Result<(), anyhow::Error> {
-        // Handle the transition case
-        let mut ratio = 0.0;
-        if self.on_a_transition {
-            match self.current_edge_idx {
-                Some(val) => {
-                    let edge = self.graph.edge(val).unwrap();
-                    let transition_idx = edge.weight();
-                    let from = self.graph.node(edge.from()).unwrap().weight();
-                    // Calculate the time taken and check whether or not the transition is finished.
-                    self.transitions[*transition_idx].seek += dt;
-                    let finished: bool;
-                    if self.transitions[*transition_idx].seek
-                        >= self.transitions[*transition_idx].duration
-                    {
-                        finished = true;
-                    } else {
-                        finished = false;
-                    }
-                    // If finished, move onto the next node.
-                    if finished {
-                        self.transitions[*transition_idx].reset();
-                        let front = self.path.front();
-                        match front {
-                            Some(val) => {
-                                self.current_edge_idx = Some(*val);
-                                self.path.pop_front();
-                                self.on_a_transition = false;
-                            }
-                            None => {}
-                        }
-                    } else {
-                        // Otherwise, evaluate the transition
-                        match from {
-                            GenericNode::Sampler(val) => {
-                                self.samplers[*val].update(dt);
-                            }
-                            GenericNode::BlendTreeOneDim(val) => {
-                                self.blend_trees_one_dim[*val].update(dt);
-                            }
-                        }
-                        let to = self.graph.node(edge.to()).unwrap().weight();
-                        match to {
-                            GenericNode::Sampler(val) => {
-                                self.samplers[*val].update(dt);
-                            }
-                            GenericNode::BlendTreeOneDim(val) => {
-                                self.blend_trees_one_dim[*val].update(dt);
-                            }
-                        }
-                        // Set the blend layer weights based on time elapsed.
-                        self.transitions[*transition_idx].seek =
-                            self.transitions[*transition_idx].seek + dt;
-                        ratio = (self.transitions[*transition_idx].duration.as_millis() as f32
-                            / self.transitions[*transition_idx].seek.as_millis() as f32)
-                            .clamp(0.0, 1.0);
-
-                        self.transitions[*transition_idx].blend_job.layers_mut()[0].weight = ratio;
-                        self.transitions[*transition_idx].blend_job.layers_mut()[0].weight =
-                            1.0 - ratio;
-
-                        let results = self.transitions[*transition_idx].blend_job.run();
-                        match results {
-                            Ok(_) => {}
-                            Err(e) => {
-                                return Err(anyhow! {"Ozz error during transition blend: {}", e});
-                            }
-                        }
-                    }
-                }
-                None => return Err(anyhow! {"Invalid current transition during evaluation."}),
+        self.events.clear();
+        self.base.evaluate(dt, &mut self.blackboard)?;
+        self.events.extend_from_slice(self.base.fired_events());
+        self.root_motion = compose(self.root_motion, self.base.root_motion());
+        for layer in &mut self.layers {
+            layer.state_machine.evaluate(dt, &mut self.blackboard)?;
+            self.events.extend_from_slice(layer.state_machine.fired_events());
+        }
+
+        if self.layers.is_empty() {
+            // The base's output changes buffer whenever it enters or leaves a transition.
+            if self.two_bone_ik.is_empty() && self.aim_ik.is_empty() {
+                self.ozz_outputs = self.base.output();
+            } else {
+                self.ozz_outputs.write_buf().copy_from_slice(&self.base.output().read_buf());
             }
         } else {
-            // If we are on a node. Far simpler to evaluate
-            match self.current_node_idx {
-                Some(val) => {
-                    // TODO: Evaluate node
-                    let node = self.graph.node(val).unwrap().weight();
-                    match node {
-                        GenericNode::Sampler(val) => {
-                            self.samplers[*val].update(dt);
-                            // : Get results of sampler to output
-                        }
-                        GenericNode::BlendTreeOneDim(val) => {
-                            self.blend_trees_one_dim[*val].update(dt);
-                        }
-                    }
-                }
-                None => return Err(anyhow! {"Invalid current node during evaluation."}),
-            }
-        }
-        // Now we check whether we are in need of transitioning through the path list, or if we're on target
-        let mut first_time_on_transition = false;
-        let mut first_time_on_node = false;
-        if self.on_a_transition && ratio >= 1.0 {
-            let last_transition_idx = self
-                .graph
-                .edge(self.current_edge_idx.unwrap())
-                .unwrap()
-                .weight();
-            self.transitions[*last_transition_idx].reset();
-            match self.path.front() {
-                Some(val) => {
-                    self.current_edge_idx = Some(*val);
-                    self.current_node_idx = None;
-                    self.path.pop_front();
-                    first_time_on_transition = true;
-                }
-                None => {
-                    let t = self
-                        .graph
-                        .edge(self.current_edge_idx.unwrap())
-                        .unwrap()
-                        .to();
-                    if t != self.target {
-                        return Err(anyhow! {"Path ended on a non-target node"});
-                    }
-                    self.current_node_idx = Some(t);
-                    self.current_edge_idx = None;
-                    first_time_on_node = true;
+            update_layer_blend(
+                self.base.output(),
+                &mut self.layers,
+                &mut self.layer_blend_job,
+                self.compiled.skeleton.num_joints(),
+            );
+            let results = self.layer_blend_job.run();
+            match results {
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(anyhow! {"Ozz error during layer blend: {}", e});
                 }
             }
         }
+        self.local_to_model_job.clear_input();
+        self.local_to_model_job.set_input(self.ozz_outputs.clone());
         let l2m_results = self.local_to_model_job.run();
         match l2m_results {
             Ok(_) => {}
             Err(e) => {return Err(anyhow!{"Error running local-to-model job: {}", e})}
         }
-        // If this is the first time we're entering a transition or we find our final target, clone() the outputs to our local2model job's inputs
-        if first_time_on_transition {
-            let current_transition_idx = self
-                .graph
-                .edge(self.current_edge_idx.unwrap())
-                .unwrap()
-                .weight();
-            self.local_to_model_job.clear_input();
-            self.local_to_model_job
-                .set_input(self.transitions[*current_transition_idx].output.clone());
-            self.ozz_outputs = self.transitions[*current_transition_idx].output.clone();
+        self.apply_ik()?;
+
+        Ok(())
+    }
+
+    // Corrects the local pose chain by chain, updating the model matrices below each correction so that the next
+    // one starts from the corrected pose.
+    fn apply_ik(&mut self) -> Result<(), anyhow::Error> {
+        for chain in &mut self.two_bone_ik {
+            let applied = chain.apply(&mut self.ozz_outputs.write_buf(), &self.model_matrices.read_buf())?;
+            if applied {
+                update_model_matrices_from(&mut self.local_to_model_job, chain.start_joint())?;
+            }
         }
-        if first_time_on_node {
-            self.local_to_model_job.clear_input();
-
-            let current_node = self
-                .graph
-                .node(self.current_node_idx.unwrap())
-                .unwrap()
-                .weight();
-            match current_node {
-                GenericNode::Sampler(val) => {
-                    self.local_to_model_job
-                        .set_input(self.samplers[*val].output.clone());
-                    self.ozz_outputs = self.samplers[*val].output.clone();
-                }
-                GenericNode::BlendTreeOneDim(val) => {
-                    self.local_to_model_job
-                        .set_input(self.blend_trees_one_dim[*val].output.clone());
-                    self.ozz_outputs = self.blend_trees_one_dim[*val].output.clone();
-                }
+        for chain in self.aim_ik.iter_mut().filter(|chain| chain.is_active()) {
+            for i in 0..chain.num_joints() {
+                chain.apply_joint(i, &mut self.ozz_outputs.write_buf(), &self.model_matrices.read_buf())?;
+                update_model_matrices_from(&mut self.local_to_model_job, chain.joint(i))?;
             }
         }
+        Ok(())
+    }
+
+    pub fn get_skeletal_matrices(&mut self) -> Buffer<glam::Mat4> {
+        self.model_matrices.clone()
+    }
 
+    // Makes a two-bone IK chain reach `target` from the next evaluate() on, bending towards `pole_vector`. Both are
+    // in model space.
+    pub fn set_two_bone_ik_target(
+        &mut self,
+        chain_name: String,
+        target: glam::Vec3,
+        pole_vector: glam::Vec3,
+    ) -> Result<(), anyhow::Error> {
+        let chain = self.two_bone_ik_chain_mut(&chain_name)?;
+        chain.target = Some(target);
+        chain.pole_vector = pole_vector;
         Ok(())
     }
 
-    pub fn get_skeletal_matrices(&mut self) -> Rc<RefCell<Vec<glam::Mat4>>> {
-        let results = self.local_to_model_job.output().unwrap();
-        results.clone()
+    // Leaves the chain as animated.
+    pub fn clear_two_bone_ik_target(&mut self, chain_name: String) -> Result<(), anyhow::Error> {
+        self.two_bone_ik_chain_mut(&chain_name)?.target = None;
+        Ok(())
     }
 
-    pub fn get_soa_transforms(&mut self) -> Rc<RefCell<Vec<SoaTransform>>> {
-        self.ozz_outputs.clone()
+    // Fades a two-bone IK chain in or out, from 0 (as animated) to 1 (fully corrected).
+    pub fn set_two_bone_ik_weight(&mut self, chain_name: String, weight: f32) -> Result<(), anyhow::Error> {
+        self.two_bone_ik_chain_mut(&chain_name)?.set_weight(weight);
+        Ok(())
+    }
+
+    pub fn get_two_bone_ik_weight(&self, chain_name: String) -> Option<f32> {
+        self.two_bone_ik
+            .iter()
+            .find(|chain| chain.name() == chain_name)
+            .map(|chain| chain.weight())
+    }
+
+    // Whether the chain's end joint reached its target on the last evaluate().
+    pub fn two_bone_ik_reached(&self, chain_name: String) -> Option<bool> {
+        self.two_bone_ik
+            .iter()
+            .find(|chain| chain.name() == chain_name)
+            .map(|chain| chain.target.is_some() && chain.reached())
     }
 
-    pub fn set_target_node_by_idx(&mut self, node_idx: NodeIndex) {
-        self.dfs(node_idx);
+    // Turns an aim IK chain towards `target`, in model space, from the next evaluate() on.
+    pub fn set_aim_ik_target(&mut self, chain_name: String, target: glam::Vec3) -> Result<(), anyhow::Error> {
+        self.aim_ik_chain_mut(&chain_name)?.target = Some(target);
+        Ok(())
     }
 
-    pub fn set_target_node_by_name(&mut self, node_name: String) {
-        if self.node_names.contains_key(&node_name) {
-            let node_idx = self.node_names[&node_name];
-            self.dfs(node_idx);
+    pub fn set_aim_ik_pole_vector(&mut self, chain_name: String, pole_vector: glam::Vec3) -> Result<(), anyhow::Error> {
+        self.aim_ik_chain_mut(&chain_name)?.pole_vector = pole_vector;
+        Ok(())
+    }
+
+    pub fn clear_aim_ik_target(&mut self, chain_name: String) -> Result<(), anyhow::Error> {
+        self.aim_ik_chain_mut(&chain_name)?.target = None;
+        Ok(())
+    }
+
+    pub fn set_aim_ik_weight(&mut self, chain_name: String, weight: f32) -> Result<(), anyhow::Error> {
+        self.aim_ik_chain_mut(&chain_name)?.set_weight(weight);
+        Ok(())
+    }
+
+    pub fn get_aim_ik_weight(&self, chain_name: String) -> Option<f32> {
+        self.aim_ik
+            .iter()
+            .find(|chain| chain.name() == chain_name)
+            .map(|chain| chain.weight())
+    }
+
+    // Whether the target was in front of the chain on the last evaluate().
+    pub fn aim_ik_reached(&self, chain_name: String) -> Option<bool> {
+        self.aim_ik
+            .iter()
+            .find(|chain| chain.name() == chain_name)
+            .map(|chain| chain.is_active() && chain.reached())
+    }
+
+    fn aim_ik_chain_mut(&mut self, chain_name: &str) -> Result<&mut AimIkChain, anyhow::Error> {
+        match self.aim_ik.iter_mut().find(|chain| chain.name() == chain_name) {
+            Some(val) => Ok(val),
+            None => Err(anyhow!("Unknown aim IK chain: {}", chain_name)),
         }
     }
 
-    pub fn get_node_by_name(&mut self, node_name: String) -> Option<NodeIndex> {
-        if self.node_names.contains_key(&node_name) {
-            let idx = self.node_names[&node_name];
-            return Some(idx);
+    fn two_bone_ik_chain_mut(&mut self, chain_name: &str) -> Result<&mut TwoBoneIkChain, anyhow::Error> {
+        match self.two_bone_ik.iter_mut().find(|chain| chain.name() == chain_name) {
+            Some(val) => Ok(val),
+            None => Err(anyhow!("Unknown IK chain: {}", chain_name)),
         }
-        None
     }
 
-    fn dfs(&mut self, target: NodeIndex) {
-        self.target = target;
-        self.path.clear();
-        self.dfs_temp_edges_stack.clear();
-        self.dfs_visited.clear();
-        self.dfs_node_under_evaluation = self.current_node_idx;
-        self.dfs_helper();
+    pub fn get_soa_transforms(&mut self) -> Buffer<SoaTransform> {
+        self.ozz_outputs.clone()
     }
 
-    fn dfs_helper(&mut self) {
-        // Get the last item in the path, check to see if its been visited, add it to path stack, and add to the visited set
-        if self
-            .dfs_visited
-            .contains(&self.dfs_node_under_evaluation.unwrap())
-        {
-            self.dfs_temp_edges_stack.pop();
-        } else {
-            self.dfs_visited
-                .insert(self.dfs_node_under_evaluation.unwrap());
+    // The events fired by the last evaluate(), in the order their nodes were updated, the base's before the layers'.
+    // During a transition both the source and destination nodes fire.
+    pub fn get_events(&self) -> &[AnimationEvent] {
+        &self.events
+    }
+
+    // The root motion accumulated by evaluate() since the last call, as a translation in the character's frame at the
+    // time of the previous call followed by a rotation. Always empty unless the definition enables root motion.
+    pub fn take_root_motion(&mut self) -> (glam::Vec3, glam::Quat) {
+        std::mem::replace(&mut self.root_motion, NO_ROOT_MOTION)
+    }
+
+    pub fn get_skeleton(&self) -> Shared<Skeleton> {
+        self.compiled.skeleton.clone()
+    }
+
+    pub fn get_root_node(&self) -> NodeIndex {
+        self.base.root_node()
+    }
+
+    pub fn set_target_node_by_idx(&mut self, node_idx: NodeIndex) -> Result<(), anyhow::Error> {
+        self.base.route_to(node_idx)
+    }
+
+    pub fn set_target_node_by_name(&mut self, node_name: String) -> Result<(), anyhow::Error> {
+        self.base.set_target_node_by_name(&node_name)
+    }
+
+    pub fn set_layer_target_node_by_name(&mut self, layer_name: String, node_name: String) -> Result<(), anyhow::Error> {
+        self.layer_mut(&layer_name)?
+            .state_machine
+            .set_target_node_by_name(&node_name)
+    }
+
+    // Fades a layer in or out, from 0 (no effect) to 1 (fully overrides, or adds to, its masked joints).
+    pub fn set_layer_weight(&mut self, layer_name: String, weight: f32) -> Result<(), anyhow::Error> {
+        self.layer_mut(&layer_name)?.weight = weight.clamp(0.0, 1.0);
+        Ok(())
+    }
+
+    pub fn get_layer_weight(&self, layer_name: String) -> Option<f32> {
+        self.layers
+            .iter()
+            .find(|layer| layer.name() == layer_name)
+            .map(|layer| layer.weight)
+    }
+
+    fn layer_mut(&mut self, layer_name: &str) -> Result<&mut AnimationLayer, anyhow::Error> {
+        match self.layers.iter_mut().find(|layer| layer.name() == layer_name) {
+            Some(val) => Ok(val),
+            None => Err(anyhow!("Unknown layer: {}", layer_name)),
         }
-        let mut backtracking = true;
-        for (edge_index, edge_ref) in self.graph.outputs(self.dfs_node_under_evaluation.unwrap()) {
-            if !self.dfs_visited.contains(&edge_ref.to()) {
-                backtracking = false;
-                self.dfs_temp_edges_stack.push(edge_index);
-                self.dfs_node_under_evaluation = Some(edge_ref.to());
-                break;
-            }
+    }
+
+    pub fn set_blend_param_by_name(&mut self, node_name: String, param: f32) {
+        self.base.set_blend_param_by_name(&node_name, param);
+    }
+
+    pub fn set_blend_param_2d_by_name(&mut self, node_name: String, param: glam::Vec2) {
+        self.base.set_blend_param_2d_by_name(&node_name, param);
+    }
+
+    pub fn get_parameter_by_name(&self, parameter_name: String) -> Option<ParameterIndex> {
+        self.blackboard.get_parameter_by_name(&parameter_name)
+    }
+
+    pub fn get_parameter_by_idx(&self, parameter_idx: ParameterIndex) -> ParameterValue {
+        self.blackboard.get(parameter_idx)
+    }
+
+    pub fn get_parameter(&self, parameter_name: String) -> Option<ParameterValue> {
+        self.blackboard
+            .get_parameter_by_name(&parameter_name)
+            .map(|idx| self.blackboard.get(idx))
+    }
+
+    pub fn set_float(&mut self, parameter_name: String, value: f32) -> Result<(), anyhow::Error> {
+        self.set_parameter(parameter_name, ParameterValue::Float(value))
+    }
+
+    pub fn set_float_by_idx(&mut self, parameter_idx: ParameterIndex, value: f32) -> Result<(), anyhow::Error> {
+        self.blackboard.set(parameter_idx, ParameterValue::Float(value))
+    }
+
+    pub fn set_int(&mut self, parameter_name: String, value: i32) -> Result<(), anyhow::Error> {
+        self.set_parameter(parameter_name, ParameterValue::Int(value))
+    }
+
+    pub fn set_int_by_idx(&mut self, parameter_idx: ParameterIndex, value: i32) -> Result<(), anyhow::Error> {
+        self.blackboard.set(parameter_idx, ParameterValue::Int(value))
+    }
+
+    pub fn set_bool(&mut self, parameter_name: String, value: bool) -> Result<(), anyhow::Error> {
+        self.set_parameter(parameter_name, ParameterValue::Bool(value))
+    }
+
+    pub fn set_bool_by_idx(&mut self, parameter_idx: ParameterIndex, value: bool) -> Result<(), anyhow::Error> {
+        self.blackboard.set(parameter_idx, ParameterValue::Bool(value))
+    }
+
+    pub fn set_vec2(&mut self, parameter_name: String, value: glam::Vec2) -> Result<(), anyhow::Error> {
+        self.set_parameter(parameter_name, ParameterValue::Vec2(value))
+    }
+
+    pub fn set_vec2_by_idx(&mut self, parameter_idx: ParameterIndex, value: glam::Vec2) -> Result<(), anyhow::Error> {
+        self.blackboard.set(parameter_idx, ParameterValue::Vec2(value))
+    }
+
+    pub fn set_trigger(&mut self, parameter_name: String) -> Result<(), anyhow::Error> {
+        self.set_parameter(parameter_name, ParameterValue::Trigger(true))
+    }
+
+    pub fn set_trigger_by_idx(&mut self, parameter_idx: ParameterIndex) -> Result<(), anyhow::Error> {
+        self.blackboard.set(parameter_idx, ParameterValue::Trigger(true))
+    }
+
+    pub fn reset_trigger(&mut self, parameter_name: String) -> Result<(), anyhow::Error> {
+        self.set_parameter(parameter_name, ParameterValue::Trigger(false))
+    }
+
+    pub fn reset_trigger_by_idx(&mut self, parameter_idx: ParameterIndex) -> Result<(), anyhow::Error> {
+        self.blackboard.set(parameter_idx, ParameterValue::Trigger(false))
+    }
+
+    fn set_parameter(&mut self, parameter_name: String, value: ParameterValue) -> Result<(), anyhow::Error> {
+        match self.blackboard.get_parameter_by_name(&parameter_name) {
+            Some(idx) => self.blackboard.set(idx, value),
+            None => Err(anyhow!("Unknown parameter: {}", parameter_name)),
         }
-        if backtracking {
-            let last_node = self
-                .graph
-                .edge(*self.dfs_temp_edges_stack.last().unwrap())
-                .unwrap()
-                .from();
-            self.dfs_node_under_evaluation = Some(last_node);
-            self.dfs_temp_edges_stack.pop();
+    }
+
+    pub fn get_node_by_name(&mut self, node_name: String) -> Option<NodeIndex> {
+        self.base.node_by_name(&node_name)
+    }
+
+    // A snapshot of the live graph: the current node, the transition being blended and the queued route, for the
+    // base and each layer.
+    pub fn to_diagram(&self) -> Diagram {
+        let mut diagram = Diagram::default();
+        let base_group = if self.layers.is_empty() { None } else { Some("base") };
+        self.base.append_to_diagram(&mut diagram, base_group, true, &self.blackboard);
+        for layer in &self.layers {
+            layer
+                .state_machine
+                .append_to_diagram(&mut diagram, Some(layer.name()), true, &self.blackboard);
         }
-        // Check to see if the work is finished and return if so.
-        let mut finished = false;
-        match self.dfs_temp_edges_stack.last() {
-            Some(val) => {
-                let n = self.graph.edge(*val).unwrap().to();
-                if n == self.target {
-                    finished = true;
-                }
+        diagram
+    }
+
+    pub fn to_dot(&self) -> String {
+        self.to_diagram().to_dot()
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        self.to_diagram().to_mermaid()
+    }
+}
+
+// Evaluates each graph on rayon's thread pool, stopping at the first error.
+#[cfg(feature = "multi-threaded")]
+pub fn evaluate_batch(graphs: &mut [AnimGraphInstance], dt: web_time::Duration) -> Result<(), anyhow::Error> {
+    use rayon::prelude::*;
+    graphs.par_iter_mut().try_for_each(|graph| graph.evaluate(dt))
+}
+
+// Fails the build should the multi-threaded runtime pick up something that can't be shared between threads.
+#[cfg(feature = "multi-threaded")]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AnimGraphInstance>();
+};
+
+// Recomputes the model matrices of `joint` and everything below it, after its local transform changed.
+fn update_model_matrices_from(local_to_model_job: &mut SharedLocalToModelJob, joint: usize) -> Result<(), anyhow::Error> {
+    local_to_model_job.set_from(joint as i32);
+    let l2m_results = local_to_model_job.run();
+    local_to_model_job.set_from(SKELETON_NO_PARENT);
+    match l2m_results {
+        Ok(_) => Ok(()),
+        Err(e) => Err(anyhow!{"Error running local-to-model job after IK: {}", e}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::condition_definitions::ConditionDefinition;
+    use crate::test_fixtures;
+
+    // idle plays at normal speed, and a "go" trigger leads to run, playing three times as fast.
+    fn compiled() -> Shared<CompiledAnimGraph> {
+        let definition = AnimGraphDefinitionBuilder::new()
+            .parameter("go", ParameterValue::Trigger(false))
+            .parameter("speed", ParameterValue::Float(1.0))
+            .sampler("idle", "a")
+            .looping(true)
+            .sampler("run", "b")
+            .looping(true)
+            .speed(3.0)
+            .root("idle")
+            .transition("idle", "run")
+            .condition(ConditionDefinition::Trigger {
+                parameter: "go".to_string(),
+            })
+            .build()
+            .unwrap();
+        let compiled = CompiledAnimGraph::new(test_fixtures::skeleton(), &definition, &test_fixtures::animations());
+        Shared::new(compiled.unwrap())
+    }
+
+    fn current_node(graph: &AnimGraphInstance) -> String {
+        graph.to_diagram().nodes.iter().find(|n| n.current).unwrap().name.clone()
+    }
+
+    fn root_x(graph: &mut AnimGraphInstance) -> f32 {
+        graph.get_soa_transforms().read_buf()[0].translation.vec3(0).x
+    }
+
+    #[test]
+    fn instances_of_one_compiled_graph_keep_their_own_state() {
+        let compiled = compiled();
+        let mut first = AnimGraphInstance::from_compiled(&compiled);
+        let mut second = AnimGraphInstance::from_compiled(&compiled);
+        test_fixtures::step(&mut first, 0.25);
+        test_fixtures::step(&mut second, 0.25);
+
+        first.set_trigger("go".to_string()).unwrap();
+        first.set_float("speed".to_string(), 4.0).unwrap();
+        test_fixtures::step(&mut first, 0.25);
+        test_fixtures::step(&mut second, 0.25);
+
+        assert_eq!(current_node(&first), "run");
+        assert_eq!(current_node(&second), "idle");
+        assert_eq!(first.get_parameter("speed".to_string()), Some(ParameterValue::Float(4.0)));
+        assert_eq!(second.get_parameter("speed".to_string()), Some(ParameterValue::Float(1.0)));
+        assert_eq!(second.get_parameter("go".to_string()), Some(ParameterValue::Trigger(false)));
+        // run started over at three times the speed, while idle went on.
+        assert!((root_x(&mut first) - 0.75).abs() < 1e-3, "{}", root_x(&mut first));
+        assert!((root_x(&mut second) - 0.5).abs() < 1e-3, "{}", root_x(&mut second));
+        // The compiled graph's own parameters stay at their defaults.
+        let speed = compiled.blackboard.get_parameter_by_name("speed").unwrap();
+        assert_eq!(compiled.blackboard.get(speed), ParameterValue::Float(1.0));
+    }
+
+    #[test]
+    fn instances_of_one_compiled_graph_have_their_own_outputs() {
+        let compiled = compiled();
+        let mut first = AnimGraphInstance::from_compiled(&compiled);
+        let mut second = AnimGraphInstance::from_compiled(&compiled);
+        test_fixtures::step(&mut first, 0.5);
+        test_fixtures::step(&mut second, 0.25);
+        assert!(!Shared::ptr_eq(&first.get_soa_transforms(), &second.get_soa_transforms()));
+        assert!((root_x(&mut first) - 0.5).abs() < 1e-3, "{}", root_x(&mut first));
+        assert!((root_x(&mut second) - 0.25).abs() < 1e-3, "{}", root_x(&mut second));
+        // A later instance starts from the beginning, whatever the others are doing.
+        let mut third = AnimGraphInstance::from_compiled(&compiled);
+        test_fixtures::step(&mut third, 0.1);
+        assert!((root_x(&mut third) - 0.1).abs() < 1e-3, "{}", root_x(&mut third));
+    }
+
+    #[cfg(feature = "multi-threaded")]
+    #[test]
+    fn evaluate_batch_matches_evaluating_one_by_one() {
+        let compiled = compiled();
+        let instances = || {
+            (0..8)
+                .map(|i| {
+                    let mut graph = AnimGraphInstance::from_compiled(&compiled);
+                    if i % 2 == 0 {
+                        graph.set_trigger("go".to_string()).unwrap();
+                    }
+                    graph
+                })
+                .collect::<Vec<AnimGraphInstance>>()
+        };
+        let mut batched = instances();
+        let mut sequential = instances();
+        for i in 0..5 {
+            let dt = web_time::Duration::from_secs_f32(0.05 * (i + 1) as f32);
+            evaluate_batch(&mut batched, dt).unwrap();
+            for graph in &mut sequential {
+                graph.evaluate(dt).unwrap();
             }
-            None => {}
-        }
-        if finished {
-            for e in &self.dfs_temp_edges_stack {
-                self.path.push_back(*e);
+            for (batched, sequential) in batched.iter_mut().zip(&mut sequential) {
+                assert_eq!(current_node(batched), current_node(sequential));
+                assert_eq!(*batched.get_soa_transforms().read_buf(), *sequential.get_soa_transforms().read_buf());
+                assert_eq!(*batched.get_skeletal_matrices().read_buf(), *sequential.get_skeletal_matrices().read_buf());
             }
-            return;
         }
-        self.dfs_helper();
     }
 }
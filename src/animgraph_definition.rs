@@ -1,8 +1,24 @@
 use mapgraph::{aliases::SlotMapGraph, map::slotmap::NodeIndex};
 
-use crate::{edge_definitions::TransitionDefinition, node_definitions::GenericNodeDefinition};
+use crate::{
+    edge_definitions::{AnyStateTransitionDefinition, TransitionDefinition}, ik_definitions::{AimIkDefinition, TwoBoneIkDefinition}, layer_definitions::LayerDefinition,
+    node_definitions::GenericNodeDefinition, parameter_definitions::ParameterDefinition,
+    root_motion_definitions::RootMotionDefinition,
+};
+
+pub type DefinitionGraph = SlotMapGraph<GenericNodeDefinition, TransitionDefinition>;
 
 pub struct AnimGraphDefinition {
-    pub graph: SlotMapGraph<GenericNodeDefinition, TransitionDefinition>,
-    pub root: Option<NodeIndex>
-}
\ No newline at end of file
+    pub graph: DefinitionGraph,
+    pub root: Option<NodeIndex>,
+    // Transitions of the base graph that may leave from any of its nodes.
+    pub any_state_transitions: Vec<AnyStateTransitionDefinition>,
+    pub parameters: Vec<ParameterDefinition>,
+    pub root_motion: RootMotionDefinition,
+    // Played over the base graph, in order.
+    pub layers: Vec<LayerDefinition>,
+    // Applied to the final pose, in order.
+    pub two_bone_ik: Vec<TwoBoneIkDefinition>,
+    // Applied after the two-bone chains, in order.
+    pub aim_ik: Vec<AimIkDefinition>,
+}
@@ -0,0 +1,610 @@
+use crate::animgraph_definition::AnimGraphDefinition;
+use crate::condition_definitions::ConditionDefinition;
+use crate::definition_file::{AnimGraphFile, LayerFile};
+use crate::curves::TransitionCurve;
+use crate::edge_definitions::{
+    AnyStateTransitionDefinition, NamedTransitionDefinition, TransitionDefinition, TransitionMode,
+};
+use crate::event_definitions::AnimationEventDefinition;
+use crate::ik_definitions::{AimIkDefinition, TwoBoneIkDefinition};
+use crate::layer_definitions::{JointMaskDefinition, LayerBlendMode};
+use crate::node_definitions::*;
+use crate::parameter_definitions::{ParameterDefinition, ParameterValue};
+use crate::root_motion_definitions::RootMotionDefinition;
+use anyhow::anyhow;
+
+// What the last call added, so that the modifiers following it know what to change.
+enum LastAdded {
+    Nothing,
+    Node,
+    Transition,
+    AnyStateTransition,
+}
+
+// Authors an AnimGraphDefinition by name, e.g.
+// AnimGraphDefinitionBuilder::new()
+//     .sampler("idle", "idle_anim").looping(true)
+//     .sampler("walk", "walk_anim").looping(true)
+//     .transition("idle", "walk").duration(Duration::from_millis(200))
+//     .root("idle")
+//     .build()
+// Modifiers such as looping() or duration() apply to the node or transition added just before them. After layer(),
+// nodes, transitions and root() go to that layer rather than the base graph.
+pub struct AnimGraphDefinitionBuilder {
+    root: Option<String>,
+    parameters: Vec<ParameterDefinition>,
+    nodes: Vec<GenericNodeDefinition>,
+    transitions: Vec<NamedTransitionDefinition>,
+    any_state_transitions: Vec<AnyStateTransitionDefinition>,
+    root_motion: RootMotionDefinition,
+    layers: Vec<LayerFile>,
+    two_bone_ik: Vec<TwoBoneIkDefinition>,
+    aim_ik: Vec<AimIkDefinition>,
+    last: LastAdded,
+    errors: Vec<String>,
+}
+
+impl Default for AnimGraphDefinitionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnimGraphDefinitionBuilder {
+    pub fn new() -> Self {
+        AnimGraphDefinitionBuilder {
+            root: None,
+            parameters: Vec::new(),
+            nodes: Vec::new(),
+            transitions: Vec::new(),
+            any_state_transitions: Vec::new(),
+            root_motion: RootMotionDefinition::default(),
+            layers: Vec::new(),
+            two_bone_ik: Vec::new(),
+            aim_ik: Vec::new(),
+            last: LastAdded::Nothing,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn root(mut self, name: &str) -> Self {
+        match self.layers.last_mut() {
+            Some(val) => val.root = name.to_string(),
+            None => self.root = Some(name.to_string()),
+        }
+        self
+    }
+
+    // Starts a layer. Everything added from here on goes to it.
+    pub fn layer(mut self, name: &str) -> Self {
+        self.layers.push(LayerFile {
+            name: name.to_string(),
+            root: String::new(),
+            nodes: Vec::new(),
+            transitions: Vec::new(),
+            weight: 1.0,
+            mask: Vec::new(),
+            blend_mode: LayerBlendMode::Override,
+        });
+        self.last = LastAdded::Nothing;
+        self
+    }
+
+    pub fn layer_weight(mut self, weight: f32) -> Self {
+        match self.layers.last_mut() {
+            Some(val) => val.weight = weight,
+            None => self.misuse("layer_weight() only applies to layers"),
+        }
+        self
+    }
+
+    pub fn blend_mode(mut self, blend_mode: LayerBlendMode) -> Self {
+        match self.layers.last_mut() {
+            Some(val) => val.blend_mode = blend_mode,
+            None => self.misuse("blend_mode() only applies to layers"),
+        }
+        self
+    }
+
+    // Adds `joint_name`, and its descendants if `include_children`, to the current layer's mask.
+    pub fn mask(mut self, joint_name: &str, include_children: bool) -> Self {
+        match self.layers.last_mut() {
+            Some(val) => val.mask.push(JointMaskDefinition {
+                joint_name: joint_name.to_string(),
+                include_children,
+            }),
+            None => self.misuse("mask() only applies to layers"),
+        }
+        self
+    }
+
+    pub fn root_motion(mut self, root_motion: RootMotionDefinition) -> Self {
+        self.root_motion = root_motion;
+        self
+    }
+
+    pub fn two_bone_ik(mut self, two_bone_ik: TwoBoneIkDefinition) -> Self {
+        self.two_bone_ik.push(two_bone_ik);
+        self
+    }
+
+    pub fn aim_ik(mut self, aim_ik: AimIkDefinition) -> Self {
+        self.aim_ik.push(aim_ik);
+        self
+    }
+
+    pub fn parameter(mut self, name: &str, default_value: ParameterValue) -> Self {
+        self.parameters.push(ParameterDefinition {
+            name: name.to_string(),
+            default_value,
+        });
+        self
+    }
+
+    pub fn sampler(self, name: &str, animation_name: &str) -> Self {
+        self.node(GenericNodeDefinition::Sampler(SamplerNodeDefinition {
+            speed: 1.0,
+            animation_name: animation_name.to_string(),
+            looping: false,
+            name: name.to_string(),
+            events: Vec::new(),
+        }))
+    }
+
+    // Adds a clip played as the difference from its first frame, see reference() for another reference pose.
+    pub fn additive(self, name: &str, animation_name: &str) -> Self {
+        self.node(GenericNodeDefinition::Additive(AdditiveNodeDefinition {
+            animation_name: animation_name.to_string(),
+            reference: AdditiveReference::FirstFrame,
+            looping: false,
+            name: name.to_string(),
+            events: Vec::new(),
+        }))
+    }
+
+    // Adds a 1D blend tree from (animation name, threshold) pairs.
+    pub fn blend_1d(self, name: &str, clips: &[(&str, f32)]) -> Self {
+        self.node(GenericNodeDefinition::BlendTreeOneDim(BlendTreeOneDimDefinition {
+            clips: clips
+                .iter()
+                .map(|(animation_name, threshold)| BlendTreeOneDimClipDefinition {
+                    animation_name: animation_name.to_string(),
+                    threshold: *threshold,
+                    events: Vec::new(),
+                })
+                .collect(),
+            name: name.to_string(),
+            sync_phase: true,
+            param_name: None,
+        }))
+    }
+
+    // Adds a 2D blend space from (animation name, position) pairs.
+    pub fn blend_2d(self, name: &str, clips: &[(&str, glam::Vec2)], mode: BlendSpaceTwoDimMode) -> Self {
+        self.node(GenericNodeDefinition::BlendSpaceTwoDim(BlendSpaceTwoDimDefinition {
+            clips: clips
+                .iter()
+                .map(|(animation_name, position)| BlendSpaceTwoDimClipDefinition {
+                    animation_name: animation_name.to_string(),
+                    position: *position,
+                    events: Vec::new(),
+                })
+                .collect(),
+            name: name.to_string(),
+            mode,
+            sync_phase: true,
+            param_name: None,
+        }))
+    }
+
+    pub fn node(mut self, node_definition: GenericNodeDefinition) -> Self {
+        match self.layers.last_mut() {
+            Some(val) => val.nodes.push(node_definition),
+            None => self.nodes.push(node_definition),
+        }
+        self.last = LastAdded::Node;
+        self
+    }
+
+    pub fn transition(mut self, from: &str, to: &str) -> Self {
+        let transitions = match self.layers.last_mut() {
+            Some(val) => &mut val.transitions,
+            None => &mut self.transitions,
+        };
+        transitions.push(NamedTransitionDefinition {
+            from: from.to_string(),
+            to: to.to_string(),
+            transition: default_transition(),
+        });
+        self.last = LastAdded::Transition;
+        self
+    }
+
+    // Adds a transition to `to` from any node of the base graph. The transition modifiers apply to it as well.
+    pub fn any_state_transition(mut self, to: &str) -> Self {
+        if !self.layers.is_empty() {
+            self.misuse("any_state_transition() only applies to the base graph");
+            return self;
+        }
+        self.any_state_transitions.push(AnyStateTransitionDefinition {
+            to: to.to_string(),
+            transition: default_transition(),
+            exclude: Vec::new(),
+            during_transitions: false,
+        });
+        self.last = LastAdded::AnyStateTransition;
+        self
+    }
+
+    // Sampler and additive node modifiers.
+
+    pub fn looping(mut self, looping: bool) -> Self {
+        match self.last_node() {
+            Some(GenericNodeDefinition::Sampler(val)) => val.looping = looping,
+            Some(GenericNodeDefinition::Additive(val)) => val.looping = looping,
+            _ => self.misuse("looping() only applies to samplers and additive nodes"),
+        }
+        self
+    }
+
+    // Takes the additive delta from the first frame of `animation_name` rather than the clip's own.
+    pub fn reference(mut self, animation_name: &str) -> Self {
+        match self.last_node() {
+            Some(GenericNodeDefinition::Additive(val)) => {
+                val.reference = AdditiveReference::Animation(animation_name.to_string())
+            }
+            _ => self.misuse("reference() only applies to additive nodes"),
+        }
+        self
+    }
+
+    pub fn speed(mut self, speed: f32) -> Self {
+        match self.last_node() {
+            Some(GenericNodeDefinition::Sampler(val)) => val.speed = speed,
+            _ => self.misuse("speed() only applies to samplers"),
+        }
+        self
+    }
+
+    pub fn event(mut self, name: &str, time: f32, payload: Option<&str>) -> Self {
+        let event = AnimationEventDefinition {
+            name: name.to_string(),
+            time,
+            payload: payload.map(|p| p.to_string()),
+        };
+        match self.last_node() {
+            Some(GenericNodeDefinition::Sampler(val)) => val.events.push(event),
+            Some(GenericNodeDefinition::Additive(val)) => val.events.push(event),
+            _ => self.misuse("event() only applies to samplers and additive nodes, use clip_event() on blend nodes"),
+        }
+        self
+    }
+
+    // Blend tree and blend space modifiers.
+
+    // Adds an event marker to the clip playing `animation_name`.
+    pub fn clip_event(mut self, animation_name: &str, name: &str, time: f32, payload: Option<&str>) -> Self {
+        let event = AnimationEventDefinition {
+            name: name.to_string(),
+            time,
+            payload: payload.map(|p| p.to_string()),
+        };
+        let events = match self.last_node() {
+            Some(GenericNodeDefinition::BlendTreeOneDim(val)) => val
+                .clips
+                .iter_mut()
+                .find(|c| c.animation_name == animation_name)
+                .map(|c| &mut c.events),
+            Some(GenericNodeDefinition::BlendSpaceTwoDim(val)) => val
+                .clips
+                .iter_mut()
+                .find(|c| c.animation_name == animation_name)
+                .map(|c| &mut c.events),
+            _ => {
+                self.misuse("clip_event() only applies to blend trees and blend spaces");
+                return self;
+            }
+        };
+        match events {
+            Some(val) => val.push(event),
+            None => self.misuse(&format!("clip_event() names unknown clip {}", animation_name)),
+        }
+        self
+    }
+
+    pub fn param(mut self, param_name: &str) -> Self {
+        match self.last_node() {
+            Some(GenericNodeDefinition::BlendTreeOneDim(val)) => val.param_name = Some(param_name.to_string()),
+            Some(GenericNodeDefinition::BlendSpaceTwoDim(val)) => val.param_name = Some(param_name.to_string()),
+            _ => self.misuse("param() only applies to blend trees and blend spaces"),
+        }
+        self
+    }
+
+    pub fn sync_phase(mut self, sync_phase: bool) -> Self {
+        match self.last_node() {
+            Some(GenericNodeDefinition::BlendTreeOneDim(val)) => val.sync_phase = sync_phase,
+            Some(GenericNodeDefinition::BlendSpaceTwoDim(val)) => val.sync_phase = sync_phase,
+            _ => self.misuse("sync_phase() only applies to blend trees and blend spaces"),
+        }
+        self
+    }
+
+    // Transition modifiers.
+
+    pub fn duration(mut self, duration: web_time::Duration) -> Self {
+        match self.last_transition() {
+            Some(val) => val.duration = duration,
+            None => self.misuse("duration() only applies to transitions"),
+        }
+        self
+    }
+
+    pub fn mode(mut self, mode: TransitionMode) -> Self {
+        match self.last_transition() {
+            Some(val) => val.mode = mode,
+            None => self.misuse("mode() only applies to transitions"),
+        }
+        self
+    }
+
+    pub fn curve(mut self, curve: TransitionCurve) -> Self {
+        match self.last_transition() {
+            Some(val) => val.curve = curve,
+            None => self.misuse("curve() only applies to transitions"),
+        }
+        self
+    }
+
+    pub fn condition(mut self, condition: ConditionDefinition) -> Self {
+        match self.last_transition() {
+            Some(val) => val.conditions.push(condition),
+            None => self.misuse("condition() only applies to transitions"),
+        }
+        self
+    }
+
+    pub fn exit_time(mut self, exit_time: f32) -> Self {
+        match self.last_transition() {
+            Some(val) => val.exit_time = Some(exit_time),
+            None => self.misuse("exit_time() only applies to transitions"),
+        }
+        self
+    }
+
+    pub fn cost(mut self, cost: f32) -> Self {
+        match self.last_transition() {
+            Some(val) => val.cost = Some(cost),
+            None => self.misuse("cost() only applies to transitions"),
+        }
+        self
+    }
+
+    pub fn interruptible(mut self, interruptible: bool) -> Self {
+        match self.last_transition() {
+            Some(val) => val.interruptible = interruptible,
+            None => self.misuse("interruptible() only applies to transitions"),
+        }
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        match self.last_transition() {
+            Some(val) => val.priority = priority,
+            None => self.misuse("priority() only applies to transitions"),
+        }
+        self
+    }
+
+    // Any-state transition modifiers.
+
+    // Keeps the any-state transition from leaving `node_name`.
+    pub fn exclude(mut self, node_name: &str) -> Self {
+        match self.last_any_state_transition() {
+            Some(val) => val.exclude.push(node_name.to_string()),
+            None => self.misuse("exclude() only applies to any-state transitions"),
+        }
+        self
+    }
+
+    pub fn during_transitions(mut self, during_transitions: bool) -> Self {
+        match self.last_any_state_transition() {
+            Some(val) => val.during_transitions = during_transitions,
+            None => self.misuse("during_transitions() only applies to any-state transitions"),
+        }
+        self
+    }
+
+    // Resolves names and validates the result, failing on any misused modifier or validation error.
+    pub fn build(self) -> Result<AnimGraphDefinition, anyhow::Error> {
+        if !self.errors.is_empty() {
+            return Err(anyhow!("Invalid animgraph builder use: {}", self.errors.join("; ")));
+        }
+        let root = match self.root {
+            Some(val) => val,
+            None => return Err(anyhow!("No root node set on animgraph builder")),
+        };
+        if let Some(layer) = self.layers.iter().find(|l| l.root.is_empty()) {
+            return Err(anyhow!("No root node set on layer {}", layer.name));
+        }
+        let definition = AnimGraphDefinition::from_file(AnimGraphFile {
+            root,
+            parameters: self.parameters,
+            nodes: self.nodes,
+            transitions: self.transitions,
+            any_state_transitions: self.any_state_transitions,
+            root_motion: self.root_motion,
+            layers: self.layers,
+            two_bone_ik: self.two_bone_ik,
+            aim_ik: self.aim_ik,
+        })?;
+        definition.validate().to_result()?;
+        Ok(definition)
+    }
+
+    fn last_node(&mut self) -> Option<&mut GenericNodeDefinition> {
+        match self.last {
+            LastAdded::Node => match self.layers.last_mut() {
+                Some(val) => val.nodes.last_mut(),
+                None => self.nodes.last_mut(),
+            },
+            _ => None,
+        }
+    }
+
+    fn last_transition(&mut self) -> Option<&mut TransitionDefinition> {
+        match self.last {
+            LastAdded::Transition => match self.layers.last_mut() {
+                Some(val) => val.transitions.last_mut().map(|t| &mut t.transition),
+                None => self.transitions.last_mut().map(|t| &mut t.transition),
+            },
+            LastAdded::AnyStateTransition => self.any_state_transitions.last_mut().map(|t| &mut t.transition),
+            _ => None,
+        }
+    }
+
+    fn last_any_state_transition(&mut self) -> Option<&mut AnyStateTransitionDefinition> {
+        match self.last {
+            LastAdded::AnyStateTransition => self.any_state_transitions.last_mut(),
+            _ => None,
+        }
+    }
+
+    fn misuse(&mut self, message: &str) {
+        self.errors.push(message.to_string());
+    }
+}
+
+fn default_transition() -> TransitionDefinition {
+    TransitionDefinition {
+        duration: web_time::Duration::ZERO,
+        mode: TransitionMode::CrossFade,
+        curve: TransitionCurve::Linear,
+        conditions: Vec::new(),
+        exit_time: None,
+        cost: None,
+        interruptible: false,
+        priority: 0,
+        declared: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(builder: AnimGraphDefinitionBuilder) -> String {
+        builder.build().err().unwrap().to_string()
+    }
+
+    #[test]
+    fn modifiers_without_anything_to_modify_are_reported_by_build() {
+        let builder = AnimGraphDefinitionBuilder::new().looping(true).sampler("idle", "a").root("idle");
+        assert_eq!(
+            error(builder),
+            "Invalid animgraph builder use: looping() only applies to samplers and additive nodes"
+        );
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("idle", "a")
+            .duration(web_time::Duration::from_millis(200))
+            .layer_weight(0.5)
+            .root("idle");
+        assert_eq!(
+            error(builder),
+            "Invalid animgraph builder use: duration() only applies to transitions; \
+             layer_weight() only applies to layers"
+        );
+    }
+
+    #[test]
+    fn modifiers_for_another_kind_of_item_are_reported_by_build() {
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("idle", "a")
+            .sampler("walk", "b")
+            .root("idle")
+            .transition("idle", "walk")
+            .looping(true);
+        assert_eq!(
+            error(builder),
+            "Invalid animgraph builder use: looping() only applies to samplers and additive nodes"
+        );
+        let builder = AnimGraphDefinitionBuilder::new().sampler("idle", "a").root("idle").reference("b");
+        assert_eq!(error(builder), "Invalid animgraph builder use: reference() only applies to additive nodes");
+    }
+
+    #[test]
+    fn transitions_between_unknown_nodes_are_reported_by_build() {
+        let builder = AnimGraphDefinitionBuilder::new().sampler("idle", "a").root("idle").transition("idle", "run");
+        assert_eq!(error(builder), "Transition to unknown node run");
+        let builder = AnimGraphDefinitionBuilder::new().sampler("idle", "a").root("idle").transition("run", "idle");
+        assert_eq!(error(builder), "Transition from unknown node run");
+    }
+
+    #[test]
+    fn missing_roots_are_reported_by_build() {
+        let builder = AnimGraphDefinitionBuilder::new().sampler("idle", "a");
+        assert_eq!(error(builder), "No root node set on animgraph builder");
+        let builder = AnimGraphDefinitionBuilder::new().sampler("idle", "a").root("idle").layer("wave");
+        assert_eq!(error(builder), "No root node set on layer wave");
+    }
+
+    #[test]
+    fn build_surfaces_validation_errors() {
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("idle", "a")
+            .sampler("walk", "b")
+            .root("idle")
+            .transition("idle", "walk")
+            .condition(ConditionDefinition::Trigger {
+                parameter: "go".to_string(),
+            });
+        let message = error(builder);
+        assert!(message.starts_with("Invalid animgraph definition: "), "{}", message);
+        assert!(message.contains("references unknown parameter go"), "{}", message);
+    }
+
+    #[test]
+    fn build_allows_warnings() {
+        // walk can't be reached, which is only a warning.
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("idle", "a")
+            .looping(true)
+            .sampler("walk", "b")
+            .root("idle");
+        let definition = builder.build().unwrap();
+        assert!(definition.validate().warnings().next().is_some());
+    }
+
+    #[test]
+    fn modifiers_apply_to_the_last_added_item() {
+        let definition = AnimGraphDefinitionBuilder::new()
+            .parameter("go", ParameterValue::Trigger(false))
+            .sampler("idle", "a")
+            .looping(true)
+            .sampler("walk", "b")
+            .speed(2.0)
+            .root("idle")
+            .transition("idle", "walk")
+            .duration(web_time::Duration::from_millis(200))
+            .priority(3)
+            .exit_time(0.5)
+            .condition(ConditionDefinition::Trigger {
+                parameter: "go".to_string(),
+            })
+            .build()
+            .unwrap();
+        let file = definition.to_file().unwrap();
+        match &file.nodes[..] {
+            [GenericNodeDefinition::Sampler(idle), GenericNodeDefinition::Sampler(walk)] => {
+                assert!(idle.looping && idle.speed == 1.0);
+                assert!(!walk.looping && walk.speed == 2.0);
+            }
+            _ => panic!("expected two samplers"),
+        }
+        let transition = &file.transitions[0].transition;
+        assert_eq!(transition.duration, web_time::Duration::from_millis(200));
+        assert_eq!(transition.priority, 3);
+        assert_eq!(transition.exit_time, Some(0.5));
+        assert_eq!(transition.conditions.len(), 1);
+    }
+}
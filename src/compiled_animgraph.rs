@@ -0,0 +1,212 @@
+use crate::animgraph_definition::*;
+use crate::ik::*;
+use crate::layers::{build_joint_mask, LayerLayout};
+use crate::parameters::*;
+use crate::root_motion::*;
+use crate::root_motion_definitions::RootMotionMode;
+use crate::shared::*;
+use crate::state_machine::{LayoutResources, StateMachineLayout};
+use anyhow::anyhow;
+use ozz_animation_rs::*;
+use std::collections::HashMap;
+
+// An AnimGraphDefinition checked and resolved once: its animations, parameters, nodes, transitions, routes, layers
+// and IK chains. Any number of AnimGraphInstances can play it, each only holding its own character's state.
+pub struct CompiledAnimGraph {
+    pub skeleton: Shared<Skeleton>,
+    // With each parameter at its default value.
+    pub blackboard: Blackboard,
+    pub base: Shared<StateMachineLayout>,
+    pub layers: Vec<Shared<LayerLayout>>,
+    pub two_bone_ik: Vec<Shared<TwoBoneIkLayout>>,
+    pub aim_ik: Vec<Shared<AimIkLayout>>,
+}
+
+impl CompiledAnimGraph {
+    pub fn new(
+        skeleton: Shared<Skeleton>,
+        animgraph_definition: &AnimGraphDefinition,
+        animations_by_name: &HashMap<String, Shared<Animation>>,
+    ) -> Result<Self, anyhow::Error> {
+        animgraph_definition
+            .validate_with_animations(animations_by_name)
+            .to_result()?;
+        let blackboard = Blackboard::new(&animgraph_definition.parameters)?;
+
+        let root_motion_definition = &animgraph_definition.root_motion;
+        let root_motion = if root_motion_definition.mode != RootMotionMode::Disabled {
+            let joint = match &root_motion_definition.joint_name {
+                Some(val) => match skeleton.joint_by_name(val) {
+                    Some(joint) => joint as usize,
+                    None => return Err(anyhow!("Unknown root motion joint: {}", val)),
+                },
+                None => 0,
+            };
+            Some(RootMotionSettings::new(
+                joint,
+                root_motion_definition.axes,
+                root_motion_definition.mode == RootMotionMode::ExtractAndRemove,
+            ))
+        } else {
+            None
+        };
+
+        let mut resources = LayoutResources {
+            skeleton: &skeleton,
+            animations_by_name,
+            blackboard: &blackboard,
+            root_motion,
+        };
+        let base = StateMachineLayout::new(
+            &resources,
+            &animgraph_definition.graph,
+            animgraph_definition.root,
+            &animgraph_definition.any_state_transitions,
+            &[],
+        )?;
+        // Only the base moves the character.
+        resources.root_motion = None;
+        let mut layers = Vec::<Shared<LayerLayout>>::new();
+        for layer_definition in &animgraph_definition.layers {
+            let state_machine =
+                StateMachineLayout::new(&resources, &layer_definition.graph, layer_definition.root, &[], &[])?;
+            layers.push(Shared::new(LayerLayout {
+                name: layer_definition.name.clone(),
+                weight: layer_definition.weight,
+                blend_mode: layer_definition.blend_mode,
+                mask: build_joint_mask(&skeleton, &layer_definition.mask)?,
+                state_machine: Shared::new(state_machine),
+            }));
+        }
+
+        let mut two_bone_ik = Vec::<Shared<TwoBoneIkLayout>>::new();
+        for two_bone_ik_definition in &animgraph_definition.two_bone_ik {
+            two_bone_ik.push(Shared::new(TwoBoneIkLayout::new(&skeleton, two_bone_ik_definition)?));
+        }
+        let mut aim_ik = Vec::<Shared<AimIkLayout>>::new();
+        for aim_ik_definition in &animgraph_definition.aim_ik {
+            aim_ik.push(Shared::new(AimIkLayout::new(&skeleton, aim_ik_definition)?));
+        }
+
+        Ok(CompiledAnimGraph {
+            skeleton,
+            blackboard,
+            base: Shared::new(base),
+            layers,
+            two_bone_ik,
+            aim_ik,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animgraph::AnimGraphInstance;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::export::DiagramEdgeState;
+    use crate::ik_definitions::TwoBoneIkDefinition;
+    use crate::test_fixtures;
+
+    // a to c directly costs more than going through b. Each transition takes a second.
+    fn detour() -> AnimGraphDefinition {
+        let second = web_time::Duration::from_secs(1);
+        ["a", "b", "c"]
+            .iter()
+            .fold(AnimGraphDefinitionBuilder::new(), |builder, name| builder.sampler(name, name).looping(true))
+            .root("a")
+            .transition("a", "c").duration(second).cost(5.0)
+            .transition("a", "b").duration(second)
+            .transition("b", "c").duration(second)
+            .build()
+            .unwrap()
+    }
+
+    fn edges_in(graph: &AnimGraphInstance, state: DiagramEdgeState) -> Vec<(String, String)> {
+        let diagram = graph.to_diagram();
+        diagram
+            .edges
+            .iter()
+            .filter(|e| e.state == state)
+            .map(|e| (diagram.nodes[e.from].name.clone(), diagram.nodes[e.to].name.clone()))
+            .collect()
+    }
+
+    fn edge(from: &str, to: &str) -> (String, String) {
+        (from.to_string(), to.to_string())
+    }
+
+    #[test]
+    fn instances_follow_the_precomputed_routes() {
+        let compiled = CompiledAnimGraph::new(test_fixtures::skeleton(), &detour(), &test_fixtures::animations());
+        let compiled = Shared::new(compiled.unwrap());
+        let (a, c) = (compiled.base.node_by_name("a").unwrap(), compiled.base.node_by_name("c").unwrap());
+        assert_eq!(compiled.base.route(a, c).unwrap().len(), 2);
+        assert_eq!(compiled.base.route(c, a), None);
+
+        for _ in 0..2 {
+            let mut graph = AnimGraphInstance::from_compiled(&compiled);
+            graph.set_target_node_by_name("c".to_string()).unwrap();
+            test_fixtures::step(&mut graph, 0.5);
+            assert_eq!(edges_in(&graph, DiagramEdgeState::Active), vec![edge("a", "b")]);
+            assert_eq!(edges_in(&graph, DiagramEdgeState::Queued), vec![edge("b", "c")]);
+        }
+    }
+
+    #[test]
+    fn animations_are_resolved_when_compiling() {
+        let definition = AnimGraphDefinitionBuilder::new().sampler("walk", "run").root("walk").build().unwrap();
+        let result = CompiledAnimGraph::new(test_fixtures::skeleton(), &definition, &test_fixtures::animations());
+        let err = result.err().unwrap();
+        assert!(err.to_string().contains("Could not find animation name run used by walk"), "{}", err);
+    }
+
+    fn arm_ik(mid_joint: &str) -> TwoBoneIkDefinition {
+        TwoBoneIkDefinition {
+            name: "arm".to_string(),
+            start_joint: "shoulder".to_string(),
+            mid_joint: mid_joint.to_string(),
+            end_joint: "hand".to_string(),
+            mid_axis: glam::Vec3::Z,
+            soften: 1.0,
+            twist_angle: 0.0,
+            weight: 1.0,
+        }
+    }
+
+    fn compile_arm(ik: TwoBoneIkDefinition) -> Result<CompiledAnimGraph, anyhow::Error> {
+        let definition = AnimGraphDefinitionBuilder::new()
+            .sampler("still", "arm")
+            .looping(true)
+            .root("still")
+            .two_bone_ik(ik)
+            .build()
+            .unwrap();
+        let animations = HashMap::from([("arm".to_string(), test_fixtures::arm_clip())]);
+        CompiledAnimGraph::new(test_fixtures::arm(), &definition, &animations)
+    }
+
+    fn hand(graph: &mut AnimGraphInstance) -> glam::Vec3 {
+        graph.get_skeletal_matrices().read_buf()[3].w_axis.truncate()
+    }
+
+    #[test]
+    fn ik_chains_are_resolved_when_compiling_and_played_per_instance() {
+        let err = compile_arm(arm_ik("knee")).err().unwrap();
+        assert_eq!(err.to_string(), "Unknown joint knee in IK chain arm");
+
+        let compiled = Shared::new(compile_arm(arm_ik("elbow")).unwrap());
+        let mut reaching = AnimGraphInstance::from_compiled(&compiled);
+        let mut resting = AnimGraphInstance::from_compiled(&compiled);
+        let target = glam::Vec3::new(1.0, 1.0, 0.0);
+        reaching.set_two_bone_ik_target("arm".to_string(), target, glam::Vec3::Y).unwrap();
+        assert!(reaching.set_two_bone_ik_target("leg".to_string(), target, glam::Vec3::Y).is_err());
+        test_fixtures::step(&mut reaching, 0.1);
+        test_fixtures::step(&mut resting, 0.1);
+
+        assert_eq!(reaching.two_bone_ik_reached("arm".to_string()), Some(true));
+        assert!(hand(&mut reaching).abs_diff_eq(target, 1e-3), "{}", hand(&mut reaching));
+        assert_eq!(resting.two_bone_ik_reached("arm".to_string()), Some(false));
+        assert!(hand(&mut resting).abs_diff_eq(glam::Vec3::new(2.0, 0.0, 0.0), 1e-3), "{}", hand(&mut resting));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::parameter_definitions::ParameterValue;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ComparisonOp {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl fmt::Display for ComparisonOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            ComparisonOp::Less => "<",
+            ComparisonOp::LessOrEqual => "<=",
+            ComparisonOp::Greater => ">",
+            ComparisonOp::GreaterOrEqual => ">=",
+            ComparisonOp::Equal => "==",
+            ComparisonOp::NotEqual => "!=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl ComparisonOp {
+    pub fn compare<T: PartialOrd>(&self, lhs: T, rhs: T) -> bool {
+        match self {
+            ComparisonOp::Less => lhs < rhs,
+            ComparisonOp::LessOrEqual => lhs <= rhs,
+            ComparisonOp::Greater => lhs > rhs,
+            ComparisonOp::GreaterOrEqual => lhs >= rhs,
+            ComparisonOp::Equal => lhs == rhs,
+            ComparisonOp::NotEqual => lhs != rhs,
+        }
+    }
+}
+
+// A test over the graph's parameters, referenced by name.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ConditionDefinition {
+    Float {
+        parameter: String,
+        op: ComparisonOp,
+        value: f32,
+    },
+    Int {
+        parameter: String,
+        op: ComparisonOp,
+        value: i32,
+    },
+    Bool {
+        parameter: String,
+        value: bool,
+    },
+    // Holds while the trigger is set. Firing the transition consumes it.
+    Trigger {
+        parameter: String,
+    },
+    All(Vec<ConditionDefinition>),
+    Any(Vec<ConditionDefinition>),
+}
+
+impl ConditionDefinition {
+    // Every parameter this condition reads, along with the type it expects it to be.
+    pub fn parameters(&self) -> Vec<(&str, ParameterValue)> {
+        match self {
+            ConditionDefinition::Float { parameter, .. } => vec![(parameter.as_str(), ParameterValue::Float(0.0))],
+            ConditionDefinition::Int { parameter, .. } => vec![(parameter.as_str(), ParameterValue::Int(0))],
+            ConditionDefinition::Bool { parameter, .. } => vec![(parameter.as_str(), ParameterValue::Bool(false))],
+            ConditionDefinition::Trigger { parameter } => vec![(parameter.as_str(), ParameterValue::Trigger(false))],
+            ConditionDefinition::All(conditions) | ConditionDefinition::Any(conditions) => {
+                conditions.iter().flat_map(|c| c.parameters()).collect()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConditionDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConditionDefinition::Float {
+                parameter,
+                op,
+                value,
+            } => write!(f, "{} {} {}", parameter, op, value),
+            ConditionDefinition::Int {
+                parameter,
+                op,
+                value,
+            } => write!(f, "{} {} {}", parameter, op, value),
+            ConditionDefinition::Bool { parameter, value } => write!(f, "{} == {}", parameter, value),
+            ConditionDefinition::Trigger { parameter } => write!(f, "{}!", parameter),
+            ConditionDefinition::All(conditions) => write_group(f, conditions, " && "),
+            ConditionDefinition::Any(conditions) => write_group(f, conditions, " || "),
+        }
+    }
+}
+
+fn write_group(f: &mut fmt::Formatter<'_>, conditions: &[ConditionDefinition], separator: &str) -> fmt::Result {
+    let parts = conditions.iter().map(|c| c.to_string()).collect::<Vec<String>>();
+    write!(f, "({})", parts.join(separator))
+}
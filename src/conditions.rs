@@ -0,0 +1,338 @@
+use crate::condition_definitions::*;
+use crate::parameter_definitions::ParameterValue;
+use crate::parameters::*;
+use anyhow::anyhow;
+
+// A condition definition with its parameter names resolved against the blackboard.
+pub enum Condition {
+    Float {
+        parameter: ParameterIndex,
+        op: ComparisonOp,
+        value: f32,
+    },
+    Int {
+        parameter: ParameterIndex,
+        op: ComparisonOp,
+        value: i32,
+    },
+    Bool {
+        parameter: ParameterIndex,
+        value: bool,
+    },
+    Trigger {
+        parameter: ParameterIndex,
+    },
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn new(
+        condition_definition: &ConditionDefinition,
+        blackboard: &Blackboard,
+    ) -> Result<Self, anyhow::Error> {
+        let condition = match condition_definition {
+            ConditionDefinition::Float {
+                parameter,
+                op,
+                value,
+            } => Condition::Float {
+                parameter: resolve(blackboard, parameter, ParameterValue::Float(0.0))?,
+                op: *op,
+                value: *value,
+            },
+            ConditionDefinition::Int {
+                parameter,
+                op,
+                value,
+            } => Condition::Int {
+                parameter: resolve(blackboard, parameter, ParameterValue::Int(0))?,
+                op: *op,
+                value: *value,
+            },
+            ConditionDefinition::Bool { parameter, value } => Condition::Bool {
+                parameter: resolve(blackboard, parameter, ParameterValue::Bool(false))?,
+                value: *value,
+            },
+            ConditionDefinition::Trigger { parameter } => Condition::Trigger {
+                parameter: resolve(blackboard, parameter, ParameterValue::Trigger(false))?,
+            },
+            ConditionDefinition::All(conditions) => Condition::All(
+                conditions
+                    .iter()
+                    .map(|c| Condition::new(c, blackboard))
+                    .collect::<Result<Vec<Condition>, anyhow::Error>>()?,
+            ),
+            ConditionDefinition::Any(conditions) => Condition::Any(
+                conditions
+                    .iter()
+                    .map(|c| Condition::new(c, blackboard))
+                    .collect::<Result<Vec<Condition>, anyhow::Error>>()?,
+            ),
+        };
+        Ok(condition)
+    }
+
+    pub fn evaluate(&self, blackboard: &Blackboard) -> bool {
+        match self {
+            Condition::Float {
+                parameter,
+                op,
+                value,
+            } => match blackboard.get(*parameter) {
+                ParameterValue::Float(current) => op.compare(current, *value),
+                _ => false,
+            },
+            Condition::Int {
+                parameter,
+                op,
+                value,
+            } => match blackboard.get(*parameter) {
+                ParameterValue::Int(current) => op.compare(current, *value),
+                _ => false,
+            },
+            Condition::Bool { parameter, value } => {
+                blackboard.get(*parameter) == ParameterValue::Bool(*value)
+            }
+            Condition::Trigger { parameter } => {
+                blackboard.get(*parameter) == ParameterValue::Trigger(true)
+            }
+            Condition::All(conditions) => conditions.iter().all(|c| c.evaluate(blackboard)),
+            // An empty Any group never holds, mirroring an empty disjunction.
+            Condition::Any(conditions) => conditions.iter().any(|c| c.evaluate(blackboard)),
+        }
+    }
+
+    // Renders the condition the way ConditionDefinition displays, with parameter names looked up on the blackboard.
+    pub fn describe(&self, blackboard: &Blackboard) -> String {
+        match self {
+            Condition::Float {
+                parameter,
+                op,
+                value,
+            } => format!("{} {} {}", blackboard.parameter_name(*parameter), op, value),
+            Condition::Int {
+                parameter,
+                op,
+                value,
+            } => format!("{} {} {}", blackboard.parameter_name(*parameter), op, value),
+            Condition::Bool { parameter, value } => {
+                format!("{} == {}", blackboard.parameter_name(*parameter), value)
+            }
+            Condition::Trigger { parameter } => format!("{}!", blackboard.parameter_name(*parameter)),
+            Condition::All(conditions) => describe_group(conditions, blackboard, " && "),
+            Condition::Any(conditions) => describe_group(conditions, blackboard, " || "),
+        }
+    }
+
+    pub fn consume_triggers(&self, blackboard: &mut Blackboard) {
+        match self {
+            Condition::Trigger { parameter } => blackboard.consume_trigger(*parameter),
+            Condition::All(conditions) => {
+                for c in conditions {
+                    c.consume_triggers(blackboard);
+                }
+            }
+            // Only the alternatives that held fired the transition, the others keep their triggers for later.
+            Condition::Any(conditions) => {
+                let held = conditions
+                    .iter()
+                    .filter(|c| c.evaluate(blackboard))
+                    .collect::<Vec<&Condition>>();
+                for c in held {
+                    c.consume_triggers(blackboard);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn describe_group(conditions: &[Condition], blackboard: &Blackboard, separator: &str) -> String {
+    let parts = conditions
+        .iter()
+        .map(|c| c.describe(blackboard))
+        .collect::<Vec<String>>();
+    format!("({})", parts.join(separator))
+}
+
+fn resolve(
+    blackboard: &Blackboard,
+    parameter_name: &str,
+    expected: ParameterValue,
+) -> Result<ParameterIndex, anyhow::Error> {
+    match blackboard.get_parameter_by_name(parameter_name) {
+        Some(idx) => {
+            if !blackboard.get(idx).same_type(&expected) {
+                return Err(anyhow!(
+                    "Condition expects {} to be a {} parameter, found {}",
+                    parameter_name,
+                    expected.type_name(),
+                    blackboard.get(idx).type_name()
+                ));
+            }
+            Ok(idx)
+        }
+        None => Err(anyhow!(
+            "Condition references unknown parameter {}",
+            parameter_name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter_definitions::ParameterDefinition;
+
+    fn blackboard() -> Blackboard {
+        let parameters = [
+            ("speed", ParameterValue::Float(2.0)),
+            ("ammo", ParameterValue::Int(3)),
+            ("grounded", ParameterValue::Bool(true)),
+            ("jump", ParameterValue::Trigger(false)),
+            ("attack", ParameterValue::Trigger(false)),
+        ];
+        let definitions = parameters
+            .iter()
+            .map(|(name, default_value)| ParameterDefinition {
+                name: name.to_string(),
+                default_value: *default_value,
+            })
+            .collect::<Vec<ParameterDefinition>>();
+        Blackboard::new(&definitions).unwrap()
+    }
+
+    fn float(op: ComparisonOp, value: f32) -> ConditionDefinition {
+        ConditionDefinition::Float {
+            parameter: "speed".to_string(),
+            op,
+            value,
+        }
+    }
+
+    fn int(op: ComparisonOp, value: i32) -> ConditionDefinition {
+        ConditionDefinition::Int {
+            parameter: "ammo".to_string(),
+            op,
+            value,
+        }
+    }
+
+    fn grounded(value: bool) -> ConditionDefinition {
+        ConditionDefinition::Bool {
+            parameter: "grounded".to_string(),
+            value,
+        }
+    }
+
+    fn trigger(parameter: &str) -> ConditionDefinition {
+        ConditionDefinition::Trigger {
+            parameter: parameter.to_string(),
+        }
+    }
+
+    fn holds(definition: &ConditionDefinition, blackboard: &Blackboard) -> bool {
+        Condition::new(definition, blackboard).unwrap().evaluate(blackboard)
+    }
+
+    fn set_trigger(blackboard: &mut Blackboard, name: &str) {
+        let idx = blackboard.get_parameter_by_name(name).unwrap();
+        blackboard.set(idx, ParameterValue::Trigger(true)).unwrap();
+    }
+
+    fn is_set(blackboard: &Blackboard, name: &str) -> bool {
+        blackboard.get(blackboard.get_parameter_by_name(name).unwrap()) == ParameterValue::Trigger(true)
+    }
+
+    #[test]
+    fn comparisons_hold_against_the_current_value() {
+        let blackboard = blackboard();
+        assert!(holds(&float(ComparisonOp::Greater, 1.0), &blackboard));
+        assert!(!holds(&float(ComparisonOp::Less, 2.0), &blackboard));
+        assert!(holds(&float(ComparisonOp::LessOrEqual, 2.0), &blackboard));
+        assert!(holds(&int(ComparisonOp::Equal, 3), &blackboard));
+        assert!(!holds(&int(ComparisonOp::NotEqual, 3), &blackboard));
+        assert!(holds(&int(ComparisonOp::GreaterOrEqual, 3), &blackboard));
+        assert!(holds(&grounded(true), &blackboard));
+        assert!(!holds(&grounded(false), &blackboard));
+    }
+
+    #[test]
+    fn triggers_hold_while_set() {
+        let mut blackboard = blackboard();
+        assert!(!holds(&trigger("jump"), &blackboard));
+        set_trigger(&mut blackboard, "jump");
+        assert!(holds(&trigger("jump"), &blackboard));
+    }
+
+    #[test]
+    fn groups_combine_their_conditions() {
+        let blackboard = blackboard();
+        let all = ConditionDefinition::All(vec![grounded(true), float(ComparisonOp::Greater, 1.0)]);
+        assert!(holds(&all, &blackboard));
+        let all = ConditionDefinition::All(vec![grounded(true), float(ComparisonOp::Greater, 5.0)]);
+        assert!(!holds(&all, &blackboard));
+        let any = ConditionDefinition::Any(vec![grounded(false), float(ComparisonOp::Greater, 1.0)]);
+        assert!(holds(&any, &blackboard));
+        assert!(holds(&ConditionDefinition::All(Vec::new()), &blackboard));
+        assert!(!holds(&ConditionDefinition::Any(Vec::new()), &blackboard));
+    }
+
+    #[test]
+    fn conditions_check_parameter_types() {
+        let blackboard = blackboard();
+        let wrong_type = ConditionDefinition::Float {
+            parameter: "ammo".to_string(),
+            op: ComparisonOp::Less,
+            value: 1.0,
+        };
+        let err = Condition::new(&wrong_type, &blackboard).err().unwrap();
+        assert_eq!(err.to_string(), "Condition expects ammo to be a float parameter, found int");
+        let err = Condition::new(&trigger("dash"), &blackboard).err().unwrap();
+        assert_eq!(err.to_string(), "Condition references unknown parameter dash");
+        let nested = ConditionDefinition::Any(vec![grounded(true), trigger("grounded")]);
+        assert!(Condition::new(&nested, &blackboard).is_err());
+    }
+
+    #[test]
+    fn all_groups_consume_every_trigger() {
+        let mut blackboard = blackboard();
+        set_trigger(&mut blackboard, "jump");
+        set_trigger(&mut blackboard, "attack");
+        let definition = ConditionDefinition::All(vec![trigger("jump"), trigger("attack")]);
+        let condition = Condition::new(&definition, &blackboard).unwrap();
+        condition.consume_triggers(&mut blackboard);
+        assert!(!is_set(&blackboard, "jump"));
+        assert!(!is_set(&blackboard, "attack"));
+    }
+
+    #[test]
+    fn any_groups_only_consume_the_triggers_that_held() {
+        let mut blackboard = blackboard();
+        set_trigger(&mut blackboard, "attack");
+        let definition = ConditionDefinition::Any(vec![
+            ConditionDefinition::All(vec![trigger("jump"), grounded(false)]),
+            trigger("attack"),
+        ]);
+        set_trigger(&mut blackboard, "jump");
+        let condition = Condition::new(&definition, &blackboard).unwrap();
+        assert!(condition.evaluate(&blackboard));
+        condition.consume_triggers(&mut blackboard);
+        // jump's alternative didn't hold as the character is grounded, so it is left for another transition.
+        assert!(is_set(&blackboard, "jump"));
+        assert!(!is_set(&blackboard, "attack"));
+    }
+
+    #[test]
+    fn conditions_describe_themselves_with_parameter_names() {
+        let blackboard = blackboard();
+        let definition = ConditionDefinition::Any(vec![
+            ConditionDefinition::All(vec![trigger("jump"), grounded(true)]),
+            float(ComparisonOp::GreaterOrEqual, 1.5),
+        ]);
+        let condition = Condition::new(&definition, &blackboard).unwrap();
+        assert_eq!(condition.describe(&blackboard), "((jump! && grounded == true) || speed >= 1.5)");
+        assert_eq!(condition.describe(&blackboard), definition.to_string());
+    }
+}
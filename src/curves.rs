@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+
+// Maps a transition's normalized elapsed time to the weight of its destination, both from 0 to 1.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum TransitionCurve {
+    #[default]
+    Linear,
+    SmoothStep,
+    // Cubic ease-in, ease-out and their combination.
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    // CSS-style cubic bezier from (0, 0) to (1, 1) through the control points (x1, y1) and (x2, y2). The x
+    // coordinates must lie within [0, 1] for the curve to be a function of time.
+    CubicBezier { x1: f32, y1: f32, x2: f32, y2: f32 },
+    // Weights evenly spaced over the transition, linearly interpolated in between.
+    Sampled(Vec<f32>),
+}
+
+// How many Newton iterations to try before falling back on bisection when solving a bezier for time.
+const BEZIER_NEWTON_ITERATIONS: usize = 8;
+const BEZIER_EPSILON: f32 = 1e-5;
+
+impl TransitionCurve {
+    pub fn evaluate(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            TransitionCurve::Linear => t,
+            TransitionCurve::SmoothStep => t * t * (3.0 - 2.0 * t),
+            TransitionCurve::EaseIn => t * t * t,
+            TransitionCurve::EaseOut => 1.0 - (1.0 - t).powi(3),
+            TransitionCurve::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) * 0.5
+                }
+            }
+            TransitionCurve::CubicBezier { x1, y1, x2, y2 } => {
+                let s = solve_bezier(*x1, *x2, t);
+                bezier(*y1, *y2, s)
+            }
+            TransitionCurve::Sampled(samples) => match samples.len() {
+                0 => t,
+                1 => samples[0],
+                len => {
+                    let position = t * (len - 1) as f32;
+                    let i = (position.floor() as usize).min(len - 2);
+                    let fraction = position - i as f32;
+                    samples[i] + (samples[i + 1] - samples[i]) * fraction
+                }
+            },
+        }
+    }
+
+    // What's wrong with the curve, if anything.
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            TransitionCurve::CubicBezier { x1, x2, .. } => {
+                if !(0.0..=1.0).contains(x1) || !(0.0..=1.0).contains(x2) {
+                    return Some(format!("bezier control points x1 = {} and x2 = {} must lie within [0, 1]", x1, x2));
+                }
+                None
+            }
+            TransitionCurve::Sampled(samples) => {
+                if samples.is_empty() {
+                    return Some("sampled curve has no samples".to_string());
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+// One coordinate of a cubic bezier whose end points are 0 and 1.
+fn bezier(p1: f32, p2: f32, s: f32) -> f32 {
+    let u = 1.0 - s;
+    3.0 * u * u * s * p1 + 3.0 * u * s * s * p2 + s * s * s
+}
+
+fn bezier_derivative(p1: f32, p2: f32, s: f32) -> f32 {
+    let u = 1.0 - s;
+    3.0 * u * u * p1 + 6.0 * u * s * (p2 - p1) + 3.0 * s * s * (1.0 - p2)
+}
+
+// Finds the curve parameter at which the bezier's x coordinate is `x`.
+fn solve_bezier(x1: f32, x2: f32, x: f32) -> f32 {
+    let mut s = x;
+    for _ in 0..BEZIER_NEWTON_ITERATIONS {
+        let error = bezier(x1, x2, s) - x;
+        if error.abs() < BEZIER_EPSILON {
+            return s;
+        }
+        let slope = bezier_derivative(x1, x2, s);
+        if slope.abs() < BEZIER_EPSILON {
+            break;
+        }
+        s -= error / slope;
+    }
+    // x is monotonic in s when the control points lie within [0, 1], so bisection always gets there.
+    let (mut low, mut high) = (0.0f32, 1.0f32);
+    s = x;
+    while high - low > BEZIER_EPSILON {
+        if bezier(x1, x2, s) < x {
+            low = s;
+        } else {
+            high = s;
+        }
+        s = (low + high) * 0.5;
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curves() -> Vec<TransitionCurve> {
+        vec![
+            TransitionCurve::Linear,
+            TransitionCurve::SmoothStep,
+            TransitionCurve::EaseIn,
+            TransitionCurve::EaseOut,
+            TransitionCurve::EaseInOut,
+            // CSS ease, ease-in-out, and one whose x slope is flat at both ends, which Newton can't start from.
+            TransitionCurve::CubicBezier { x1: 0.25, y1: 0.1, x2: 0.25, y2: 1.0 },
+            TransitionCurve::CubicBezier { x1: 0.42, y1: 0.0, x2: 0.58, y2: 1.0 },
+            TransitionCurve::CubicBezier { x1: 0.0, y1: 0.0, x2: 1.0, y2: 1.0 },
+            TransitionCurve::Sampled(vec![0.0, 0.2, 0.9, 1.0]),
+        ]
+    }
+
+    #[test]
+    fn curves_go_from_zero_to_one() {
+        for curve in curves() {
+            assert!(curve.evaluate(0.0).abs() < 1e-4, "{:?} starts at {}", curve, curve.evaluate(0.0));
+            assert!((curve.evaluate(1.0) - 1.0).abs() < 1e-4, "{:?} ends at {}", curve, curve.evaluate(1.0));
+            // Out of range times are clamped.
+            assert_eq!(curve.evaluate(-1.0), curve.evaluate(0.0));
+            assert_eq!(curve.evaluate(2.0), curve.evaluate(1.0));
+        }
+    }
+
+    #[test]
+    fn curves_are_monotone() {
+        for curve in curves() {
+            let mut previous = curve.evaluate(0.0);
+            for step in 1..=200 {
+                let weight = curve.evaluate(step as f32 / 200.0);
+                assert!(weight >= previous - 1e-4, "{:?} goes back down at step {}", curve, step);
+                previous = weight;
+            }
+        }
+    }
+
+    #[test]
+    fn solved_bezier_lands_on_x() {
+        for (x1, x2) in [(0.25, 0.25), (0.42, 0.58), (0.0, 1.0), (1.0, 0.0), (0.0, 0.0), (1.0, 1.0)] {
+            for step in 0..=100 {
+                let x = step as f32 / 100.0;
+                let s = solve_bezier(x1, x2, x);
+                assert!((0.0..=1.0).contains(&s), "parameter {} out of range for ({}, {})", s, x1, x2);
+                assert!((bezier(x1, x2, s) - x).abs() < 1e-4, "missed x = {} for ({}, {})", x, x1, x2);
+            }
+        }
+    }
+
+    #[test]
+    fn bezier_with_thirds_is_linear() {
+        let curve = TransitionCurve::CubicBezier { x1: 1.0 / 3.0, y1: 1.0 / 3.0, x2: 2.0 / 3.0, y2: 2.0 / 3.0 };
+        for step in 0..=10 {
+            let t = step as f32 / 10.0;
+            assert!((curve.evaluate(t) - t).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn css_ease_matches_browsers() {
+        let ease = TransitionCurve::CubicBezier { x1: 0.25, y1: 0.1, x2: 0.25, y2: 1.0 };
+        assert!((ease.evaluate(0.5) - 0.8024).abs() < 1e-3, "{}", ease.evaluate(0.5));
+    }
+}
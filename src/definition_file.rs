@@ -0,0 +1,320 @@
+use crate::animgraph_definition::{AnimGraphDefinition, DefinitionGraph};
+use crate::edge_definitions::{AnyStateTransitionDefinition, TransitionDefinition};
+pub use crate::edge_definitions::NamedTransitionDefinition;
+use crate::ik_definitions::{AimIkDefinition, TwoBoneIkDefinition};
+use crate::layer_definitions::{JointMaskDefinition, LayerBlendMode, LayerDefinition};
+use crate::node_definitions::{GenericNodeDefinition, StateMachineDefinition};
+use crate::parameter_definitions::ParameterDefinition;
+use crate::root_motion_definitions::RootMotionDefinition;
+use anyhow::anyhow;
+use mapgraph::aliases::SlotMapGraph;
+use mapgraph::map::slotmap::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// The on-disk form of an AnimGraphDefinition. Nodes are referred to by name rather than by NodeIndex.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AnimGraphFile {
+    pub root: String,
+    #[serde(default)]
+    pub parameters: Vec<ParameterDefinition>,
+    pub nodes: Vec<GenericNodeDefinition>,
+    #[serde(default)]
+    pub transitions: Vec<NamedTransitionDefinition>,
+    #[serde(default)]
+    pub any_state_transitions: Vec<AnyStateTransitionDefinition>,
+    #[serde(default)]
+    pub root_motion: RootMotionDefinition,
+    #[serde(default)]
+    pub layers: Vec<LayerFile>,
+    #[serde(default)]
+    pub two_bone_ik: Vec<TwoBoneIkDefinition>,
+    #[serde(default)]
+    pub aim_ik: Vec<AimIkDefinition>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LayerFile {
+    pub name: String,
+    pub root: String,
+    pub nodes: Vec<GenericNodeDefinition>,
+    #[serde(default)]
+    pub transitions: Vec<NamedTransitionDefinition>,
+    #[serde(default = "default_layer_weight")]
+    pub weight: f32,
+    #[serde(default)]
+    pub mask: Vec<JointMaskDefinition>,
+    #[serde(default)]
+    pub blend_mode: LayerBlendMode,
+}
+
+impl AnimGraphDefinition {
+    pub fn from_file(file: AnimGraphFile) -> Result<Self, anyhow::Error> {
+        let (graph, root) = graph_from_file(file.nodes, file.transitions, &file.root)?;
+        let mut layers = Vec::<LayerDefinition>::new();
+        for layer in file.layers {
+            let (layer_graph, layer_root) = graph_from_file(layer.nodes, layer.transitions, &layer.root)
+                .map_err(|e| anyhow!("Layer {}: {}", layer.name, e))?;
+            layers.push(LayerDefinition {
+                name: layer.name,
+                graph: layer_graph,
+                root: Some(layer_root),
+                weight: layer.weight,
+                mask: layer.mask,
+                blend_mode: layer.blend_mode,
+            });
+        }
+        Ok(AnimGraphDefinition {
+            graph,
+            root: Some(root),
+            any_state_transitions: file.any_state_transitions,
+            parameters: file.parameters,
+            root_motion: file.root_motion,
+            layers,
+            two_bone_ik: file.two_bone_ik,
+            aim_ik: file.aim_ik,
+        })
+    }
+
+    pub fn to_file(&self) -> Result<AnimGraphFile, anyhow::Error> {
+        let (root, nodes, transitions) = graph_to_file(&self.graph, self.root)?;
+        let mut layers = Vec::<LayerFile>::new();
+        for layer in &self.layers {
+            let (layer_root, layer_nodes, layer_transitions) =
+                graph_to_file(&layer.graph, layer.root).map_err(|e| anyhow!("Layer {}: {}", layer.name, e))?;
+            layers.push(LayerFile {
+                name: layer.name.clone(),
+                root: layer_root,
+                nodes: layer_nodes,
+                transitions: layer_transitions,
+                weight: layer.weight,
+                mask: layer.mask.clone(),
+                blend_mode: layer.blend_mode,
+            });
+        }
+        Ok(AnimGraphFile {
+            root,
+            parameters: self.parameters.clone(),
+            nodes,
+            transitions,
+            any_state_transitions: self.any_state_transitions.clone(),
+            root_motion: self.root_motion.clone(),
+            layers,
+            two_bone_ik: self.two_bone_ik.clone(),
+            aim_ik: self.aim_ik.clone(),
+        })
+    }
+
+    pub fn from_ron_str(ron_str: &str) -> Result<Self, anyhow::Error> {
+        let file: AnimGraphFile = ron::from_str(ron_str)?;
+        Self::from_file(file)
+    }
+
+    pub fn from_json_str(json_str: &str) -> Result<Self, anyhow::Error> {
+        let file: AnimGraphFile = serde_json::from_str(json_str)?;
+        Self::from_file(file)
+    }
+
+    pub fn to_ron_string(&self) -> Result<String, anyhow::Error> {
+        let file = self.to_file()?;
+        Ok(ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default())?)
+    }
+
+    pub fn to_json_string(&self) -> Result<String, anyhow::Error> {
+        let file = self.to_file()?;
+        Ok(serde_json::to_string_pretty(&file)?)
+    }
+}
+
+impl StateMachineDefinition {
+    // Resolves node names, giving back the nested graph and its entry state.
+    pub fn to_graph(&self) -> Result<(DefinitionGraph, NodeIndex), anyhow::Error> {
+        graph_from_file(self.nodes.clone(), self.transitions.clone(), &self.entry)
+    }
+}
+
+// Resolves node names, giving back the graph and its root.
+fn graph_from_file(
+    nodes: Vec<GenericNodeDefinition>,
+    transitions: Vec<NamedTransitionDefinition>,
+    root: &str,
+) -> Result<(DefinitionGraph, NodeIndex), anyhow::Error> {
+    let mut graph = SlotMapGraph::<GenericNodeDefinition, TransitionDefinition>::with_capacities(
+        nodes.len(),
+        transitions.len(),
+    );
+    let mut node_names = HashMap::<String, NodeIndex>::new();
+    for node_definition in nodes {
+        let name = node_definition.name().to_string();
+        if node_names.contains_key(&name) {
+            return Err(anyhow!("Duplicate node name: {}", name));
+        }
+        let node_idx = graph.add_node(node_definition);
+        node_names.insert(name, node_idx);
+    }
+    for (declared, mut named_transition) in transitions.into_iter().enumerate() {
+        named_transition.transition.declared = declared;
+        let from = match node_names.get(&named_transition.from) {
+            Some(val) => *val,
+            None => {
+                return Err(anyhow!(
+                    "Transition from unknown node {}",
+                    named_transition.from
+                ));
+            }
+        };
+        let to = match node_names.get(&named_transition.to) {
+            Some(val) => *val,
+            None => {
+                return Err(anyhow!("Transition to unknown node {}", named_transition.to));
+            }
+        };
+        graph.add_edge(named_transition.transition, from, to)?;
+    }
+    match node_names.get(root) {
+        Some(val) => Ok((graph, *val)),
+        None => Err(anyhow!("Unknown root node {}", root)),
+    }
+}
+
+fn graph_to_file(
+    graph: &DefinitionGraph,
+    root: Option<NodeIndex>,
+) -> Result<(String, Vec<GenericNodeDefinition>, Vec<NamedTransitionDefinition>), anyhow::Error> {
+    let root = match root.and_then(|val| graph.node(val)) {
+        Some(val) => val.weight().name().to_string(),
+        None => return Err(anyhow!("No root node found in animgraph definition")),
+    };
+    let nodes = graph
+        .node_weights()
+        .map(|(_, node_definition)| node_definition.clone())
+        .collect();
+    let mut transitions = Vec::<NamedTransitionDefinition>::new();
+    for (edge_idx, transition_definition) in graph.edge_weights() {
+        let edge = graph.edge(edge_idx).unwrap();
+        transitions.push(NamedTransitionDefinition {
+            from: graph.node(edge.from()).unwrap().weight().name().to_string(),
+            to: graph.node(edge.to()).unwrap().weight().name().to_string(),
+            transition: transition_definition.clone(),
+        });
+    }
+    transitions.sort_by_key(|t| t.transition.declared);
+    Ok((root, nodes, transitions))
+}
+
+fn default_layer_weight() -> f32 {
+    1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::condition_definitions::{ComparisonOp, ConditionDefinition};
+    use crate::parameter_definitions::ParameterValue;
+
+    fn definition() -> AnimGraphDefinition {
+        AnimGraphDefinitionBuilder::new()
+            .parameter("speed", ParameterValue::Float(0.0))
+            .sampler("idle", "idle_anim").looping(true)
+            .sampler("walk", "walk_anim").looping(true).speed(1.5).event("step", 0.5, Some("left"))
+            .sampler("jump", "jump_anim")
+            .transition("walk", "idle").priority(2)
+            .transition("idle", "walk").duration(web_time::Duration::from_millis(250))
+            .condition(ConditionDefinition::Float {
+                parameter: "speed".to_string(),
+                op: ComparisonOp::Greater,
+                value: 0.1,
+            })
+            .transition("idle", "jump").cost(3.0)
+            .transition("jump", "idle").exit_time(0.9)
+            .any_state_transition("jump").exclude("walk")
+            .root("idle")
+            .layer("upper").root("wave").sampler("wave", "idle_anim").looping(true).layer_weight(0.5)
+            .build()
+            .unwrap()
+    }
+
+    fn transitions(definition: &AnimGraphDefinition) -> Vec<(String, String)> {
+        let file = definition.to_file().unwrap();
+        file.transitions.into_iter().map(|t| (t.from, t.to)).collect()
+    }
+
+    #[test]
+    fn ron_round_trips() {
+        let written = definition().to_ron_string().unwrap();
+        let read = AnimGraphDefinition::from_ron_str(&written).unwrap();
+        assert_eq!(read.to_ron_string().unwrap(), written);
+        assert_eq!(transitions(&read), transitions(&definition()));
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let written = definition().to_json_string().unwrap();
+        let read = AnimGraphDefinition::from_json_str(&written).unwrap();
+        assert_eq!(read.to_json_string().unwrap(), written);
+        // Either format reads back the same definition.
+        let from_ron = AnimGraphDefinition::from_ron_str(&definition().to_ron_string().unwrap()).unwrap();
+        assert_eq!(from_ron.to_json_string().unwrap(), written);
+    }
+
+    #[test]
+    fn round_trips_keep_what_was_authored() {
+        let read = AnimGraphDefinition::from_json_str(&definition().to_json_string().unwrap()).unwrap();
+        let file = read.to_file().unwrap();
+        assert_eq!(file.root, "idle");
+        assert_eq!(file.parameters[0].name, "speed");
+        match &file.nodes[1] {
+            GenericNodeDefinition::Sampler(val) => {
+                assert_eq!((val.name.as_str(), val.speed, val.looping), ("walk", 1.5, true));
+                assert_eq!(val.events[0].payload.as_deref(), Some("left"));
+            }
+            _ => panic!("walk should be a sampler"),
+        }
+        // Declaration order, which breaks priority ties, survives.
+        let expected = [("walk", "idle"), ("idle", "walk"), ("idle", "jump"), ("jump", "idle")];
+        assert_eq!(transitions(&read), expected.map(|(from, to)| (from.to_string(), to.to_string())));
+        assert_eq!(file.transitions[1].transition.duration, web_time::Duration::from_millis(250));
+        assert_eq!(file.transitions[3].transition.exit_time, Some(0.9));
+        assert_eq!(file.any_state_transitions[0].exclude, vec!["walk".to_string()]);
+        assert_eq!((file.layers[0].name.as_str(), file.layers[0].weight), ("upper", 0.5));
+    }
+
+    #[test]
+    fn omitted_fields_take_their_defaults() {
+        let json = r#"{
+            "root": "idle",
+            "nodes": [{ "Sampler": { "name": "idle", "animation_name": "idle_anim" } }]
+        }"#;
+        let file = AnimGraphDefinition::from_json_str(json).unwrap().to_file().unwrap();
+        match &file.nodes[0] {
+            GenericNodeDefinition::Sampler(val) => assert_eq!((val.speed, val.looping), (1.0, false)),
+            _ => panic!("idle should be a sampler"),
+        }
+        assert!(file.transitions.is_empty() && file.layers.is_empty());
+    }
+
+    #[test]
+    fn duplicate_node_names_are_an_error() {
+        let mut file = definition().to_file().unwrap();
+        file.nodes.push(file.nodes[0].clone());
+        let result = AnimGraphDefinition::from_file(file);
+        assert!(result.is_err_and(|e| e.to_string() == "Duplicate node name: idle"));
+        // In layers too, which say which layer.
+        let mut file = definition().to_file().unwrap();
+        let wave = file.layers[0].nodes[0].clone();
+        file.layers[0].nodes.push(wave);
+        let result = AnimGraphDefinition::from_file(file);
+        assert!(result.is_err_and(|e| e.to_string() == "Layer upper: Duplicate node name: wave"));
+    }
+
+    #[test]
+    fn transitions_between_unknown_nodes_are_an_error() {
+        let mut file = definition().to_file().unwrap();
+        file.transitions[0].from = "run".to_string();
+        assert!(AnimGraphDefinition::from_file(file).is_err_and(|e| e.to_string().contains("from unknown node run")));
+        let mut file = definition().to_file().unwrap();
+        file.root = "run".to_string();
+        assert!(AnimGraphDefinition::from_file(file).is_err_and(|e| e.to_string() == "Unknown root node run"));
+    }
+}
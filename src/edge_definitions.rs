@@ -1,9 +1,96 @@
+use crate::condition_definitions::ConditionDefinition;
+use crate::curves::TransitionCurve;
+use serde::{Deserialize, Serialize};
+
 safe_index::new! {
     TransitionDefinitionIndex,
     map: TransitionDefinitionContainer,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TransitionDefinition {
+    // Written out as seconds.
+    #[serde(with = "duration_as_secs")]
     pub duration: web_time::Duration,
+    #[serde(default)]
+    pub mode: TransitionMode,
+    // How the blend progresses over the duration. Only used when cross-fading.
+    #[serde(default)]
+    pub curve: TransitionCurve,
+    // All of these must hold for the transition to fire on its own.
+    #[serde(default)]
+    pub conditions: Vec<ConditionDefinition>,
+    // Normalized time of the source node at which the transition may fire, on top of its conditions. A one-shot
+    // sampler that has finished always counts as having reached it.
+    // Transitions with neither conditions nor an exit time are only taken when routing.
+    #[serde(default)]
+    pub exit_time: Option<f32>,
+    // How expensive this transition is to route through. Defaults to its duration in seconds.
+    #[serde(default)]
+    pub cost: Option<f32>,
+    // Whether a new route may cut this transition short, blending from wherever it got to. Otherwise the new route
+    // starts once it is over.
+    #[serde(default)]
+    pub interruptible: bool,
+    // Which of the transitions that could fire together is taken, the highest first. Ties go to the one declared
+    // first. Between routes of the same cost, routing arrives at each node through its highest priority transition.
+    #[serde(default)]
+    pub priority: i32,
+    // Where the transition was declared among its graph's transitions, which breaks ties between equal priorities.
+    // Files and the builder number transitions in the order they list them; files are written back in that order.
+    #[serde(skip)]
+    pub declared: usize,
+}
+
+// A transition between two nodes given by name, as written in files.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NamedTransitionDefinition {
+    pub from: String,
+    pub to: String,
+    pub transition: TransitionDefinition,
+}
+
+// A transition that may leave from any node of its state machine, e.g. into a death or a hit reaction.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AnyStateTransitionDefinition {
+    pub to: String,
+    // Any-state transitions are checked before a node's own transitions, in priority order too.
+    pub transition: TransitionDefinition,
+    // The nodes it may not leave from.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // Whether it may also cut a transition short, blending from wherever that transition got to.
+    #[serde(default)]
+    pub during_transitions: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum TransitionMode {
+    // Evaluates both nodes for the whole transition and blends between them.
+    #[default]
+    CrossFade,
+    // Only evaluates the destination, decaying the difference from the pose we left over the duration.
+    Inertialization(InertializationDecay),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum InertializationDecay {
+    // Reaches the destination exactly at the end of the duration, with no overshoot.
+    Quintic,
+    // Settles quickly and smoothly, but only gets to within a percent of the destination by the end.
+    CriticallyDamped,
+}
+
+mod duration_as_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &web_time::Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(duration.as_secs_f32())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<web_time::Duration, D::Error> {
+        let secs = f32::deserialize(deserializer)?;
+        web_time::Duration::try_from_secs_f32(secs).map_err(serde::de::Error::custom)
+    }
 }
 
@@ -1,28 +1,114 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cmp::Ordering, collections::HashMap};
 
-use ozz_animation_rs::{BlendingJob, BlendingLayer, Skeleton, SoaTransform};
+use mapgraph::map::slotmap::{EdgeIndex, NodeIndex};
+
+use ozz_animation_rs::{BlendingLayer, Skeleton, SoaTransform};
+
+use crate::shared::*;
+use crate::{
+    conditions::Condition,
+    curves::TransitionCurve,
+    edge_definitions::{TransitionDefinition, TransitionMode},
+    inertialization::Inertialization,
+    parameters::Blackboard,
+};
 
 safe_index::new! {
 TransitionIndex,
 map: TransitionsContainer
 }
 
-pub struct Transition {
+// The parts of a transition that don't change as it plays, with its conditions resolved against the blackboard.
+pub struct TransitionLayout {
     pub duration: web_time::Duration,
+    pub curve: TransitionCurve,
+    pub mode: TransitionMode,
+    pub conditions: Vec<Condition>,
+    pub exit_time: Option<f32>,
+    // How expensive this transition is to route through.
+    pub cost: f32,
+    pub interruptible: bool,
+    pub priority: i32,
+    pub declared: usize,
+}
+
+// The playback state of a transition. Its settings are in the TransitionLayout under the same index.
+pub struct Transition {
     pub seek: web_time::Duration,
-    pub blend_job: BlendingJob,
-    pub output: Rc<RefCell<Vec<SoaTransform>>>,
+    // Set for inertialized transitions, which don't use the blend job.
+    pub inertialization: Option<Inertialization>,
+    pub blend_job: SharedBlendingJob,
+    pub output: Buffer<SoaTransform>,
     pub started: bool,
+    // The source node's output, which the first layer blends from unless the transition started from a snapshot.
+    from_output: Buffer<SoaTransform>,
+}
+
+// The order candidate transitions are considered in, both when firing on their own and when routing: the highest
+// priority first, then the one declared first.
+pub fn transition_order(a: &TransitionLayout, b: &TransitionLayout) -> Ordering {
+    b.priority.cmp(&a.priority).then_with(|| a.declared.cmp(&b.declared))
+}
+
+// An any-state transition, built as one edge from each node it may leave from so that routing can take it too.
+pub struct AnyStateTransition {
+    pub priority: i32,
+    pub during_transitions: bool,
+    // The edge leaving from each node.
+    pub edges: HashMap<NodeIndex, EdgeIndex>,
+}
+
+impl TransitionLayout {
+    pub fn new(definition: &TransitionDefinition, blackboard: &Blackboard) -> Result<Self, anyhow::Error> {
+        let mut conditions = Vec::<Condition>::new();
+        for condition_definition in &definition.conditions {
+            conditions.push(Condition::new(condition_definition, blackboard)?);
+        }
+        Ok(TransitionLayout {
+            duration: definition.duration,
+            curve: definition.curve.clone(),
+            mode: definition.mode,
+            conditions,
+            exit_time: definition.exit_time,
+            // Defaults to the duration in seconds.
+            cost: definition.cost.unwrap_or(definition.duration.as_secs_f32()),
+            interruptible: definition.interruptible,
+            priority: definition.priority,
+            declared: definition.declared,
+        })
+    }
+
+    pub fn is_automatic(&self) -> bool {
+        !self.conditions.is_empty() || self.exit_time.is_some()
+    }
+
+    // Whether this transition should fire on its own. Transitions with neither conditions nor an exit time never do.
+    pub fn can_fire(&self, blackboard: &Blackboard, exit_time_reached: bool) -> bool {
+        if !self.is_automatic() {
+            return false;
+        }
+        if self.exit_time.is_some() && !exit_time_reached {
+            return false;
+        }
+        self.conditions.iter().all(|c| c.evaluate(blackboard))
+    }
+
+    pub fn consume_triggers(&self, blackboard: &mut Blackboard) {
+        for c in &self.conditions {
+            c.consume_triggers(blackboard);
+        }
+    }
 }
 
 impl Transition {
     pub fn new(
-        skeleton: Rc<Skeleton>,
-        duration: web_time::Duration,
-        from_output: Rc<RefCell<Vec<SoaTransform>>>,
-        to_output: Rc<RefCell<Vec<SoaTransform>>>,
+        skeleton: Shared<Skeleton>,
+        layout: &TransitionLayout,
+        from_output: Buffer<SoaTransform>,
+        to_output: Buffer<SoaTransform>,
     ) -> Transition {
-        let mut blend_job = BlendingJob::default();
+        let mut blend_job = SharedBlendingJob::default();
+        blend_job.set_skeleton(skeleton.clone());
         blend_job
             .layers_mut()
             .push(BlendingLayer::new(from_output.clone()));
@@ -30,22 +116,92 @@ impl Transition {
             .layers_mut()
             .push(BlendingLayer::new(to_output.clone()));
 
-        let output = Rc::new(RefCell::new(vec![
+        let output = new_buffer(vec![
             SoaTransform::default();
             skeleton.num_soa_joints()
-        ]));
+        ]);
         blend_job.set_output(output.clone());
+        let inertialization = match layout.mode {
+            TransitionMode::CrossFade => None,
+            TransitionMode::Inertialization(decay) => Some(Inertialization::new(decay, skeleton.num_joints())),
+        };
         Transition {
-            duration,
             seek: web_time::Duration::from_nanos(0),
+            inertialization,
             blend_job,
             output: output.clone(),
             started: false,
+            from_output,
+        }
+    }
+
+    // Elapsed time over duration, from 0 to 1. Instant transitions are always done.
+    pub fn progress(&self, layout: &TransitionLayout) -> f32 {
+        if layout.duration.is_zero() {
+            return 1.0;
         }
+        (self.seek.as_secs_f32() / layout.duration.as_secs_f32()).clamp(0.0, 1.0)
+    }
+
+    pub fn is_finished(&self, layout: &TransitionLayout) -> bool {
+        self.seek >= layout.duration
+    }
+
+    // The weight of the destination, the source getting the rest.
+    pub fn destination_weight(&self, layout: &TransitionLayout) -> f32 {
+        layout.curve.evaluate(self.progress(layout))
+    }
+
+    pub fn update_weights(&mut self, layout: &TransitionLayout) {
+        let weight = self.destination_weight(layout);
+        let layers = self.blend_job.layers_mut();
+        layers[0].weight = 1.0 - weight;
+        layers[1].weight = weight;
+    }
+
+    // Blends from `snapshot` instead of the source node, until reset.
+    pub fn start_from_snapshot(&mut self, snapshot: Buffer<SoaTransform>) {
+        self.blend_job.layers_mut()[0].transform = snapshot;
+    }
+
+    pub fn is_from_snapshot(&self) -> bool {
+        !Shared::ptr_eq(&self.blend_job.layers()[0].transform, &self.from_output)
     }
 
     pub fn reset(&mut self) {
         self.seek = web_time::Duration::from_nanos(0);
         self.started = false;
+        self.blend_job.layers_mut()[0].transform = self.from_output.clone();
+        if let Some(inertialization) = &mut self.inertialization {
+            inertialization.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(priority: i32, declared: usize) -> TransitionLayout {
+        let definition = TransitionDefinition {
+            duration: web_time::Duration::ZERO,
+            mode: TransitionMode::CrossFade,
+            curve: TransitionCurve::Linear,
+            conditions: Vec::new(),
+            exit_time: None,
+            cost: None,
+            interruptible: false,
+            priority,
+            declared,
+        };
+        TransitionLayout::new(&definition, &Blackboard::new(&[]).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn highest_priority_then_first_declared_comes_first() {
+        let mut candidates = vec![transition(0, 0), transition(5, 2), transition(-1, 3), transition(5, 1)];
+        candidates.sort_by(transition_order);
+        let order = candidates.iter().map(|t| (t.priority, t.declared)).collect::<Vec<_>>();
+        assert_eq!(order, vec![(5, 1), (5, 2), (0, 0), (-1, 3)]);
     }
 }
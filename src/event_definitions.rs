@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+// A marker on a clip, fired whenever playback crosses `time` (normalized, 0 to 1).
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AnimationEventDefinition {
+    pub name: String,
+    pub time: f32,
+    // Free-form data for whoever handles the event, e.g. a sound or VFX name.
+    #[serde(default)]
+    pub payload: Option<String>,
+}
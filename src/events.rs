@@ -0,0 +1,119 @@
+use crate::event_definitions::AnimationEventDefinition;
+
+// An event fired during the last AnimGraph::evaluate().
+#[derive(Clone, PartialEq, Debug)]
+pub struct AnimationEvent {
+    pub name: String,
+    pub payload: Option<String>,
+    // The node whose clip the marker is on.
+    pub node: String,
+    // The clip's blend weight within its node when it fired. Always 1 for samplers.
+    pub weight: f32,
+}
+
+// The markers of a single clip, kept sorted by time.
+pub struct EventTrack {
+    node: String,
+    markers: Vec<AnimationEventDefinition>,
+}
+
+impl EventTrack {
+    pub fn new(node: &str, markers: &[AnimationEventDefinition]) -> Self {
+        let mut markers = markers.to_vec();
+        markers.sort_by(|a, b| a.time.total_cmp(&b.time));
+        EventTrack {
+            node: node.to_string(),
+            markers,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    // Pushes the markers crossed going from `previous` to `current` (normalized). Going backwards means playback
+    // wrapped around, in which case the markers up to the end of the clip fire before those from its start.
+    pub fn collect(&self, previous: f32, current: f32, weight: f32, fired: &mut Vec<AnimationEvent>) {
+        if current >= previous {
+            self.collect_range(previous, current, weight, fired);
+        } else {
+            self.collect_range(previous, f32::INFINITY, weight, fired);
+            self.collect_range(f32::NEG_INFINITY, current, weight, fired);
+        }
+    }
+
+    fn collect_range(&self, after: f32, up_to: f32, weight: f32, fired: &mut Vec<AnimationEvent>) {
+        for marker in &self.markers {
+            if after < marker.time && marker.time <= up_to {
+                fired.push(AnimationEvent {
+                    name: marker.name.clone(),
+                    payload: marker.payload.clone(),
+                    node: self.node.clone(),
+                    weight,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::test_fixtures::*;
+
+    fn marker(name: &str, time: f32) -> AnimationEventDefinition {
+        AnimationEventDefinition {
+            name: name.to_string(),
+            time,
+            payload: None,
+        }
+    }
+
+    fn fired(track: &EventTrack, previous: f32, current: f32) -> Vec<String> {
+        let mut fired = Vec::new();
+        track.collect(previous, current, 1.0, &mut fired);
+        fired.into_iter().map(|e| e.name).collect()
+    }
+
+    fn track() -> EventTrack {
+        EventTrack::new("walk", &[marker("end", 1.0), marker("middle", 0.5), marker("start", 0.0)])
+    }
+
+    #[test]
+    fn fires_the_markers_crossed() {
+        let track = track();
+        assert_eq!(fired(&track, 0.2, 0.4), Vec::<String>::new());
+        assert_eq!(fired(&track, 0.4, 0.5), vec!["middle"]);
+        // A marker fires once, on the frame that reaches it.
+        assert_eq!(fired(&track, 0.5, 0.6), Vec::<String>::new());
+        assert_eq!(fired(&track, 0.9, 1.0), vec!["end"]);
+    }
+
+    #[test]
+    fn fires_both_ends_across_a_wrap_in_order() {
+        let track = track();
+        assert_eq!(fired(&track, 0.9, 0.1), vec!["end", "start"]);
+        assert_eq!(fired(&track, 0.4, 0.1), vec!["middle", "end", "start"]);
+        // Landing exactly on the start fires it, leaving the start doesn't fire it again.
+        assert_eq!(fired(&track, 0.9, 0.0), vec!["end", "start"]);
+        assert_eq!(fired(&track, 0.0, 0.1), Vec::<String>::new());
+    }
+
+    #[test]
+    fn looping_samplers_fire_each_marker_once_per_loop() {
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("walk", "a").looping(true)
+            .event("start", 0.0, None)
+            .event("end", 1.0, Some("right foot"))
+            .root("walk");
+        let mut graph = graph(&builder.build().unwrap());
+        let mut fired = Vec::new();
+        for _ in 0..25 {
+            step(&mut graph, 0.1);
+            fired.extend(graph.get_events().iter().map(|e| e.name.clone()));
+        }
+        // Entering the clip, then 2.5 loops of it, wrapping twice.
+        assert_eq!(fired, vec!["start", "end", "start", "end", "start"]);
+    }
+}
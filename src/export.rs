@@ -0,0 +1,457 @@
+use crate::animgraph_definition::{AnimGraphDefinition, DefinitionGraph};
+use crate::edge_definitions::{AnyStateTransitionDefinition, TransitionDefinition};
+use crate::node_definitions::GenericNodeDefinition;
+use mapgraph::map::slotmap::EdgeIndex;
+use mapgraph::map::slotmap::NodeIndex;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiagramNodeKind {
+    Sampler,
+    BlendTreeOneDim,
+    BlendSpaceTwoDim,
+    Additive,
+    StateMachine,
+    // Where any-state transitions leave from.
+    AnyState,
+}
+
+impl DiagramNodeKind {
+    fn label(&self) -> &'static str {
+        match self {
+            DiagramNodeKind::Sampler => "sampler",
+            DiagramNodeKind::BlendTreeOneDim => "blend 1D",
+            DiagramNodeKind::BlendSpaceTwoDim => "blend 2D",
+            DiagramNodeKind::Additive => "additive",
+            DiagramNodeKind::StateMachine => "state machine",
+            DiagramNodeKind::AnyState => "any state",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiagramEdgeState {
+    Idle,
+    // The transition currently being blended.
+    Active,
+    // Part of the route still queued up.
+    Queued,
+}
+
+pub struct DiagramNode {
+    pub name: String,
+    pub kind: DiagramNodeKind,
+    pub root: bool,
+    pub current: bool,
+    // The layer the node belongs to, drawn as a cluster. None when the graph has no layers.
+    pub group: Option<String>,
+}
+
+pub struct DiagramEdge {
+    pub from: usize,
+    pub to: usize,
+    pub label: String,
+    pub state: DiagramEdgeState,
+}
+
+// A renderer-agnostic view of a graph, built either from a definition or from a live AnimGraph.
+#[derive(Default)]
+pub struct Diagram {
+    pub nodes: Vec<DiagramNode>,
+    pub edges: Vec<DiagramEdge>,
+}
+
+impl Diagram {
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph animgraph {\n    node [fontname=\"Helvetica\"];\n");
+        let mut node_lines = Vec::<(Option<&str>, String)>::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let shape = match node.kind {
+                DiagramNodeKind::Sampler => "box",
+                DiagramNodeKind::BlendTreeOneDim => "trapezium",
+                DiagramNodeKind::BlendSpaceTwoDim => "hexagon",
+                DiagramNodeKind::Additive => "parallelogram",
+                DiagramNodeKind::StateMachine => "box3d",
+                DiagramNodeKind::AnyState => "circle",
+            };
+            let mut attributes = format!(
+                "label=\"{}\\n({})\", shape={}",
+                escape_dot(&node.name),
+                node.kind.label(),
+                shape
+            );
+            if node.root {
+                attributes.push_str(", peripheries=2");
+            }
+            if node.current {
+                attributes.push_str(", style=filled, fillcolor=\"#ffcc66\"");
+            }
+            node_lines.push((node.group.as_deref(), format!("n{} [{}];", i, attributes)));
+        }
+        for (i, (group, lines)) in group_lines(node_lines).into_iter().enumerate() {
+            match group {
+                Some(name) => {
+                    let _ = writeln!(out, "    subgraph cluster_{} {{\n        label=\"{}\";", i, escape_dot(name));
+                    for line in lines {
+                        let _ = writeln!(out, "        {}", line);
+                    }
+                    out.push_str("    }\n");
+                }
+                None => {
+                    for line in lines {
+                        let _ = writeln!(out, "    {}", line);
+                    }
+                }
+            }
+        }
+        for edge in &self.edges {
+            let style = match edge.state {
+                DiagramEdgeState::Idle => "",
+                DiagramEdgeState::Active => ", color=\"#e03030\", penwidth=3",
+                DiagramEdgeState::Queued => ", color=\"#3070e0\", style=dashed, penwidth=2",
+            };
+            let _ = writeln!(
+                out,
+                "    n{} -> n{} [label=\"{}\"{}];",
+                edge.from,
+                edge.to,
+                escape_dot(&edge.label),
+                style
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart LR\n");
+        let mut node_lines = Vec::<(Option<&str>, String)>::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = format!("{}<br/>({})", escape_mermaid(&node.name), node.kind.label());
+            let shape = match node.kind {
+                DiagramNodeKind::Sampler => format!("[\"{}\"]", label),
+                DiagramNodeKind::BlendTreeOneDim => format!("[/\"{}\"\\]", label),
+                DiagramNodeKind::BlendSpaceTwoDim => format!("{{{{\"{}\"}}}}", label),
+                DiagramNodeKind::Additive => format!("[/\"{}\"/]", label),
+                DiagramNodeKind::StateMachine => format!("[[\"{}\"]]", label),
+                DiagramNodeKind::AnyState => format!("((\"{}\"))", label),
+            };
+            node_lines.push((node.group.as_deref(), format!("n{}{}", i, shape)));
+        }
+        for (i, (group, lines)) in group_lines(node_lines).into_iter().enumerate() {
+            match group {
+                Some(name) => {
+                    let _ = writeln!(out, "    subgraph g{} [\"{}\"]", i, escape_mermaid(name));
+                    for line in lines {
+                        let _ = writeln!(out, "        {}", line);
+                    }
+                    out.push_str("    end\n");
+                }
+                None => {
+                    for line in lines {
+                        let _ = writeln!(out, "    {}", line);
+                    }
+                }
+            }
+        }
+        for edge in &self.edges {
+            if edge.label.is_empty() {
+                let _ = writeln!(out, "    n{} --> n{}", edge.from, edge.to);
+            } else {
+                let _ = writeln!(
+                    out,
+                    "    n{} -->|\"{}\"| n{}",
+                    edge.from,
+                    escape_mermaid(&edge.label),
+                    edge.to
+                );
+            }
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.root {
+                let _ = writeln!(out, "    style n{} stroke-width:4px", i);
+            }
+            if node.current {
+                let _ = writeln!(out, "    style n{} fill:#ffcc66", i);
+            }
+        }
+        // Mermaid styles links by the order they were declared in.
+        for (i, edge) in self.edges.iter().enumerate() {
+            match edge.state {
+                DiagramEdgeState::Idle => {}
+                DiagramEdgeState::Active => {
+                    let _ = writeln!(out, "    linkStyle {} stroke:#e03030,stroke-width:3px", i);
+                }
+                DiagramEdgeState::Queued => {
+                    let _ = writeln!(
+                        out,
+                        "    linkStyle {} stroke:#3070e0,stroke-width:2px,stroke-dasharray:4",
+                        i
+                    );
+                }
+            }
+        }
+        out
+    }
+}
+
+// The edge label: duration, then priority, exit time and conditions when there are any.
+pub fn transition_label(
+    duration: web_time::Duration,
+    priority: i32,
+    exit_time: Option<f32>,
+    conditions: &[String],
+) -> String {
+    let mut parts = vec![format!("{}s", duration.as_secs_f32())];
+    if priority != 0 {
+        parts.push(format!("priority {}", priority));
+    }
+    if let Some(exit_time) = exit_time {
+        parts.push(format!("exit {}", exit_time));
+    }
+    if !conditions.is_empty() {
+        parts.push(conditions.join(" && "));
+    }
+    parts.join(", ")
+}
+
+// Gathers the node declarations by group, keeping the order groups first appear in.
+fn group_lines(node_lines: Vec<(Option<&str>, String)>) -> Vec<(Option<&str>, Vec<String>)> {
+    let mut groups = Vec::<(Option<&str>, Vec<String>)>::new();
+    for (group, line) in node_lines {
+        match groups.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, lines)) => lines.push(line),
+            None => groups.push((group, vec![line])),
+        }
+    }
+    groups
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Mermaid entity codes start with '#', so it goes first.
+fn escape_mermaid(text: &str) -> String {
+    text.replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('|', "#124;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+impl AnimGraphDefinition {
+    pub fn to_diagram(&self) -> Diagram {
+        let mut diagram = Diagram::default();
+        let base_group = if self.layers.is_empty() { None } else { Some("base") };
+        append_definition_graph(&mut diagram, &self.graph, self.root, &self.any_state_transitions, base_group);
+        for layer in &self.layers {
+            append_definition_graph(&mut diagram, &layer.graph, layer.root, &[], Some(&layer.name));
+        }
+        diagram
+    }
+
+    pub fn to_dot(&self) -> String {
+        self.to_diagram().to_dot()
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        self.to_diagram().to_mermaid()
+    }
+}
+
+fn append_definition_graph(
+    diagram: &mut Diagram,
+    graph: &DefinitionGraph,
+    root: Option<NodeIndex>,
+    any_state_transitions: &[AnyStateTransitionDefinition],
+    group: Option<&str>,
+) {
+    let mut node_ids = HashMap::<NodeIndex, usize>::new();
+    let mut node_ids_by_name = HashMap::<&str, usize>::new();
+    for (node_idx, node_definition) in graph.node_weights() {
+        let kind = match node_definition {
+            GenericNodeDefinition::Sampler(_) => DiagramNodeKind::Sampler,
+            GenericNodeDefinition::BlendTreeOneDim(_) => DiagramNodeKind::BlendTreeOneDim,
+            GenericNodeDefinition::BlendSpaceTwoDim(_) => DiagramNodeKind::BlendSpaceTwoDim,
+            GenericNodeDefinition::Additive(_) => DiagramNodeKind::Additive,
+            GenericNodeDefinition::StateMachine(_) => DiagramNodeKind::StateMachine,
+        };
+        node_ids.insert(node_idx, diagram.nodes.len());
+        node_ids_by_name.insert(node_definition.name(), diagram.nodes.len());
+        diagram.nodes.push(DiagramNode {
+            name: node_definition.name().to_string(),
+            kind,
+            root: root == Some(node_idx),
+            current: false,
+            group: group.map(|g| g.to_string()),
+        });
+    }
+    for (edge_idx, transition_definition) in graph.edge_weights() {
+        let edge = graph.edge(edge_idx).unwrap();
+        diagram.edges.push(DiagramEdge {
+            from: node_ids[&edge.from()],
+            to: node_ids[&edge.to()],
+            label: definition_transition_label(transition_definition),
+            state: DiagramEdgeState::Idle,
+        });
+    }
+    if !any_state_transitions.is_empty() {
+        let any_state_id = diagram.nodes.len();
+        diagram.nodes.push(DiagramNode {
+            name: String::from("any state"),
+            kind: DiagramNodeKind::AnyState,
+            root: false,
+            current: false,
+            group: group.map(|g| g.to_string()),
+        });
+        for any_state in any_state_transitions {
+            if let Some(to) = node_ids_by_name.get(any_state.to.as_str()) {
+                diagram.edges.push(DiagramEdge {
+                    from: any_state_id,
+                    to: *to,
+                    label: definition_transition_label(&any_state.transition),
+                    state: DiagramEdgeState::Idle,
+                });
+            }
+        }
+    }
+    for (_, node_definition) in graph.node_weights() {
+        if let GenericNodeDefinition::StateMachine(val) = node_definition
+            && let Ok((nested_graph, entry)) = val.to_graph()
+        {
+            let nested_group = match group {
+                Some(g) => format!("{}/{}", g, val.name),
+                None => val.name.clone(),
+            };
+            append_definition_graph(
+                diagram,
+                &nested_graph,
+                Some(entry),
+                &val.any_state_transitions,
+                Some(&nested_group),
+            );
+        }
+    }
+}
+
+fn definition_transition_label(transition_definition: &TransitionDefinition) -> String {
+    let conditions = transition_definition
+        .conditions
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>();
+    transition_label(
+        transition_definition.duration,
+        transition_definition.priority,
+        transition_definition.exit_time,
+        &conditions,
+    )
+}
+
+// Looks up the state of `edge_idx` given the live graph's active edge and queued path.
+pub fn edge_state(edge_idx: EdgeIndex, active: Option<EdgeIndex>, queued: &[EdgeIndex]) -> DiagramEdgeState {
+    if active == Some(edge_idx) {
+        DiagramEdgeState::Active
+    } else if queued.contains(&edge_idx) {
+        DiagramEdgeState::Queued
+    } else {
+        DiagramEdgeState::Idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::condition_definitions::ConditionDefinition;
+    use crate::parameter_definitions::ParameterValue;
+    use crate::test_fixtures::*;
+
+    fn definition() -> crate::animgraph_definition::AnimGraphDefinition {
+        AnimGraphDefinitionBuilder::new()
+            .parameter("run|walk", ParameterValue::Bool(false))
+            .sampler(r#"idle "loop""#, "a").looping(true)
+            .sampler("walk [fast]", "b").looping(true)
+            .sampler("turn <left> #2", "c").looping(true)
+            .transition(r#"idle "loop""#, "walk [fast]").duration(web_time::Duration::from_millis(250))
+            .transition("walk [fast]", "turn <left> #2").priority(1).condition(ConditionDefinition::Bool {
+                parameter: "run|walk".to_string(),
+                value: true,
+            })
+            .transition("turn <left> #2", r#"idle "loop""#).exit_time(0.5)
+            .root(r#"idle "loop""#)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn definitions_render_as_dot() {
+        let expected = r#"digraph animgraph {
+    node [fontname="Helvetica"];
+    n0 [label="idle \"loop\"\n(sampler)", shape=box, peripheries=2];
+    n1 [label="walk [fast]\n(sampler)", shape=box];
+    n2 [label="turn <left> #2\n(sampler)", shape=box];
+    n0 -> n1 [label="0.25s"];
+    n1 -> n2 [label="0s, priority 1, run|walk == true"];
+    n2 -> n0 [label="0s, exit 0.5"];
+}
+"#;
+        assert_eq!(definition().to_dot(), expected);
+    }
+
+    #[test]
+    fn definitions_render_as_mermaid() {
+        let expected = r#"flowchart LR
+    n0["idle #quot;loop#quot;<br/>(sampler)"]
+    n1["walk [fast]<br/>(sampler)"]
+    n2["turn #lt;left#gt; #35;2<br/>(sampler)"]
+    n0 -->|"0.25s"| n1
+    n1 -->|"0s, priority 1, run#124;walk == true"| n2
+    n2 -->|"0s, exit 0.5"| n0
+    style n0 stroke-width:4px
+"#;
+        assert_eq!(definition().to_mermaid(), expected);
+    }
+
+    // Halfway from "a" to "b" on the way to "c", which leaves "b" and "c" queued up.
+    fn live_graph() -> crate::animgraph::AnimGraph {
+        let definition = AnimGraphDefinitionBuilder::new()
+            .sampler("a", "a").looping(true)
+            .sampler("b", "b").looping(true)
+            .sampler("c", "c").looping(true)
+            .transition("a", "b").duration(web_time::Duration::from_secs(1))
+            .transition("b", "c").duration(web_time::Duration::from_secs(1))
+            .root("a")
+            .build()
+            .unwrap();
+        let mut graph = graph(&definition);
+        graph.set_target_node_by_name("c".to_string()).unwrap();
+        step(&mut graph, 0.5);
+        graph
+    }
+
+    #[test]
+    fn live_graphs_highlight_the_active_transition_and_the_queued_path() {
+        let mut graph = live_graph();
+        let dot = graph.to_dot();
+        assert!(dot.contains("    n0 -> n1 [label=\"1s\", color=\"#e03030\", penwidth=3];\n"), "{}", dot);
+        assert!(dot.contains("    n1 -> n2 [label=\"1s\", color=\"#3070e0\", style=dashed, penwidth=2];\n"), "{}", dot);
+        // Nothing is current while blending.
+        assert!(!dot.contains("fillcolor"), "{}", dot);
+        let mermaid = graph.to_mermaid();
+        assert!(mermaid.ends_with(
+            "    style n0 stroke-width:4px\n    linkStyle 0 stroke:#e03030,stroke-width:3px\n    linkStyle 1 \
+             stroke:#3070e0,stroke-width:2px,stroke-dasharray:4\n"
+        ), "{}", mermaid);
+
+        step(&mut graph, 0.6);
+        step(&mut graph, 1.0);
+        let dot = graph.to_dot();
+        let current = "    n2 [label=\"c\\n(sampler)\", shape=box, style=filled, fillcolor=\"#ffcc66\"];\n";
+        assert!(dot.contains(current), "{}", dot);
+        assert!(!dot.contains("color=\"#e03030\"") && !dot.contains("dashed"), "{}", dot);
+        let mermaid = graph.to_mermaid();
+        assert!(mermaid.ends_with("    style n0 stroke-width:4px\n    style n2 fill:#ffcc66\n"), "{}", mermaid);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::ik_definitions::{AimIkDefinition, TwoBoneIkDefinition};
+use crate::shared::*;
+use anyhow::anyhow;
+use ozz_animation_rs::*;
+
+// A two-bone IK chain resolved against the skeleton, shared by every AnimGraphInstance.
+pub struct TwoBoneIkLayout {
+    pub name: String,
+    start: usize,
+    mid: usize,
+    end: usize,
+    mid_axis: glam::Vec3,
+    soften: f32,
+    twist_angle: f32,
+    weight: f32,
+}
+
+impl TwoBoneIkLayout {
+    pub fn new(skeleton: &Skeleton, definition: &TwoBoneIkDefinition) -> Result<Self, anyhow::Error> {
+        Ok(TwoBoneIkLayout {
+            name: definition.name.clone(),
+            start: joint_index(skeleton, &definition.name, &definition.start_joint)?,
+            mid: joint_index(skeleton, &definition.name, &definition.mid_joint)?,
+            end: joint_index(skeleton, &definition.name, &definition.end_joint)?,
+            mid_axis: definition.mid_axis.normalize_or(glam::Vec3::Z),
+            soften: definition.soften,
+            twist_angle: definition.twist_angle,
+            weight: definition.weight.clamp(0.0, 1.0),
+        })
+    }
+}
+
+// A two-bone IK chain being played. It only applies while it has a target.
+pub struct TwoBoneIkChain {
+    // Model-space position the end joint should reach.
+    pub target: Option<glam::Vec3>,
+    // Model-space direction the mid joint should point towards, e.g. forward for a knee.
+    pub pole_vector: glam::Vec3,
+    layout: Shared<TwoBoneIkLayout>,
+    job: IKTwoBoneJob,
+}
+
+impl TwoBoneIkChain {
+    pub fn new(layout: Shared<TwoBoneIkLayout>) -> Self {
+        let mut job = IKTwoBoneJob::default();
+        job.set_mid_axis(layout.mid_axis.into());
+        job.set_soften(layout.soften);
+        job.set_twist_angle(layout.twist_angle);
+        job.set_weight(layout.weight);
+        TwoBoneIkChain {
+            target: None,
+            pole_vector: glam::Vec3::Y,
+            layout,
+            job,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.layout.name
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.job.weight()
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        self.job.set_weight(weight.clamp(0.0, 1.0));
+    }
+
+    // Whether the end joint reached the target on the last apply().
+    pub fn reached(&self) -> bool {
+        self.job.reached()
+    }
+
+    // The first joint the corrections move, from which model matrices have to be recomputed.
+    pub fn start_joint(&self) -> usize {
+        self.layout.start
+    }
+
+    // Rotates the start and mid joints of `locals` so that the end joint moves towards the target. Returns whether
+    // anything changed.
+    pub fn apply(&mut self, locals: &mut [SoaTransform], models: &[glam::Mat4]) -> Result<bool, anyhow::Error> {
+        let target = match self.target {
+            Some(val) if self.job.weight() > 0.0 => val,
+            _ => return Ok(false),
+        };
+        self.job.set_target(target.into());
+        self.job.set_pole_vector(self.pole_vector.into());
+        self.job.set_start_joint(models[self.layout.start]);
+        self.job.set_mid_joint(models[self.layout.mid]);
+        self.job.set_end_joint(models[self.layout.end]);
+        if let Err(e) = self.job.run() {
+            return Err(anyhow!("Ozz error during two-bone IK on {}: {}", self.layout.name, e));
+        }
+        multiply_rotation(locals, self.layout.start, self.job.start_joint_correction());
+        multiply_rotation(locals, self.layout.mid, self.job.mid_joint_correction());
+        Ok(true)
+    }
+}
+
+struct AimIkJoint {
+    joint: usize,
+    weight: f32,
+    max_angle: Option<f32>,
+}
+
+// An aim IK chain resolved against the skeleton, shared by every AnimGraphInstance.
+pub struct AimIkLayout {
+    pub name: String,
+    forward: glam::Vec3,
+    up: glam::Vec3,
+    weight: f32,
+    offset: glam::Vec3,
+    joints: Vec<AimIkJoint>,
+}
+
+impl AimIkLayout {
+    pub fn new(skeleton: &Skeleton, definition: &AimIkDefinition) -> Result<Self, anyhow::Error> {
+        let mut joints = Vec::<AimIkJoint>::new();
+        for joint in &definition.joints {
+            joints.push(AimIkJoint {
+                joint: joint_index(skeleton, &definition.name, &joint.joint_name)?,
+                weight: joint.weight.clamp(0.0, 1.0),
+                max_angle: joint.max_angle,
+            });
+        }
+        Ok(AimIkLayout {
+            name: definition.name.clone(),
+            forward: definition.forward.normalize_or(glam::Vec3::X),
+            up: definition.up.normalize_or(glam::Vec3::Y),
+            weight: definition.weight.clamp(0.0, 1.0),
+            offset: definition.offset,
+            joints,
+        })
+    }
+}
+
+// An aim IK chain being played. It only applies while it has a target.
+pub struct AimIkChain {
+    // Model-space position the chain should face.
+    pub target: Option<glam::Vec3>,
+    // Model-space direction the up axis should stay towards.
+    pub pole_vector: glam::Vec3,
+    weight: f32,
+    layout: Shared<AimIkLayout>,
+    job: IKAimJob,
+    reached: bool,
+}
+
+impl AimIkChain {
+    pub fn new(layout: Shared<AimIkLayout>) -> Self {
+        let mut job = IKAimJob::default();
+        job.set_forward(layout.forward.into());
+        job.set_up(layout.up.into());
+        AimIkChain {
+            target: None,
+            pole_vector: glam::Vec3::Y,
+            weight: layout.weight,
+            layout,
+            job,
+            reached: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.layout.name
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight.clamp(0.0, 1.0);
+    }
+
+    // Whether the target was in front of the offset on the last apply().
+    pub fn reached(&self) -> bool {
+        self.reached
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.target.is_some() && self.weight > 0.0
+    }
+
+    pub fn num_joints(&self) -> usize {
+        self.layout.joints.len()
+    }
+
+    pub fn joint(&self, i: usize) -> usize {
+        self.layout.joints[i].joint
+    }
+
+    // Turns the `i`th joint of the chain towards the target. The model matrices must be up to date with every joint
+    // before it, as each joint aims from wherever the previous ones left it.
+    pub fn apply_joint(
+        &mut self,
+        i: usize,
+        locals: &mut [SoaTransform],
+        models: &[glam::Mat4],
+    ) -> Result<(), anyhow::Error> {
+        let target = match self.target {
+            Some(val) => val,
+            None => return Ok(()),
+        };
+        let joint = &self.layout.joints[i];
+        let last = i + 1 == self.layout.joints.len();
+        // Earlier joints aim from their own origin, only the last one knows where the offset is.
+        let offset = if last { self.layout.offset } else { glam::Vec3::ZERO };
+        self.job.set_target(target.into());
+        self.job.set_pole_vector(self.pole_vector.into());
+        self.job.set_offset(offset.into());
+        self.job.set_weight(self.weight * joint.weight);
+        self.job.set_joint(models[joint.joint]);
+        if let Err(e) = self.job.run() {
+            return Err(anyhow!("Ozz error during aim IK on {}: {}", self.layout.name, e));
+        }
+        if last {
+            self.reached = self.job.reached();
+        }
+        let correction = match joint.max_angle {
+            Some(max_angle) => limit_angle(self.job.joint_correction(), max_angle),
+            None => self.job.joint_correction(),
+        };
+        multiply_rotation(locals, joint.joint, correction);
+        Ok(())
+    }
+}
+
+fn limit_angle(rotation: glam::Quat, max_angle: f32) -> glam::Quat {
+    let angle = rotation.angle_between(glam::Quat::IDENTITY);
+    if angle <= max_angle.max(0.0) {
+        return rotation;
+    }
+    glam::Quat::IDENTITY.slerp(rotation, max_angle.max(0.0) / angle)
+}
+
+fn joint_index(skeleton: &Skeleton, chain: &str, joint_name: &str) -> Result<usize, anyhow::Error> {
+    match skeleton.joint_by_name(joint_name) {
+        Some(val) => Ok(val as usize),
+        None => Err(anyhow!("Unknown joint {} in IK chain {}", joint_name, chain)),
+    }
+}
+
+// IK corrections are local-space rotations applied after the joint's own.
+fn multiply_rotation(locals: &mut [SoaTransform], joint: usize, correction: glam::Quat) {
+    let transform = &mut locals[joint / 4];
+    let lane = joint % 4;
+    let rotation = transform.rotation.quat(lane);
+    transform.rotation.set_quat(lane, rotation * correction);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ik_definitions::AimIkJointDefinition;
+    use crate::test_fixtures;
+
+    fn arm_ik(weight: f32) -> TwoBoneIkChain {
+        let definition = TwoBoneIkDefinition {
+            name: "arm".to_string(),
+            start_joint: "shoulder".to_string(),
+            mid_joint: "elbow".to_string(),
+            end_joint: "hand".to_string(),
+            mid_axis: glam::Vec3::Z,
+            soften: 1.0,
+            twist_angle: 0.0,
+            weight,
+        };
+        let layout = TwoBoneIkLayout::new(&test_fixtures::arm(), &definition).unwrap();
+        TwoBoneIkChain::new(Shared::new(layout))
+    }
+
+    fn model_matrices(skeleton: &Shared<Skeleton>, locals: &[SoaTransform]) -> Vec<glam::Mat4> {
+        let output = new_buffer(vec![glam::Mat4::IDENTITY; skeleton.num_joints()]);
+        let mut job = SharedLocalToModelJob::default();
+        job.set_skeleton(skeleton.clone());
+        job.set_input(new_buffer(locals.to_vec()));
+        job.set_output(output.clone());
+        job.run().unwrap();
+        output.read_buf().clone()
+    }
+
+    // Applies `chain` to the arm's rest pose and returns the hand's model-space position.
+    fn reach(chain: &mut TwoBoneIkChain) -> glam::Vec3 {
+        let skeleton = test_fixtures::arm();
+        let mut locals = skeleton.joint_rest_poses().to_vec();
+        let models = model_matrices(&skeleton, &locals);
+        chain.apply(&mut locals, &models).unwrap();
+        model_matrices(&skeleton, &locals)[3].w_axis.truncate()
+    }
+
+    #[test]
+    fn two_bone_ik_reaches_a_reachable_target() {
+        let mut chain = arm_ik(1.0);
+        let target = glam::Vec3::new(1.0, 1.0, 0.0);
+        chain.target = Some(target);
+        let hand = reach(&mut chain);
+        assert!(chain.reached());
+        assert!(hand.abs_diff_eq(target, 1e-3), "{}", hand);
+    }
+
+    #[test]
+    fn two_bone_ik_without_weight_leaves_the_pose_unchanged() {
+        let mut chain = arm_ik(0.0);
+        chain.target = Some(glam::Vec3::new(1.0, 1.0, 0.0));
+        let skeleton = test_fixtures::arm();
+        let mut locals = skeleton.joint_rest_poses().to_vec();
+        let models = model_matrices(&skeleton, &locals);
+        assert!(!chain.apply(&mut locals, &models).unwrap());
+        assert_eq!(locals, skeleton.joint_rest_poses());
+    }
+
+    #[test]
+    fn two_bone_ik_without_a_target_leaves_the_pose_unchanged() {
+        let mut chain = arm_ik(1.0);
+        assert!(reach(&mut chain).abs_diff_eq(glam::Vec3::new(2.0, 0.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn unknown_joints_are_an_error() {
+        let definition = TwoBoneIkDefinition {
+            name: "arm".to_string(),
+            start_joint: "shoulder".to_string(),
+            mid_joint: "knee".to_string(),
+            end_joint: "hand".to_string(),
+            mid_axis: glam::Vec3::Z,
+            soften: 1.0,
+            twist_angle: 0.0,
+            weight: 1.0,
+        };
+        let err = TwoBoneIkLayout::new(&test_fixtures::arm(), &definition).err().unwrap();
+        assert_eq!(err.to_string(), "Unknown joint knee in IK chain arm");
+    }
+
+    #[test]
+    fn limit_angle_clamps_larger_rotations_around_the_same_axis() {
+        let rotation = glam::Quat::from_rotation_z(90f32.to_radians());
+        let limited = limit_angle(rotation, 30f32.to_radians());
+        assert!(limited.abs_diff_eq(glam::Quat::from_rotation_z(30f32.to_radians()), 1e-5), "{}", limited);
+    }
+
+    #[test]
+    fn limit_angle_keeps_smaller_rotations() {
+        let rotation = glam::Quat::from_rotation_y(20f32.to_radians());
+        assert_eq!(limit_angle(rotation, 30f32.to_radians()), rotation);
+    }
+
+    #[test]
+    fn limit_angle_below_zero_allows_no_rotation() {
+        let rotation = glam::Quat::from_rotation_x(20f32.to_radians());
+        assert!(limit_angle(rotation, -1.0).abs_diff_eq(glam::Quat::IDENTITY, 1e-6));
+    }
+
+    #[test]
+    fn aim_ik_turns_no_further_than_the_joint_limit() {
+        let definition = AimIkDefinition {
+            name: "look".to_string(),
+            joints: vec![AimIkJointDefinition {
+                joint_name: "head".to_string(),
+                weight: 1.0,
+                max_angle: Some(30f32.to_radians()),
+            }],
+            forward: glam::Vec3::X,
+            up: glam::Vec3::Y,
+            offset: glam::Vec3::ZERO,
+            weight: 1.0,
+        };
+        let layout = AimIkLayout::new(&test_fixtures::arm(), &definition).unwrap();
+        let mut chain = AimIkChain::new(Shared::new(layout));
+        // Straight behind the head's forward axis, a quarter turn away.
+        chain.target = Some(glam::Vec3::new(0.0, 1.0, -1.0));
+        let skeleton = test_fixtures::arm();
+        let mut locals = skeleton.joint_rest_poses().to_vec();
+        let models = model_matrices(&skeleton, &locals);
+        chain.apply_joint(0, &mut locals, &models).unwrap();
+        let rotation = locals[1].rotation.quat(0);
+        assert!(rotation.abs_diff_eq(glam::Quat::from_rotation_y(30f32.to_radians()), 1e-4), "{}", rotation);
+    }
+}
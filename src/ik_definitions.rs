@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+// A three joint chain, e.g. thigh/calf/foot, bent so that its end reaches a target. The joints must be ancestors of
+// one another, but not necessarily direct ones.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TwoBoneIkDefinition {
+    pub name: String,
+    pub start_joint: String,
+    pub mid_joint: String,
+    pub end_joint: String,
+    // The axis the mid joint bends around, in the mid joint's local space.
+    #[serde(default = "default_mid_axis")]
+    pub mid_axis: glam::Vec3,
+    // From 0 to 1. Below 1, the chain stops stretching before it is fully straight, which avoids a snap as it
+    // reaches full extension.
+    #[serde(default = "default_soften")]
+    pub soften: f32,
+    // Rotation of the chain's plane around the start-to-target axis, in radians.
+    #[serde(default)]
+    pub twist_angle: f32,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_mid_axis() -> glam::Vec3 {
+    glam::Vec3::Z
+}
+
+fn default_soften() -> f32 {
+    1.0
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+// A chain of joints, e.g. spine/neck/head, turned one after the other so that the last one faces a target.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AimIkDefinition {
+    pub name: String,
+    // From the chain's base to the joint that should end up facing the target.
+    pub joints: Vec<AimIkJointDefinition>,
+    // The axis to aim, in each joint's local space.
+    #[serde(default = "default_forward")]
+    pub forward: glam::Vec3,
+    // The axis kept towards the pole vector, in each joint's local space.
+    #[serde(default = "default_up")]
+    pub up: glam::Vec3,
+    // Where the aiming starts from, in the last joint's local space, e.g. between the eyes.
+    #[serde(default)]
+    pub offset: glam::Vec3,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AimIkJointDefinition {
+    pub joint_name: String,
+    // The share of the remaining rotation this joint takes. The last joint usually takes it all, so that the chain
+    // ends up facing the target.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    // The most this joint turns away from its animated orientation, in radians.
+    #[serde(default)]
+    pub max_angle: Option<f32>,
+}
+
+fn default_forward() -> glam::Vec3 {
+    glam::Vec3::X
+}
+
+fn default_up() -> glam::Vec3 {
+    glam::Vec3::Y
+}
@@ -0,0 +1,354 @@
+use crate::edge_definitions::InertializationDecay;
+use ozz_animation_rs::SoaTransform;
+
+// Where (1 + y) * e^-y drops to 1%: scales a critically damped decay so that it has all but settled by the end of the
+// transition.
+const CRITICALLY_DAMPED_SETTLE: f32 = 6.638;
+
+// The last two poses a graph output, to measure where the character was and how fast it was moving when a
+// transition starts.
+pub struct PoseHistory {
+    last: Vec<SoaTransform>,
+    before_last: Vec<SoaTransform>,
+    last_dt: f32,
+    frames: usize,
+}
+
+impl PoseHistory {
+    pub fn new(num_soa_joints: usize) -> Self {
+        PoseHistory {
+            last: vec![SoaTransform::default(); num_soa_joints],
+            before_last: vec![SoaTransform::default(); num_soa_joints],
+            last_dt: 0.0,
+            frames: 0,
+        }
+    }
+
+    pub fn record(&mut self, pose: &[SoaTransform], dt: web_time::Duration) {
+        std::mem::swap(&mut self.last, &mut self.before_last);
+        self.last.copy_from_slice(pose);
+        self.last_dt = dt.as_secs_f32();
+        self.frames = (self.frames + 1).min(2);
+    }
+
+    pub fn last(&self) -> Option<&[SoaTransform]> {
+        match self.frames {
+            0 => None,
+            _ => Some(&self.last),
+        }
+    }
+
+    // The pose before the last one, with the time between the two.
+    pub fn before_last(&self) -> Option<(&[SoaTransform], f32)> {
+        match self.frames {
+            2 if self.last_dt > 0.0 => Some((&self.before_last, self.last_dt)),
+            _ => None,
+        }
+    }
+}
+
+// A vector offset decaying to zero along a fixed direction.
+#[derive(Clone, Copy)]
+struct DecayingOffset {
+    direction: glam::Vec3,
+    x0: f32,
+    v0: f32,
+}
+
+impl DecayingOffset {
+    const ZERO: DecayingOffset = DecayingOffset {
+        direction: glam::Vec3::ZERO,
+        x0: 0.0,
+        v0: 0.0,
+    };
+
+    // `offset` is where we are relative to the destination, `velocity` how fast that offset is changing.
+    fn new(offset: glam::Vec3, velocity: glam::Vec3) -> Self {
+        let x0 = offset.length();
+        if x0 <= f32::EPSILON {
+            return DecayingOffset::ZERO;
+        }
+        let direction = offset / x0;
+        DecayingOffset {
+            direction,
+            x0,
+            v0: velocity.dot(direction),
+        }
+    }
+
+    fn at(&self, decay: InertializationDecay, duration: f32, t: f32) -> glam::Vec3 {
+        if self.x0 == 0.0 {
+            return glam::Vec3::ZERO;
+        }
+        let x = match decay {
+            InertializationDecay::Quintic => quintic(self.x0, self.v0, duration, t),
+            InertializationDecay::CriticallyDamped => critically_damped(self.x0, self.v0, duration, t),
+        };
+        self.direction * x
+    }
+}
+
+// Bollo's quintic ("Inertialization: High-Performance Animation Transitions in Gears of War", GDC 2018). Reaches
+// zero with zero velocity and acceleration at the end of the duration.
+fn quintic(x0: f32, v0: f32, duration: f32, t: f32) -> f32 {
+    // Moving away from the destination would overshoot; so would closing in too fast for the duration.
+    let v0 = v0.min(0.0);
+    let duration = if v0 < 0.0 { duration.min(-5.0 * x0 / v0) } else { duration };
+    if duration <= 0.0 || t >= duration {
+        return 0.0;
+    }
+    let a0 = ((-8.0 * v0 * duration - 20.0 * x0) / (duration * duration)).max(0.0);
+    let t2 = duration * duration;
+    let a = -(a0 * t2 + 6.0 * v0 * duration + 12.0 * x0) / (2.0 * t2 * t2 * duration);
+    let b = (3.0 * a0 * t2 + 16.0 * v0 * duration + 30.0 * x0) / (2.0 * t2 * t2);
+    let c = -(3.0 * a0 * t2 + 12.0 * v0 * duration + 20.0 * x0) / (2.0 * t2 * duration);
+    (((((a * t + b) * t + c) * t + a0 * 0.5) * t + v0) * t) + x0
+}
+
+// A critically damped spring, stiff enough to have settled to within a percent by the end of the duration. That last
+// percent, and the speed it still closes in at, are faded out over the duration so that it arrives at zero, at rest.
+fn critically_damped(x0: f32, v0: f32, duration: f32, t: f32) -> f32 {
+    if duration <= 0.0 || t >= duration {
+        return 0.0;
+    }
+    let omega = CRITICALLY_DAMPED_SETTLE / duration;
+    let b = v0 + omega * x0;
+    let spring = |t: f32| (x0 + b * t) * (-omega * t).exp();
+    let velocity = |t: f32| (v0 - omega * b * t) * (-omega * t).exp();
+    // The spring's course around the end, taken away through a smoothstep, which leaves the start untouched.
+    let remainder = spring(duration) + velocity(duration) * (t - duration);
+    let s = t / duration;
+    spring(t) - remainder * s * s * (3.0 - 2.0 * s)
+}
+
+struct JointOffset {
+    translation: DecayingOffset,
+    // Angle around the direction, in radians.
+    rotation: DecayingOffset,
+    scale: DecayingOffset,
+}
+
+// Instead of cross-fading, records how far the pose we are leaving is from the destination's first pose and lets that
+// difference die out, so only the destination needs evaluating.
+pub struct Inertialization {
+    pub decay: InertializationDecay,
+    num_joints: usize,
+    offsets: Vec<JointOffset>,
+    captured: bool,
+}
+
+impl Inertialization {
+    pub fn new(decay: InertializationDecay, num_joints: usize) -> Self {
+        Inertialization {
+            decay,
+            num_joints,
+            offsets: Vec::new(),
+            captured: false,
+        }
+    }
+
+    pub fn is_captured(&self) -> bool {
+        self.captured
+    }
+
+    // Measures the offsets between the last output pose and `destination`. Without any history there is nothing to
+    // decay from, and the destination plays as is.
+    pub fn capture(&mut self, history: &PoseHistory, destination: &[SoaTransform]) {
+        self.offsets.clear();
+        self.captured = true;
+        let source = match history.last() {
+            Some(val) => val,
+            None => return,
+        };
+        let before = history.before_last();
+        // Only real joints; the padding lanes of the last SoA transform aren't guaranteed to hold valid rotations.
+        for joint in 0..self.num_joints {
+            let (soa, lane) = (joint / 4, joint % 4);
+            let (t, r, s) = lane_of(&source[soa], lane);
+            let (dt, dr, ds) = lane_of(&destination[soa], lane);
+
+            let rotation_offset = shortest(r * dr.inverse());
+            let (axis, angle) = rotation_offset.to_axis_angle();
+
+            let (translation_velocity, angular_velocity, scale_velocity) = match before {
+                Some((previous, elapsed)) => {
+                    let (pt, pr, ps) = lane_of(&previous[soa], lane);
+                    let previous_rotation_offset = shortest(pr * dr.inverse());
+                    let previous_angle = 2.0
+                        * glam::Vec3::new(
+                            previous_rotation_offset.x,
+                            previous_rotation_offset.y,
+                            previous_rotation_offset.z,
+                        )
+                        .dot(axis)
+                        .atan2(previous_rotation_offset.w);
+                    ((t - pt) / elapsed, (angle - previous_angle) / elapsed, (s - ps) / elapsed)
+                }
+                None => (glam::Vec3::ZERO, 0.0, glam::Vec3::ZERO),
+            };
+
+            self.offsets.push(JointOffset {
+                translation: DecayingOffset::new(t - dt, translation_velocity),
+                rotation: DecayingOffset::new(axis * angle, axis * angular_velocity),
+                scale: DecayingOffset::new(s - ds, scale_velocity),
+            });
+        }
+    }
+
+    // Writes `destination` plus the offsets as they stand `elapsed` seconds into a transition lasting `duration`.
+    pub fn apply(&self, destination: &[SoaTransform], output: &mut [SoaTransform], duration: f32, elapsed: f32) {
+        output.copy_from_slice(destination);
+        if self.offsets.is_empty() {
+            return;
+        }
+        for (joint, offset) in self.offsets.iter().enumerate() {
+            let (soa, lane) = (joint / 4, joint % 4);
+            let transform = &mut output[soa];
+            let (t, r, s) = lane_of(transform, lane);
+            let translation = t + offset.translation.at(self.decay, duration, elapsed);
+            let rotation_vector = offset.rotation.at(self.decay, duration, elapsed);
+            let rotation = if rotation_vector.length_squared() > f32::EPSILON {
+                let angle = rotation_vector.length();
+                (glam::Quat::from_axis_angle(rotation_vector / angle, angle) * r).normalize()
+            } else {
+                r
+            };
+            let scale = s + offset.scale.at(self.decay, duration, elapsed);
+            transform.translation.set_vec3(lane, translation);
+            transform.rotation.set_quat(lane, rotation);
+            transform.scale.set_vec3(lane, scale);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.offsets.clear();
+        self.captured = false;
+    }
+}
+
+fn lane_of(transform: &SoaTransform, lane: usize) -> (glam::Vec3, glam::Quat, glam::Vec3) {
+    (
+        transform.translation.vec3(lane),
+        transform.rotation.quat(lane),
+        transform.scale.vec3(lane),
+    )
+}
+
+// The same rotation, going the short way round.
+fn shortest(rotation: glam::Quat) -> glam::Quat {
+    if rotation.w < 0.0 {
+        -rotation
+    } else {
+        rotation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The slope of `f` at `t`, measured over a small step.
+    fn slope(f: impl Fn(f32) -> f32, t: f32) -> f32 {
+        let h = 1e-3;
+        (f(t + h) - f(t - h)) / (2.0 * h)
+    }
+
+    #[test]
+    fn quintic_starts_at_the_offset_and_its_velocity() {
+        for (x0, v0) in [(1.0, 0.0), (1.0, -2.0), (0.3, -0.5), (2.0, -1.0)] {
+            assert!((quintic(x0, v0, 0.5, 0.0) - x0).abs() < 1e-5);
+            let f = |t: f32| quintic(x0, v0, 0.5, t);
+            assert!((slope(f, 0.01) - v0).abs() < 0.3, "started at {} rather than {}", slope(f, 0.01), v0);
+        }
+    }
+
+    #[test]
+    fn quintic_settles_at_the_end_of_the_duration() {
+        for (x0, v0) in [(1.0, 0.0), (1.0, -2.0), (0.3, -0.5)] {
+            let f = |t: f32| quintic(x0, v0, 0.5, t);
+            assert_eq!(f(0.5), 0.0);
+            assert_eq!(f(1.0), 0.0);
+            // Zero velocity and acceleration as well, so that there is no kink on arrival: what is left shrinks with the
+            // cube of the time left.
+            assert!(f(0.49).abs() < 1e-3 && slope(f, 0.49).abs() < 0.1);
+            assert!((f(0.495) / f(0.49) - 0.125).abs() < 0.02, "{}", f(0.495) / f(0.49));
+        }
+    }
+
+    #[test]
+    fn quintic_never_overshoots() {
+        for (x0, v0) in [(1.0, 0.0), (1.0, 3.0), (1.0, -2.0), (1.0, -50.0), (0.01, -1.0)] {
+            let mut previous = x0;
+            for step in 0..=100 {
+                let x = quintic(x0, v0, 0.5, step as f32 * 0.005);
+                assert!(x >= -1e-5, "({}, {}) went past the destination: {}", x0, v0, x);
+                assert!(x <= previous + 1e-5, "({}, {}) moved away from the destination", x0, v0);
+                previous = x;
+            }
+        }
+    }
+
+    #[test]
+    fn quintic_shortens_the_decay_when_closing_in_fast() {
+        // Closing a 1m offset at 50m/s has to be over within 5 * 1 / 50 = 0.1s.
+        assert_eq!(quintic(1.0, -50.0, 0.5, 0.1), 0.0);
+        assert!(quintic(1.0, -50.0, 0.5, 0.05) > 0.0);
+    }
+
+    #[test]
+    fn quintic_ignores_velocity_away_from_the_destination() {
+        for t in [0.0, 0.1, 0.25, 0.4] {
+            assert_eq!(quintic(1.0, 3.0, 0.5, t), quintic(1.0, 0.0, 0.5, t));
+        }
+    }
+
+    #[test]
+    fn critically_damped_keeps_its_start() {
+        for (x0, v0) in [(1.0, 0.0), (1.0, -2.0), (0.3, 1.0)] {
+            let f = |t: f32| critically_damped(x0, v0, 0.5, t);
+            assert_eq!(f(0.0), x0);
+            let start_velocity = (f(1e-4) - f(0.0)) / 1e-4;
+            assert!((start_velocity - v0).abs() < 0.05, "{} != {}", start_velocity, v0);
+        }
+    }
+
+    #[test]
+    fn critically_damped_arrives_without_a_jump() {
+        for (x0, v0) in [(1.0, 0.0), (1.0, -2.0), (0.3, 1.0)] {
+            let f = |t: f32| critically_damped(x0, v0, 0.5, t);
+            assert_eq!(f(0.5), 0.0);
+            assert_eq!(f(1.0), 0.0);
+            // What is left just before the end is next to nothing, and no longer moving.
+            assert!(f(0.499).abs() < 1e-5, "{}", f(0.499));
+            assert!(slope(f, 0.498).abs() < 0.01, "{}", slope(f, 0.498));
+            // Nor does it jump anywhere on the way.
+            for step in 1..=500 {
+                let t = step as f32 * 0.001;
+                assert!((f(t) - f(t - 0.001)).abs() < 0.02, "({}, {}) jumped at {}", x0, v0, t);
+            }
+        }
+    }
+
+    #[test]
+    fn critically_damped_stays_on_its_side_of_the_destination() {
+        let mut previous = 1.0;
+        for step in 0..=100 {
+            let x = critically_damped(1.0, 0.0, 0.5, step as f32 * 0.005);
+            assert!(x >= 0.0, "went past the destination: {}", x);
+            assert!(x <= previous, "moved away from the destination");
+            previous = x;
+        }
+    }
+
+    #[test]
+    fn offsets_decay_along_their_direction() {
+        let offset = DecayingOffset::new(glam::Vec3::new(0.0, 3.0, 4.0), glam::Vec3::ZERO);
+        let start = offset.at(InertializationDecay::Quintic, 0.5, 0.0);
+        assert!(start.abs_diff_eq(glam::Vec3::new(0.0, 3.0, 4.0), 1e-5));
+        let halfway = offset.at(InertializationDecay::Quintic, 0.5, 0.25);
+        assert!(halfway.normalize().abs_diff_eq(glam::Vec3::new(0.0, 0.6, 0.8), 1e-5));
+        assert!(halfway.length() < 5.0);
+        let none = DecayingOffset::new(glam::Vec3::ZERO, glam::Vec3::ONE);
+        assert_eq!(none.at(InertializationDecay::Quintic, 0.5, 0.0), glam::Vec3::ZERO);
+    }
+}
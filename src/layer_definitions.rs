@@ -0,0 +1,37 @@
+use crate::animgraph_definition::DefinitionGraph;
+use mapgraph::map::slotmap::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+// A state machine playing on top of the base graph, overriding or adding to the joints of its mask.
+pub struct LayerDefinition {
+    pub name: String,
+    pub graph: DefinitionGraph,
+    pub root: Option<NodeIndex>,
+    // From 0 (no effect) to 1 (fully overrides the masked joints).
+    pub weight: f32,
+    // Which joints the layer affects. An empty mask affects the whole skeleton.
+    pub mask: Vec<JointMaskDefinition>,
+    pub blend_mode: LayerBlendMode,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum LayerBlendMode {
+    // Replaces the pose below it.
+    #[default]
+    Override,
+    // Adds its additive nodes' deltas to the pose below it. Additive layers apply after every override layer,
+    // whatever their order.
+    Additive,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct JointMaskDefinition {
+    pub joint_name: String,
+    // Whether the joint's descendants are part of the mask too, e.g. "spine_01" for the whole upper body.
+    #[serde(default = "default_include_children")]
+    pub include_children: bool,
+}
+
+fn default_include_children() -> bool {
+    true
+}
@@ -0,0 +1,239 @@
+use crate::layer_definitions::{JointMaskDefinition, LayerBlendMode};
+use crate::shared::*;
+use crate::state_machine::{StateMachine, StateMachineLayout};
+use anyhow::anyhow;
+use ozz_animation_rs::*;
+
+// The parts of a layer that don't change as it plays, shared by every AnimGraphInstance.
+pub struct LayerLayout {
+    pub name: String,
+    // The weight each instance starts with.
+    pub weight: f32,
+    pub blend_mode: LayerBlendMode,
+    // How much the layer affects each joint, from 0 to 1.
+    pub mask: Vec<f32>,
+    pub state_machine: Shared<StateMachineLayout>,
+}
+
+pub struct AnimationLayer {
+    pub layout: Shared<LayerLayout>,
+    pub weight: f32,
+    pub state_machine: StateMachine,
+    // This frame's per-joint weights in the blend job, kept to avoid allocating every frame.
+    effective_weights: Vec<f32>,
+}
+
+impl AnimationLayer {
+    pub fn new(skeleton: Shared<Skeleton>, layout: Shared<LayerLayout>) -> Self {
+        AnimationLayer {
+            weight: layout.weight,
+            state_machine: StateMachine::new(skeleton, layout.state_machine.clone()),
+            effective_weights: vec![0.0; layout.mask.len()],
+            layout,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.layout.name
+    }
+
+    pub fn blend_mode(&self) -> LayerBlendMode {
+        self.layout.blend_mode
+    }
+}
+
+// An empty mask covers the whole skeleton.
+pub fn build_joint_mask(skeleton: &Skeleton, mask: &[JointMaskDefinition]) -> Result<Vec<f32>, anyhow::Error> {
+    if mask.is_empty() {
+        return Ok(vec![1.0; skeleton.num_joints()]);
+    }
+    let mut weights = vec![0.0; skeleton.num_joints()];
+    for joint_mask in mask {
+        let joint = match skeleton.joint_by_name(&joint_mask.joint_name) {
+            Some(val) => val,
+            None => return Err(anyhow!("Unknown joint in layer mask: {}", joint_mask.joint_name)),
+        };
+        if joint_mask.include_children {
+            // The walk covers every joint after `joint` in depth-first order, so stop at the first one that isn't a
+            // descendant.
+            let mut in_subtree = vec![false; skeleton.num_joints()];
+            skeleton.iter_depth_first(joint, |current, parent| {
+                if current == joint || (parent >= 0 && in_subtree[parent as usize]) {
+                    in_subtree[current as usize] = true;
+                }
+            });
+            for (weight, included) in weights.iter_mut().zip(in_subtree) {
+                if included {
+                    *weight = 1.0;
+                }
+            }
+        } else {
+            weights[joint as usize] = 1.0;
+        }
+    }
+    Ok(weights)
+}
+
+// Points the blend job at this frame's outputs and sets its weights. Override layers stack over the base: each takes
+// its share of whatever the layers above it left over, and the base gets the rest. Additive layers are weighted
+// independently. The job holds the base then one layer per override layer, and one additive layer per additive
+// layer, both in order.
+pub fn update_layer_blend(
+    base_output: Buffer<SoaTransform>,
+    layers: &mut [AnimationLayer],
+    blend_job: &mut SharedBlendingJob,
+    num_joints: usize,
+) {
+    let mut remaining = vec![1.0f32; num_joints];
+    let num_override = layers.iter().filter(|l| l.blend_mode() == LayerBlendMode::Override).count();
+    let mut override_idx = num_override;
+    let mut additive_idx = layers.len() - num_override;
+    for layer in layers.iter_mut().rev() {
+        let weight = layer.weight.clamp(0.0, 1.0);
+        match layer.blend_mode() {
+            LayerBlendMode::Override => {
+                let effective = &mut layer.effective_weights;
+                for ((effective, remaining), mask) in effective.iter_mut().zip(remaining.iter_mut()).zip(&layer.layout.mask)
+                {
+                    *effective = weight * mask * *remaining;
+                    *remaining -= *effective;
+                }
+                let blend_layer = &mut blend_job.layers_mut()[override_idx];
+                blend_layer.transform = layer.state_machine.output();
+                write_joint_weights(blend_layer, effective);
+                override_idx -= 1;
+            }
+            LayerBlendMode::Additive => {
+                additive_idx -= 1;
+                let blend_layer = &mut blend_job.additive_layers_mut()[additive_idx];
+                blend_layer.transform = layer.state_machine.output();
+                write_joint_weights(blend_layer, &layer.layout.mask);
+                blend_layer.weight = weight;
+            }
+        }
+    }
+    let base_layer = &mut blend_job.layers_mut()[0];
+    base_layer.transform = base_output;
+    write_joint_weights(base_layer, &remaining);
+}
+fn write_joint_weights(blend_layer: &mut BlendingLayer<Buffer<SoaTransform>>, weights: &[f32]) {
+    blend_layer.weight = 1.0;
+    for (soa_joint, joint_weight) in blend_layer.joint_weights.iter_mut().enumerate() {
+        let lane = |i: usize| weights.get(soa_joint * 4 + i).copied().unwrap_or(0.0);
+        *joint_weight = glam::Vec4::new(lane(0), lane(1), lane(2), lane(3));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animgraph::AnimGraph;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::test_fixtures;
+
+    fn mask(entries: &[(&str, bool)]) -> Result<Vec<f32>, anyhow::Error> {
+        let mask: Vec<JointMaskDefinition> = entries
+            .iter()
+            .map(|(joint_name, include_children)| JointMaskDefinition {
+                joint_name: joint_name.to_string(),
+                include_children: *include_children,
+            })
+            .collect();
+        build_joint_mask(&test_fixtures::arm(), &mask)
+    }
+
+    #[test]
+    fn an_empty_mask_covers_every_joint() {
+        assert_eq!(mask(&[]).unwrap(), vec![1.0; 5]);
+    }
+
+    #[test]
+    fn a_mask_covers_a_joint_and_its_subtree() {
+        // root -> shoulder -> elbow -> hand, and root -> head.
+        assert_eq!(mask(&[("elbow", true)]).unwrap(), vec![0.0, 0.0, 1.0, 1.0, 0.0]);
+        assert_eq!(mask(&[("shoulder", false)]).unwrap(), vec![0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(mask(&[("hand", true), ("head", false)]).unwrap(), vec![0.0, 0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(mask(&[("root", true)]).unwrap(), vec![1.0; 5]);
+    }
+
+    #[test]
+    fn unknown_mask_joints_are_an_error() {
+        let err = mask(&[("elbow", true), ("tail", true)]).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown joint in layer mask: tail");
+    }
+
+    // The base plays the fixture clip, so after `seconds` its root is at x = `seconds`. Each layer plays it faster.
+    fn root_x(builder: AnimGraphDefinitionBuilder, seconds: f32) -> f32 {
+        let mut graph = test_fixtures::graph(&builder.build().unwrap());
+        test_fixtures::step(&mut graph, seconds);
+        root_x_of(&mut graph)
+    }
+
+    fn root_x_of(graph: &mut AnimGraph) -> f32 {
+        graph.get_soa_transforms().read_buf()[0].translation.vec3(0).x
+    }
+
+    fn base() -> AnimGraphDefinitionBuilder {
+        AnimGraphDefinitionBuilder::new().sampler("idle", "a").looping(true).root("idle")
+    }
+
+    fn override_layer(
+        builder: AnimGraphDefinitionBuilder,
+        name: &str,
+        speed: f32,
+        weight: f32,
+    ) -> AnimGraphDefinitionBuilder {
+        builder
+            .layer(name)
+            .layer_weight(weight)
+            .root(name)
+            .sampler(name, "b")
+            .speed(speed)
+            .looping(true)
+    }
+
+    fn assert_near(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1e-3, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn an_override_layer_blends_by_its_weight() {
+        assert_near(root_x(base(), 0.25), 0.25);
+        assert_near(root_x(override_layer(base(), "wave", 2.0, 1.0), 0.25), 0.5);
+        assert_near(root_x(override_layer(base(), "wave", 2.0, 0.5), 0.25), 0.375);
+        assert_near(root_x(override_layer(base(), "wave", 2.0, 0.0), 0.25), 0.25);
+    }
+
+    #[test]
+    fn later_override_layers_take_their_share_first() {
+        let builder = override_layer(override_layer(base(), "lower", 2.0, 1.0), "upper", 3.0, 0.5);
+        // upper takes half, lower all of what is left, and the base nothing.
+        assert_near(root_x(builder, 0.25), 0.5 * 0.75 + 0.5 * 0.5);
+    }
+
+    #[test]
+    fn layer_weights_change_at_runtime() {
+        let mut graph = test_fixtures::graph(&override_layer(base(), "wave", 2.0, 1.0).build().unwrap());
+        graph.set_layer_weight("wave".to_string(), 0.5).unwrap();
+        test_fixtures::step(&mut graph, 0.25);
+        assert_near(root_x_of(&mut graph), 0.375);
+        assert_eq!(graph.get_layer_weight("wave".to_string()), Some(0.5));
+        assert!(graph.set_layer_weight("legs".to_string(), 0.5).is_err());
+    }
+
+    #[test]
+    fn additive_layers_add_their_weighted_delta() {
+        let additive = |weight: f32| {
+            base()
+                .layer("lean")
+                .blend_mode(LayerBlendMode::Additive)
+                .layer_weight(weight)
+                .root("lean")
+                .additive("lean", "b")
+                .looping(true)
+        };
+        assert_near(root_x(additive(1.0), 0.25), 0.5);
+        assert_near(root_x(additive(0.5), 0.25), 0.375);
+        assert_near(root_x(additive(0.0), 0.25), 0.25);
+    }
+}
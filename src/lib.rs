@@ -1,6 +1,35 @@
+// The containers generated by safe_index::new! trip these lints; there is nothing to fix on our side.
+#![allow(clippy::len_without_is_empty, clippy::implied_bounds_in_impls)]
+
 pub mod animgraph;
+pub mod compiled_animgraph;
 pub mod nodes;
 pub mod animgraph_definition;
 pub mod node_definitions;
 pub mod edges;
-pub mod edge_definitions;
\ No newline at end of file
+pub mod edge_definitions;
+pub mod events;
+pub mod ik;
+pub mod ik_definitions;
+pub mod event_definitions;
+pub mod inertialization;
+pub mod layers;
+pub mod layer_definitions;
+pub mod parameters;
+pub mod parameter_definitions;
+pub mod conditions;
+pub mod condition_definitions;
+pub mod curves;
+pub mod root_motion;
+pub mod root_motion_definitions;
+pub mod routing;
+pub mod shared;
+pub mod state_machine;
+pub mod validation;
+pub mod definition_file;
+pub mod builder;
+pub mod export;
+
+#[cfg(test)]
+mod test_fixtures;
+
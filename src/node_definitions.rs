@@ -1,18 +1,162 @@
+use crate::edge_definitions::{AnyStateTransitionDefinition, NamedTransitionDefinition};
+use crate::event_definitions::AnimationEventDefinition;
+use serde::{Deserialize, Serialize};
 
-
+#[derive(Clone, Serialize, Deserialize)]
 pub enum GenericNodeDefinition {
     Sampler(SamplerNodeDefinition),
-    BlendTreeOneDim(BlendTreeOneDimDefinition)
+    BlendTreeOneDim(BlendTreeOneDimDefinition),
+    BlendSpaceTwoDim(BlendSpaceTwoDimDefinition),
+    Additive(AdditiveNodeDefinition),
+    StateMachine(StateMachineDefinition),
+}
+
+impl GenericNodeDefinition {
+    pub fn name(&self) -> &str {
+        match self {
+            GenericNodeDefinition::Sampler(val) => &val.name,
+            GenericNodeDefinition::BlendTreeOneDim(val) => &val.name,
+            GenericNodeDefinition::BlendSpaceTwoDim(val) => &val.name,
+            GenericNodeDefinition::Additive(val) => &val.name,
+            GenericNodeDefinition::StateMachine(val) => &val.name,
+        }
+    }
+
+    pub fn animation_names(&self) -> Vec<&str> {
+        match self {
+            GenericNodeDefinition::Sampler(val) => vec![val.animation_name.as_str()],
+            GenericNodeDefinition::BlendTreeOneDim(val) => {
+                val.clips.iter().map(|c| c.animation_name.as_str()).collect()
+            }
+            GenericNodeDefinition::BlendSpaceTwoDim(val) => {
+                val.clips.iter().map(|c| c.animation_name.as_str()).collect()
+            }
+            GenericNodeDefinition::Additive(val) => match &val.reference {
+                AdditiveReference::FirstFrame => vec![val.animation_name.as_str()],
+                AdditiveReference::Animation(reference) => vec![val.animation_name.as_str(), reference.as_str()],
+            },
+            GenericNodeDefinition::StateMachine(val) => {
+                val.nodes.iter().flat_map(|n| n.animation_names()).collect()
+            }
+        }
+    }
+
+    // Every event marker of the node, across all of its clips.
+    pub fn events(&self) -> Vec<&AnimationEventDefinition> {
+        match self {
+            GenericNodeDefinition::Sampler(val) => val.events.iter().collect(),
+            GenericNodeDefinition::BlendTreeOneDim(val) => val.clips.iter().flat_map(|c| c.events.iter()).collect(),
+            GenericNodeDefinition::BlendSpaceTwoDim(val) => val.clips.iter().flat_map(|c| c.events.iter()).collect(),
+            GenericNodeDefinition::Additive(val) => val.events.iter().collect(),
+            GenericNodeDefinition::StateMachine(val) => val.nodes.iter().flat_map(|n| n.events()).collect(),
+        }
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SamplerNodeDefinition {
+    #[serde(default = "default_speed")]
     pub speed: f32,
     pub animation_name: String,
+    #[serde(default)]
     pub looping: bool,
     pub name: String,
+    #[serde(default)]
+    pub events: Vec<AnimationEventDefinition>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BlendTreeOneDimDefinition {
-    animation_names: Vec<String>,
-    name: String
-}
\ No newline at end of file
+    pub clips: Vec<BlendTreeOneDimClipDefinition>,
+    pub name: String,
+    // Plays every clip at a shared normalized phase. Turn off for non-cyclic content.
+    #[serde(default = "default_sync_phase")]
+    pub sync_phase: bool,
+    // Float (or int) graph parameter driving the blend, if any.
+    #[serde(default)]
+    pub param_name: Option<String>,
+}
+
+// One animation of a 1D blend tree, placed at `threshold` on the parameter axis.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlendTreeOneDimClipDefinition {
+    pub animation_name: String,
+    pub threshold: f32,
+    #[serde(default)]
+    pub events: Vec<AnimationEventDefinition>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlendSpaceTwoDimDefinition {
+    pub clips: Vec<BlendSpaceTwoDimClipDefinition>,
+    pub name: String,
+    pub mode: BlendSpaceTwoDimMode,
+    #[serde(default = "default_sync_phase")]
+    pub sync_phase: bool,
+    // Vec2 graph parameter driving the blend, if any.
+    #[serde(default)]
+    pub param_name: Option<String>,
+}
+
+// One animation of a 2D blend space, placed at `position` in parameter space.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlendSpaceTwoDimClipDefinition {
+    pub animation_name: String,
+    pub position: glam::Vec2,
+    #[serde(default)]
+    pub events: Vec<AnimationEventDefinition>,
+}
+
+// A nested graph of states, entered at `entry`. The transitions leaving the node in the parent graph only fire on
+// their own once the nested machine rests on one of its `exits`, or on any state if there are none. Routing leaves
+// it straight away.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StateMachineDefinition {
+    pub name: String,
+    pub entry: String,
+    pub nodes: Vec<GenericNodeDefinition>,
+    #[serde(default)]
+    pub transitions: Vec<NamedTransitionDefinition>,
+    #[serde(default)]
+    pub exits: Vec<String>,
+    #[serde(default)]
+    pub any_state_transitions: Vec<AnyStateTransitionDefinition>,
+}
+
+// Plays a clip as the difference from a reference pose. Only valid in additive layers.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AdditiveNodeDefinition {
+    pub animation_name: String,
+    #[serde(default)]
+    pub reference: AdditiveReference,
+    #[serde(default)]
+    pub looping: bool,
+    pub name: String,
+    #[serde(default)]
+    pub events: Vec<AnimationEventDefinition>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum AdditiveReference {
+    // The clip's own first frame, e.g. for a breathing loop authored from a neutral pose.
+    #[default]
+    FirstFrame,
+    // The first frame of another animation, e.g. the center pose of a set of aim offsets.
+    Animation(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum BlendSpaceTwoDimMode {
+    // Gradient band interpolation on plain x/y distances. Suits blend spaces like speed/slope.
+    Cartesian,
+    // Gradient band interpolation on magnitude and angle. Suits directional movement laid out around the origin.
+    Directional,
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+fn default_sync_phase() -> bool {
+    true
+}
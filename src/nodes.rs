@@ -1,6 +1,9 @@
+use crate::events::{AnimationEvent, EventTrack};
+use crate::node_definitions::BlendSpaceTwoDimMode;
+use crate::parameters::ParameterIndex;
+use crate::root_motion::*;
+use crate::shared::*;
 use ozz_animation_rs::*;
-use std::cell::RefCell;
-use std::rc::Rc;
 
 
 
@@ -14,104 +17,913 @@ BlendTreeOneDimNodeIndex,
 map: BlendTreeOneDimNodesContainer
 }
 
+safe_index::new! {
+BlendSpaceTwoDimNodeIndex,
+map: BlendSpaceTwoDimNodesContainer
+}
+
+safe_index::new! {
+AdditiveNodeIndex,
+map: AdditiveNodesContainer
+}
+
+safe_index::new! {
+StateMachineNodeIndex,
+map: StateMachineNodesContainer
+}
+
 pub enum GenericNode {
     Sampler(SamplerNodeIndex),
-    BlendTreeOneDim(BlendTreeOneDimNodeIndex)
+    BlendTreeOneDim(BlendTreeOneDimNodeIndex),
+    BlendSpaceTwoDim(BlendSpaceTwoDimNodeIndex),
+    Additive(AdditiveNodeIndex),
+    StateMachine(StateMachineNodeIndex),
+}
+// A clip resolved for playback, shared by every instance of the node playing it.
+pub struct ClipLayout {
+    pub animation: Shared<Animation>,
+    pub events: EventTrack,
+    // Only set when the graph extracts root motion.
+    pub root_motion: Option<RootMotionClip>,
+}
+
+impl ClipLayout {
+    pub fn new(
+        skeleton: &Skeleton,
+        animation: Shared<Animation>,
+        events: EventTrack,
+        root_motion: Option<RootMotionSettings>,
+    ) -> Self {
+        let root_motion = root_motion.map(|settings| {
+            let start = sample_pose(skeleton, &animation, 0.0);
+            let end = sample_pose(skeleton, &animation, 1.0);
+            RootMotionClip::new(settings, &start, &end)
+        });
+        ClipLayout {
+            animation,
+            events,
+            root_motion,
+        }
+    }
 }
+
+#[derive(Clone)]
+pub struct SamplerLayout {
+    pub clip: Shared<ClipLayout>,
+    pub looping: bool,
+    pub speed: f32,
+}
+
 pub struct SamplerNode {
-    pub output: Rc<RefCell<Vec<SoaTransform>>>,
+    pub output: Buffer<SoaTransform>,
     pub speed: f32,
-    pub sample_job: ozz_animation_rs::SamplingJobRc,
+    // The definition's speed, which reset() goes back to.
+    default_speed: f32,
+    pub sample_job: SharedSamplingJob,
     pub seek: f32,
     pub looping: bool,
     pub finished: bool,
+    previous_seek: f32,
+    // Events crossed during the last update.
+    fired_events: Vec<AnimationEvent>,
+    started: bool,
+    clip: Shared<ClipLayout>,
+    root_motion: Option<RootMotionTrack>,
 }
 
 impl SamplerNode {
-    pub fn new(skeleton: Rc<Skeleton>, animation: Rc<Animation>, looping: bool) -> Self {
-        let mut sample_job = ozz_animation_rs::SamplingJob::default();
+    pub fn new(skeleton: Shared<Skeleton>, layout: &SamplerLayout) -> Self {
+        let animation = &layout.clip.animation;
+        let mut sample_job = SharedSamplingJob::default();
         sample_job.set_animation(animation.clone());
 
         sample_job.set_context(SamplingContext::new(animation.num_tracks()));
 
-        let output = Rc::new(RefCell::new(vec![
+        let output = new_buffer(vec![
             SoaTransform::default();
             skeleton.num_soa_joints()
-        ]));
+        ]);
 
         sample_job.set_output(output.clone());
 
         SamplerNode {
             output,
-            speed: 1.0,
+            speed: layout.speed,
+            default_speed: layout.speed,
             sample_job,
             seek: 0.0,
-            looping,
+            looping: layout.looping,
             finished: false,
+            previous_seek: 0.0,
+            fired_events: Vec::new(),
+            started: false,
+            clip: layout.clip.clone(),
+            root_motion: layout.clip.root_motion.map(RootMotionTrack::new),
+        }
+    }
+
+    // The root motion of the last update.
+    pub fn root_motion(&self) -> RootMotion {
+        match &self.root_motion {
+            Some(val) => val.delta,
+            None => NO_ROOT_MOTION,
         }
     }
 
     pub fn update(&mut self, dt: web_time::Duration) {
-        let duration = self.sample_job.animation().unwrap().duration();
+        let duration = self.clip.animation.duration();
+        self.previous_seek = self.seek;
         self.seek += dt.as_secs_f32() * self.speed;
         if self.looping && !self.finished {
             self.seek %= duration;
-        } else {
-            if self.seek > duration {
-                self.seek = 0.0;
-                self.finished = true;
-            }
+        } else if self.seek >= duration {
+            // One-shot clips hold their last frame once done.
+            self.seek = duration;
+            self.finished = true;
         }
         let ratio = self.seek / duration;
         self.sample_job.set_ratio(ratio);
         self.sample_job.run().unwrap();
+
+        if let Some(track) = &mut self.root_motion {
+            // Only looping clips go backwards.
+            let wrapped = self.started && self.seek < self.previous_seek;
+            track.extract(&mut self.output.write_buf(), wrapped);
+        }
+
+        self.fired_events.clear();
+        if !self.clip.events.is_empty() {
+            // Markers right at the start only fire on entry if we pretend to have come from before it.
+            let previous = if self.started { self.previous_seek / duration } else { -1.0 };
+            self.clip.events.collect(previous, ratio, 1.0, &mut self.fired_events);
+        }
+        self.started = true;
+    }
+
+    pub fn normalized_time(&self) -> f32 {
+        self.seek / self.clip.animation.duration()
+    }
+
+    // Whether the last update reached `exit_time` (normalized), or finished a one-shot clip.
+    pub fn exit_time_reached(&self, exit_time: f32) -> bool {
+        if self.finished {
+            return true;
+        }
+        let duration = self.clip.animation.duration();
+        crossed_normalized_time(self.previous_seek / duration, self.seek / duration, exit_time)
+    }
+
+    pub fn fired_events(&self) -> &[AnimationEvent] {
+        &self.fired_events
     }
 
     pub fn reset(&mut self) {
         self.finished = false;
         self.seek = 0.0;
-        self.speed = 1.0;
+        self.previous_seek = 0.0;
+        self.started = false;
+        self.fired_events.clear();
+        if let Some(track) = &mut self.root_motion {
+            track.reset();
+        }
+        self.speed = self.default_speed;
     }
 }
 
-pub struct BlendTreeOneDimNode {
-    pub output: Rc<RefCell<Vec<SoaTransform>>>,
-    pub playback_speed: f32,
-    pub param: f32,
-    blend_job: BlendingJobRc,
-    sample_jobs: Vec<SamplingJobRc>,
+// The sampling and blending shared by blend trees and blend spaces: one sampling job per clip, each feeding a layer of the blend job.
+struct BlendedClips {
+    output: Buffer<SoaTransform>,
+    phase: f32,
+    previous_phase: f32,
+    seeks: Vec<f32>,
+    previous_seeks: Vec<f32>,
+    started: bool,
+    blend_job: SharedBlendingJob,
+    sample_jobs: Vec<SharedSamplingJob>,
+    clips: Vec<Shared<ClipLayout>>,
+    fired_events: Vec<AnimationEvent>,
+    sample_outputs: Vec<Buffer<SoaTransform>>,
+    root_motions: Vec<RootMotionTrack>,
+    root_motion: RootMotion,
 }
 
-impl BlendTreeOneDimNode {
-    pub fn new(skeleton: Rc<Skeleton>, anims: Vec<Rc<Animation>>) -> Self {
-        let mut sample_jobs = Vec::<SamplingJobRc>::new();
-        let mut blend_job = BlendingJobRc::default();
+impl BlendedClips {
+    fn new(skeleton: Shared<Skeleton>, clips: &[Shared<ClipLayout>]) -> Self {
+        let mut sample_jobs = Vec::<SharedSamplingJob>::new();
+        let mut blend_job = SharedBlendingJob::default();
         blend_job.set_skeleton(skeleton.clone());
+        let mut sample_outputs = Vec::<Buffer<SoaTransform>>::new();
 
-        for a in anims {
-            let mut sample = SamplingJobRc::default();
+        for clip in clips {
+            let a = &clip.animation;
+            let mut sample = SharedSamplingJob::default();
             sample.set_animation(a.clone());
             sample.set_context(SamplingContext::new(a.num_tracks()));
-            let sample_out = Rc::new(RefCell::new(vec![SoaTransform::default(); skeleton.num_soa_joints()]));
+            let sample_out = new_buffer(vec![SoaTransform::default(); skeleton.num_soa_joints()]);
             sample.set_output(sample_out.clone());
             sample_jobs.push(sample);
+            sample_outputs.push(sample_out.clone());
             blend_job.layers_mut().push(BlendingLayer::new(sample_out.clone()));
             let layers_idx = blend_job.layers().len() - 1;
             blend_job.layers_mut()[layers_idx].weight = 0.0;
         }
 
-        let output = blend_job.output().unwrap();
+        let output = new_buffer(vec![
+            SoaTransform::default();
+            skeleton.num_soa_joints()
+        ]);
+        blend_job.set_output(output.clone());
+
+        let seeks = vec![0.0; sample_jobs.len()];
+
+        BlendedClips {
+            output,
+            phase: 0.0,
+            previous_phase: 0.0,
+            previous_seeks: seeks.clone(),
+            seeks,
+            started: false,
+            blend_job,
+            sample_jobs,
+            clips: clips.to_vec(),
+            fired_events: Vec::new(),
+            sample_outputs,
+            root_motions: clips.iter().filter_map(|clip| clip.root_motion.map(RootMotionTrack::new)).collect(),
+            root_motion: NO_ROOT_MOTION,
+        }
+    }
+
+    fn set_weights(&mut self, weights: Vec<f32>) {
+        for (layer, weight) in self.blend_job.layers_mut().iter_mut().zip(weights) {
+            layer.weight = weight;
+        }
+    }
+
+    fn update(&mut self, dt: web_time::Duration, playback_speed: f32, sync_phase: bool) {
+        self.advance_phase(dt, playback_speed);
+        self.previous_seeks.copy_from_slice(&self.seeks);
+        if sync_phase {
+            self.update_synchronized();
+        } else {
+            self.update_unsynchronized(dt, playback_speed);
+        }
+        self.extract_root_motion();
+        self.blend_job.run().unwrap();
+        self.collect_events();
+        self.started = true;
+    }
+
+    // Root motion is taken out of each clip before blending, then blended with the same weights as the poses.
+    fn extract_root_motion(&mut self) {
+        if self.root_motions.is_empty() {
+            return;
+        }
+        for (i, track) in self.root_motions.iter_mut().enumerate() {
+            if self.blend_job.layers()[i].weight <= 0.0 {
+                track.skip();
+                continue;
+            }
+            let wrapped = self.started && self.seeks[i] < self.previous_seeks[i];
+            track.extract(&mut self.sample_outputs[i].write_buf(), wrapped);
+        }
+        let layers = self.blend_job.layers();
+        self.root_motion = blend_root_motion(
+            self.root_motions
+                .iter()
+                .enumerate()
+                .map(|(i, track)| (layers[i].weight, track.delta)),
+        );
+    }
+
+    // Only clips that are blended in fire their markers.
+    fn collect_events(&mut self) {
+        self.fired_events.clear();
+        for (i, clip) in self.clips.iter().enumerate() {
+            let track = &clip.events;
+            let weight = self.blend_job.layers()[i].weight;
+            if weight <= 0.0 || track.is_empty() {
+                continue;
+            }
+            let duration = self.sample_jobs[i].animation().unwrap().duration();
+            let previous = if self.started { self.previous_seeks[i] / duration } else { -1.0 };
+            track.collect(previous, self.seeks[i] / duration, weight, &mut self.fired_events);
+        }
+    }
+
+    // The phase advances using the weighted average of the active clips' durations. Unsynchronized clips keep their
+    // own seeks, in which case the phase only serves to report the node's overall progress.
+    fn advance_phase(&mut self, dt: web_time::Duration, playback_speed: f32) {
+        let mut cycle_duration = 0.0;
+        for (i, sample_job) in self.sample_jobs.iter().enumerate() {
+            let weight = self.blend_job.layers()[i].weight;
+            cycle_duration += weight * sample_job.animation().unwrap().duration();
+        }
+        self.previous_phase = self.phase;
+        if cycle_duration > 0.0 {
+            self.phase += dt.as_secs_f32() * playback_speed / cycle_duration;
+            self.phase = self.phase.rem_euclid(1.0);
+        }
+    }
+
+    // All clips are sampled at the shared phase.
+    fn update_synchronized(&mut self) {
+        for (i, sample_job) in self.sample_jobs.iter_mut().enumerate() {
+            let duration = sample_job.animation().unwrap().duration();
+            self.seeks[i] = self.phase * duration;
+            if self.blend_job.layers()[i].weight <= 0.0 {
+                continue;
+            }
+            sample_job.set_ratio(self.phase);
+            sample_job.run().unwrap();
+        }
+    }
+
+    fn update_unsynchronized(&mut self, dt: web_time::Duration, playback_speed: f32) {
+        for (i, sample_job) in self.sample_jobs.iter_mut().enumerate() {
+            let duration = sample_job.animation().unwrap().duration();
+            // Every clip keeps advancing so that it is at a sensible place once it gets blended in again.
+            self.seeks[i] += dt.as_secs_f32() * playback_speed;
+            self.seeks[i] %= duration;
+            if self.blend_job.layers()[i].weight <= 0.0 {
+                continue;
+            }
+            sample_job.set_ratio(self.seeks[i] / duration);
+            sample_job.run().unwrap();
+        }
+    }
+
+    fn exit_time_reached(&self, exit_time: f32) -> bool {
+        crossed_normalized_time(self.previous_phase, self.phase, exit_time)
+    }
+
+    fn reset(&mut self) {
+        for seek in self.seeks.iter_mut() {
+            *seek = 0.0;
+        }
+        self.phase = 0.0;
+        self.previous_phase = 0.0;
+        self.started = false;
+        self.fired_events.clear();
+        for track in self.root_motions.iter_mut() {
+            track.reset();
+        }
+        self.root_motion = NO_ROOT_MOTION;
+    }
+}
+
+pub struct BlendTreeOneDimLayout {
+    // Ordered along the parameter axis so neighbours can be found by a linear scan.
+    pub clips: Vec<Shared<ClipLayout>>,
+    pub thresholds: Vec<f32>,
+    pub param_idx: Option<ParameterIndex>,
+    pub sync_phase: bool,
+}
+
+impl BlendTreeOneDimLayout {
+    pub fn new(clips: Vec<(Shared<ClipLayout>, f32)>, param_idx: Option<ParameterIndex>, sync_phase: bool) -> Self {
+        let mut clips = clips;
+        clips.sort_by(|a, b| a.1.total_cmp(&b.1));
+        BlendTreeOneDimLayout {
+            thresholds: clips.iter().map(|a| a.1).collect(),
+            clips: clips.into_iter().map(|a| a.0).collect(),
+            param_idx,
+            sync_phase,
+        }
+    }
+}
+
+pub struct BlendTreeOneDimNode {
+    pub output: Buffer<SoaTransform>,
+    pub playback_speed: f32,
+    pub param: f32,
+    pub layout: Shared<BlendTreeOneDimLayout>,
+    clips: BlendedClips,
+}
+
+impl BlendTreeOneDimNode {
+    pub fn new(skeleton: Shared<Skeleton>, layout: Shared<BlendTreeOneDimLayout>) -> Self {
+        let clips = BlendedClips::new(skeleton, &layout.clips);
 
         BlendTreeOneDimNode {
-            output: output.clone(),
+            output: clips.output.clone(),
             playback_speed: 1.0,
             param: 0.0,
-            blend_job,
-            sample_jobs,
+            layout,
+            clips,
+        }
+    }
+
+    pub fn update(&mut self, dt: web_time::Duration) {
+        if self.layout.thresholds.is_empty() {
+            return;
+        }
+        self.clips.set_weights(one_dim_weights(&self.layout.thresholds, self.param));
+        self.clips.update(dt, self.playback_speed, self.layout.sync_phase);
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.clips.phase
+    }
+
+    pub fn exit_time_reached(&self, exit_time: f32) -> bool {
+        self.clips.exit_time_reached(exit_time)
+    }
+
+    pub fn fired_events(&self) -> &[AnimationEvent] {
+        &self.clips.fired_events
+    }
+
+    pub fn root_motion(&self) -> RootMotion {
+        self.clips.root_motion
+    }
+
+    pub fn reset(&mut self) {
+        self.clips.reset();
+        self.playback_speed = 1.0;
+    }
+}
+
+pub struct BlendSpaceTwoDimLayout {
+    pub clips: Vec<Shared<ClipLayout>>,
+    pub positions: Vec<glam::Vec2>,
+    pub mode: BlendSpaceTwoDimMode,
+    pub param_idx: Option<ParameterIndex>,
+    pub sync_phase: bool,
+}
+
+pub struct BlendSpaceTwoDimNode {
+    pub output: Buffer<SoaTransform>,
+    pub playback_speed: f32,
+    pub param: glam::Vec2,
+    pub layout: Shared<BlendSpaceTwoDimLayout>,
+    clips: BlendedClips,
+}
+
+impl BlendSpaceTwoDimNode {
+    pub fn new(skeleton: Shared<Skeleton>, layout: Shared<BlendSpaceTwoDimLayout>) -> Self {
+        let clips = BlendedClips::new(skeleton, &layout.clips);
+
+        BlendSpaceTwoDimNode {
+            output: clips.output.clone(),
+            playback_speed: 1.0,
+            param: glam::Vec2::ZERO,
+            layout,
+            clips,
+        }
+    }
+
+    pub fn update(&mut self, dt: web_time::Duration) {
+        if self.layout.positions.is_empty() {
+            return;
+        }
+        let weights = match self.layout.mode {
+            BlendSpaceTwoDimMode::Cartesian => cartesian_gradient_band_weights(&self.layout.positions, self.param),
+            BlendSpaceTwoDimMode::Directional => polar_gradient_band_weights(&self.layout.positions, self.param),
+        };
+        self.clips.set_weights(weights);
+        self.clips.update(dt, self.playback_speed, self.layout.sync_phase);
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.clips.phase
+    }
+
+    pub fn exit_time_reached(&self, exit_time: f32) -> bool {
+        self.clips.exit_time_reached(exit_time)
+    }
+
+    pub fn fired_events(&self) -> &[AnimationEvent] {
+        &self.clips.fired_events
+    }
+
+    pub fn root_motion(&self) -> RootMotion {
+        self.clips.root_motion
+    }
+
+    pub fn reset(&mut self) {
+        self.clips.reset();
+        self.playback_speed = 1.0;
+    }
+}
+
+pub struct AdditiveLayout {
+    pub sampler: SamplerLayout,
+    // The pose the clip's deltas are taken from.
+    pub reference: Vec<SoaTransform>,
+}
+
+impl AdditiveLayout {
+    // Without a `reference` animation, the delta is taken from the clip's own first frame.
+    pub fn new(skeleton: &Skeleton, sampler: SamplerLayout, reference: Option<Shared<Animation>>) -> Self {
+        let reference = reference.unwrap_or_else(|| sampler.clip.animation.clone());
+        AdditiveLayout {
+            reference: sample_pose(skeleton, &reference, 0.0),
+            sampler,
+        }
+    }
+}
+
+// Plays a clip as the difference from a reference pose, for additive layers to add on top of whatever is below them.
+pub struct AdditiveNode {
+    pub output: Buffer<SoaTransform>,
+    pub layout: Shared<AdditiveLayout>,
+    sampler: SamplerNode,
+}
+
+impl AdditiveNode {
+    pub fn new(skeleton: Shared<Skeleton>, layout: Shared<AdditiveLayout>) -> Self {
+        let output = new_buffer(vec![
+            SoaTransform::default();
+            skeleton.num_soa_joints()
+        ]);
+        AdditiveNode {
+            output,
+            sampler: SamplerNode::new(skeleton, &layout.sampler),
+            layout,
         }
     }
 
     pub fn update(&mut self, dt: web_time::Duration) {
+        self.sampler.update(dt);
+        additive_delta(&self.sampler.output.read_buf(), &self.layout.reference, &mut self.output.write_buf());
+    }
+
+    pub fn exit_time_reached(&self, exit_time: f32) -> bool {
+        self.sampler.exit_time_reached(exit_time)
+    }
+
+    pub fn fired_events(&self) -> &[AnimationEvent] {
+        self.sampler.fired_events()
+    }
+
+    pub fn reset(&mut self) {
+        self.sampler.reset();
+    }
+}
+
+// The transform that, added by an ozz additive layer on top of `reference`, gives back `pose`.
+fn additive_delta(pose: &[SoaTransform], reference: &[SoaTransform], out: &mut [SoaTransform]) {
+    for ((pose, reference), out) in pose.iter().zip(reference).zip(out.iter_mut()) {
+        for lane in 0..4 {
+            out.translation
+                .set_vec3(lane, pose.translation.vec3(lane) - reference.translation.vec3(lane));
+            out.rotation
+                .set_quat(lane, reference.rotation.quat(lane).conjugate() * pose.rotation.quat(lane));
+            // Padding lanes past the last joint may hold zeroes.
+            let reference_scale = reference.scale.vec3(lane);
+            if reference_scale.cmpeq(glam::Vec3::ZERO).any() {
+                out.scale.set_vec3(lane, glam::Vec3::ONE);
+            } else {
+                out.scale.set_vec3(lane, pose.scale.vec3(lane) / reference_scale);
+            }
+        }
+    }
+}
+
+// The pose at `ratio` (normalized) of the clip.
+fn sample_pose(skeleton: &Skeleton, animation: &Shared<Animation>, ratio: f32) -> Vec<SoaTransform> {
+    let output = new_buffer(vec![
+        SoaTransform::default();
+        skeleton.num_soa_joints()
+    ]);
+    let mut sample_job = SharedSamplingJob::default();
+    sample_job.set_context(SamplingContext::new(animation.num_tracks()));
+    sample_job.set_animation(animation.clone());
+    sample_job.set_output(output.clone());
+    sample_job.set_ratio(ratio);
+    sample_job.run().unwrap();
+    output.read_buf().clone()
+}
+
+// Whether playback went past `time` going from `previous` to `current`, all normalized. Going backwards means we looped.
+fn crossed_normalized_time(previous: f32, current: f32, time: f32) -> bool {
+    if current >= previous {
+        previous < time && time <= current
+    } else {
+        time > previous || time <= current
+    }
+}
+
+// Weights the two clips whose thresholds surround `param`, clamping to the first/last clip outside of the range.
+fn one_dim_weights(thresholds: &[f32], param: f32) -> Vec<f32> {
+    let mut weights = vec![0.0; thresholds.len()];
+    let last = thresholds.len() - 1;
+    if param <= thresholds[0] {
+        weights[0] = 1.0;
+        return weights;
+    }
+    if param >= thresholds[last] {
+        weights[last] = 1.0;
+        return weights;
+    }
+    for i in 0..last {
+        let (low, high) = (thresholds[i], thresholds[i + 1]);
+        if param >= low && param <= high {
+            let range = high - low;
+            let t = if range > 0.0 { (param - low) / range } else { 0.0 };
+            weights[i] = 1.0 - t;
+            weights[i + 1] = t;
+            break;
+        }
+    }
+    weights
+}
+
+// How strongly angular distance counts against radial distance in the polar gradient band.
+const POLAR_ANGULAR_WEIGHT: f32 = 2.0;
+
+// Gradient band interpolation (Johansen, "Automated Semi-Procedural Animation for Character Locomotion").
+// Each clip's influence is the smallest of its falloffs towards every other clip, then all influences are normalized.
+fn cartesian_gradient_band_weights(positions: &[glam::Vec2], param: glam::Vec2) -> Vec<f32> {
+    gradient_band_weights(positions, |p_i, p_j| {
+        let p_ij = p_j - p_i;
+        let p_ip = param - p_i;
+        (p_ij, p_ip)
+    })
+}
+
+// The polar variant measures distance as (relative magnitude, angle), so clips placed on a circle blend by direction
+// rather than across its interior. This is the one to use for strafing locomotion.
+fn polar_gradient_band_weights(positions: &[glam::Vec2], param: glam::Vec2) -> Vec<f32> {
+    gradient_band_weights(positions, |p_i, p_j| {
+        let mean_length = (p_i.length() + p_j.length()) * 0.5;
+        let mut angle_ij = 0.0;
+        let mut angle_ip = 0.0;
+        // The angle to or from the origin is undefined, in which case only the radial distance counts.
+        if p_i.length_squared() > f32::EPSILON && p_j.length_squared() > f32::EPSILON {
+            angle_ij = p_i.angle_to(p_j);
+            if param.length_squared() > f32::EPSILON {
+                angle_ip = p_i.angle_to(param);
+            }
+        }
+        let p_ij = glam::Vec2::new(
+            (p_j.length() - p_i.length()) / mean_length,
+            angle_ij * POLAR_ANGULAR_WEIGHT,
+        );
+        let p_ip = glam::Vec2::new(
+            (param.length() - p_i.length()) / mean_length,
+            angle_ip * POLAR_ANGULAR_WEIGHT,
+        );
+        (p_ij, p_ip)
+    })
+}
+
+fn gradient_band_weights<F>(positions: &[glam::Vec2], mut band_vectors: F) -> Vec<f32>
+where
+    F: FnMut(glam::Vec2, glam::Vec2) -> (glam::Vec2, glam::Vec2),
+{
+    let mut weights = vec![0.0; positions.len()];
+    let mut total = 0.0;
+    for (i, p_i) in positions.iter().enumerate() {
+        let mut weight = 1.0f32;
+        for (j, p_j) in positions.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            let (p_ij, p_ip) = band_vectors(*p_i, *p_j);
+            let length_squared = p_ij.length_squared();
+            // Clips sharing a position can't be told apart; let them split the weight.
+            if length_squared <= f32::EPSILON || !length_squared.is_finite() {
+                continue;
+            }
+            let falloff = 1.0 - p_ip.dot(p_ij) / length_squared;
+            weight = weight.min(falloff.max(0.0));
+        }
+        weights[i] = weight;
+        total += weight;
+    }
+    if total > 0.0 {
+        for weight in weights.iter_mut() {
+            *weight /= total;
+        }
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+
+    fn assert_weights(weights: &[f32], expected: &[f32]) {
+        assert_eq!(weights.len(), expected.len());
+        for (weight, expected) in weights.iter().zip(expected) {
+            assert!((weight - expected).abs() < 1e-5, "{:?} != {:?}", weights, expected);
+        }
+    }
+
+    #[test]
+    fn one_dim_weights_hit_each_threshold() {
+        let thresholds = [-1.0, 0.0, 2.0];
+        assert_weights(&one_dim_weights(&thresholds, -1.0), &[1.0, 0.0, 0.0]);
+        assert_weights(&one_dim_weights(&thresholds, 0.0), &[0.0, 1.0, 0.0]);
+        assert_weights(&one_dim_weights(&thresholds, 2.0), &[0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn one_dim_weights_clamp_outside_of_the_range() {
+        let thresholds = [-1.0, 0.0, 2.0];
+        assert_weights(&one_dim_weights(&thresholds, -5.0), &[1.0, 0.0, 0.0]);
+        assert_weights(&one_dim_weights(&thresholds, 5.0), &[0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn one_dim_weights_blend_the_surrounding_clips() {
+        let thresholds = [-1.0, 0.0, 2.0];
+        assert_weights(&one_dim_weights(&thresholds, 0.5), &[0.0, 0.75, 0.25]);
+        for step in 0..=30 {
+            let param = -1.5 + step as f32 * 0.125;
+            let sum: f32 = one_dim_weights(&thresholds, param).iter().sum();
+            assert!((sum - 1.0).abs() < 1e-5, "weights at {} sum to {}", param, sum);
+        }
+    }
+
+    #[test]
+    fn one_dim_weights_with_a_single_clip() {
+        assert_weights(&one_dim_weights(&[0.5], 3.0), &[1.0]);
+        assert_weights(&one_dim_weights(&[0.5], -3.0), &[1.0]);
+    }
+
+    // A square of clips plus one in the middle, as in a speed/slope blend space.
+    fn square() -> Vec<glam::Vec2> {
+        vec![
+            glam::Vec2::new(0.0, 0.0),
+            glam::Vec2::new(-1.0, -1.0),
+            glam::Vec2::new(1.0, -1.0),
+            glam::Vec2::new(-1.0, 1.0),
+            glam::Vec2::new(1.0, 1.0),
+        ]
+    }
+
+    // Strafing clips around the origin, with idle in the middle.
+    fn directions() -> Vec<glam::Vec2> {
+        vec![
+            glam::Vec2::ZERO,
+            glam::Vec2::new(0.0, 1.0),
+            glam::Vec2::new(1.0, 0.0),
+            glam::Vec2::new(0.0, -1.0),
+            glam::Vec2::new(-1.0, 0.0),
+        ]
+    }
+
+    fn one_hot(len: usize, i: usize) -> Vec<f32> {
+        let mut weights = vec![0.0; len];
+        weights[i] = 1.0;
+        weights
+    }
+
+    #[test]
+    fn gradient_band_weights_are_one_on_each_clip() {
+        let positions = square();
+        for (i, position) in positions.iter().enumerate() {
+            assert_weights(&cartesian_gradient_band_weights(&positions, *position), &one_hot(positions.len(), i));
+        }
+        let positions = directions();
+        for (i, position) in positions.iter().enumerate() {
+            assert_weights(&polar_gradient_band_weights(&positions, *position), &one_hot(positions.len(), i));
+        }
+    }
+
+    #[test]
+    fn gradient_band_weights_sum_to_one() {
+        for x in -4..=4 {
+            for y in -4..=4 {
+                let param = glam::Vec2::new(x as f32, y as f32) * 0.3;
+                for weights in [
+                    cartesian_gradient_band_weights(&square(), param),
+                    polar_gradient_band_weights(&directions(), param),
+                ] {
+                    let sum: f32 = weights.iter().sum();
+                    assert!((sum - 1.0).abs() < 1e-4, "weights at {} sum to {}", param, sum);
+                    assert!(weights.iter().all(|w| *w >= 0.0), "negative weight at {}: {:?}", param, weights);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_band_weights_split_evenly_between_two_clips() {
+        let positions = [glam::Vec2::new(-1.0, 0.0), glam::Vec2::new(1.0, 0.0)];
+        assert_weights(&cartesian_gradient_band_weights(&positions, glam::Vec2::ZERO), &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn polar_gradient_band_weights_blend_by_direction() {
+        // Halfway between forward and right, at full speed, leaves idle and the other directions out.
+        let param = glam::Vec2::new(1.0, 1.0).normalize();
+        let weights = polar_gradient_band_weights(&directions(), param);
+        assert!(weights[0] < 1e-5 && weights[3] < 1e-5 && weights[4] < 1e-5, "{:?}", weights);
+        assert!((weights[1] - weights[2]).abs() < 1e-5, "{:?}", weights);
+    }
+
+    // Blends a one second and a two second clip half and half.
+    fn uneven_blend_tree(sync_phase: bool) -> BlendTreeOneDimNode {
+        let skeleton = test_fixtures::skeleton();
+        let clip = |duration: f32| {
+            let clip = ClipLayout::new(&skeleton, test_fixtures::clip(duration), EventTrack::new("walk", &[]), None);
+            Shared::new(clip)
+        };
+        let layout = BlendTreeOneDimLayout::new(vec![(clip(1.0), 0.0), (clip(2.0), 1.0)], None, sync_phase);
+        let mut node = BlendTreeOneDimNode::new(skeleton.clone(), Shared::new(layout));
+        node.param = 0.5;
+        node
+    }
+
+    fn normalized_seeks(node: &BlendTreeOneDimNode) -> Vec<f32> {
+        node.clips
+            .seeks
+            .iter()
+            .zip(&node.layout.clips)
+            .map(|(seek, clip)| seek / clip.animation.duration())
+            .collect()
+    }
+
+    #[test]
+    fn synchronized_clips_of_different_lengths_keep_the_same_phase() {
+        let mut node = uneven_blend_tree(true);
+        let mut expected = 0.0;
+        for _ in 0..7 {
+            node.update(web_time::Duration::from_secs_f32(0.3));
+            // Half of each clip makes for a 1.5 second cycle.
+            expected = (expected + 0.2f32).rem_euclid(1.0);
+            assert!((node.phase() - expected).abs() < 1e-4, "{} != {}", node.phase(), expected);
+            for seek in normalized_seeks(&node) {
+                assert!((seek - expected).abs() < 1e-4, "{:?} != {}", normalized_seeks(&node), expected);
+            }
+            // Both clips move the root from 0 to 1 over their length, so in phase they agree on where it is.
+            let root = root_translation(&node.output.read_buf()).x;
+            assert!((root - expected).abs() < 1e-3, "{} != {}", root, expected);
+        }
+    }
+
+    #[test]
+    fn unsynchronized_clips_drift_apart() {
+        let mut node = uneven_blend_tree(false);
+        for _ in 0..3 {
+            node.update(web_time::Duration::from_secs_f32(0.3));
+        }
+        let seeks = normalized_seeks(&node);
+        assert!((seeks[0] - 0.9).abs() < 1e-4 && (seeks[1] - 0.45).abs() < 1e-4, "{:?}", seeks);
+    }
+
+    // Plays the fixture clip, moving the root from 0 to 1 along x, as an additive over its first frame.
+    fn additive_node() -> AdditiveNode {
+        let skeleton = test_fixtures::skeleton();
+        let clip = ClipLayout::new(&skeleton, test_fixtures::clip(1.0), EventTrack::new("lean", &[]), None);
+        let sampler = SamplerLayout {
+            clip: Shared::new(clip),
+            looping: true,
+            speed: 1.0,
+        };
+        let layout = AdditiveLayout::new(&skeleton, sampler, None);
+        AdditiveNode::new(skeleton, Shared::new(layout))
+    }
+
+    // Runs an ozz additive blend of `delta` at `weight` over `base`.
+    fn add(base: &[SoaTransform], delta: Buffer<SoaTransform>, weight: f32) -> Vec<SoaTransform> {
+        let mut blend_job = SharedBlendingJob::default();
+        blend_job.set_skeleton(test_fixtures::skeleton());
+        let mut layer = BlendingLayer::new(new_buffer(base.to_vec()));
+        layer.weight = 1.0;
+        blend_job.layers_mut().push(layer);
+        let mut additive = BlendingLayer::new(delta);
+        additive.weight = weight;
+        blend_job.additive_layers_mut().push(additive);
+        let output = new_buffer(vec![SoaTransform::default(); base.len()]);
+        blend_job.set_output(output.clone());
+        blend_job.run().unwrap();
+        output.read_buf().clone()
+    }
+
+    fn root_translation(pose: &[SoaTransform]) -> glam::Vec3 {
+        pose[0].translation.vec3(0)
+    }
+
+    #[test]
+    fn additive_delta_over_the_reference_gives_back_the_clip() {
+        let mut node = additive_node();
+        node.update(web_time::Duration::from_secs_f32(0.25));
+        let skeleton = test_fixtures::skeleton();
+        let clip = sample_pose(&skeleton, &test_fixtures::clip(1.0), 0.25);
+        let pose = add(&node.layout.reference, node.output.clone(), 1.0);
+        assert!(root_translation(&pose).abs_diff_eq(root_translation(&clip), 1e-3), "{}", root_translation(&pose));
+        assert!(pose[0].rotation.quat(0).abs_diff_eq(clip[0].rotation.quat(0), 1e-4));
+        assert!(pose[0].scale.vec3(0).abs_diff_eq(clip[0].scale.vec3(0), 1e-4));
+
+        // On another base pose, the same delta moves the root by as much as the clip did.
+        let mut base = node.layout.reference.clone();
+        base[0].translation.set_vec3(0, glam::Vec3::new(2.0, 1.0, 0.0));
+        let pose = add(&base, node.output.clone(), 1.0);
+        assert!(root_translation(&pose).abs_diff_eq(glam::Vec3::new(2.25, 1.0, 0.0), 1e-3), "{}", root_translation(&pose));
+    }
 
+    #[test]
+    fn zero_additive_weight_leaves_the_base_untouched() {
+        let mut node = additive_node();
+        node.update(web_time::Duration::from_secs_f32(0.5));
+        let mut base = node.layout.reference.clone();
+        base[0].translation.set_vec3(0, glam::Vec3::new(2.0, 1.0, 0.0));
+        let pose = add(&base, node.output.clone(), 0.0);
+        assert!(root_translation(&pose).abs_diff_eq(glam::Vec3::new(2.0, 1.0, 0.0), 1e-5), "{}", root_translation(&pose));
     }
 }
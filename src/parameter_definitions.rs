@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ParameterValue {
+    Float(f32),
+    Int(i32),
+    Bool(bool),
+    // Stays set until a transition consumes it.
+    Trigger(bool),
+    Vec2(glam::Vec2),
+}
+
+impl ParameterValue {
+    // Whether both values are of the same parameter type, ignoring the values themselves.
+    pub fn same_type(&self, other: &ParameterValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ParameterValue::Float(_) => "float",
+            ParameterValue::Int(_) => "int",
+            ParameterValue::Bool(_) => "bool",
+            ParameterValue::Trigger(_) => "trigger",
+            ParameterValue::Vec2(_) => "vec2",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ParameterDefinition {
+    pub name: String,
+    pub default_value: ParameterValue,
+}
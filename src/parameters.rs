@@ -0,0 +1,165 @@
+use crate::parameter_definitions::*;
+use anyhow::anyhow;
+use std::collections::HashMap;
+
+safe_index::new! {
+ParameterIndex,
+map: ParametersContainer
+}
+
+#[derive(Clone)]
+pub struct Blackboard {
+    values: ParametersContainer<ParameterValue>,
+    names: HashMap<String, ParameterIndex>,
+}
+
+impl Blackboard {
+    pub fn new(parameter_definitions: &[ParameterDefinition]) -> Result<Self, anyhow::Error> {
+        let mut values = ParametersContainer::<ParameterValue>::new();
+        let mut names = HashMap::<String, ParameterIndex>::new();
+        for parameter_definition in parameter_definitions {
+            if names.contains_key(&parameter_definition.name) {
+                return Err(anyhow!(
+                    "Duplicate parameter name: {}",
+                    &parameter_definition.name
+                ));
+            }
+            let idx = values.push(parameter_definition.default_value);
+            names.insert(parameter_definition.name.clone(), idx);
+        }
+        Ok(Blackboard { values, names })
+    }
+
+    pub fn get_parameter_by_name(&self, name: &str) -> Option<ParameterIndex> {
+        self.names.get(name).copied()
+    }
+
+    pub fn parameter_name(&self, idx: ParameterIndex) -> &str {
+        self.names
+            .iter()
+            .find(|(_, val)| **val == idx)
+            .map(|(name, _)| name.as_str())
+            .unwrap_or("<unknown>")
+    }
+
+    pub fn get(&self, idx: ParameterIndex) -> ParameterValue {
+        self.values[idx]
+    }
+
+    // Overwrites a parameter, refusing values of a different type than the one it was declared with.
+    pub fn set(&mut self, idx: ParameterIndex, value: ParameterValue) -> Result<(), anyhow::Error> {
+        if !self.values[idx].same_type(&value) {
+            return Err(anyhow!(
+                "Cannot assign a {} to a {} parameter",
+                value.type_name(),
+                self.values[idx].type_name()
+            ));
+        }
+        self.values[idx] = value;
+        Ok(())
+    }
+
+    // Int parameters read as floats too, so that a blend tree can be driven by e.g. a gear number.
+    pub fn get_float(&self, idx: ParameterIndex) -> Option<f32> {
+        match self.values[idx] {
+            ParameterValue::Float(val) => Some(val),
+            ParameterValue::Int(val) => Some(val as f32),
+            _ => None,
+        }
+    }
+
+    pub fn get_vec2(&self, idx: ParameterIndex) -> Option<glam::Vec2> {
+        match self.values[idx] {
+            ParameterValue::Vec2(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    pub fn consume_trigger(&mut self, idx: ParameterIndex) {
+        if let ParameterValue::Trigger(_) = self.values[idx] {
+            self.values[idx] = ParameterValue::Trigger(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(name: &str, default_value: ParameterValue) -> ParameterDefinition {
+        ParameterDefinition {
+            name: name.to_string(),
+            default_value,
+        }
+    }
+
+    fn blackboard() -> Blackboard {
+        Blackboard::new(&[
+            definition("speed", ParameterValue::Float(1.5)),
+            definition("gear", ParameterValue::Int(2)),
+            definition("grounded", ParameterValue::Bool(true)),
+            definition("jump", ParameterValue::Trigger(false)),
+            definition("direction", ParameterValue::Vec2(glam::Vec2::X)),
+        ])
+        .unwrap()
+    }
+
+    fn idx(blackboard: &Blackboard, name: &str) -> ParameterIndex {
+        blackboard.get_parameter_by_name(name).unwrap()
+    }
+
+    #[test]
+    fn parameters_start_at_their_defaults() {
+        let blackboard = blackboard();
+        assert_eq!(blackboard.get(idx(&blackboard, "speed")), ParameterValue::Float(1.5));
+        assert_eq!(blackboard.get(idx(&blackboard, "jump")), ParameterValue::Trigger(false));
+        assert_eq!(blackboard.parameter_name(idx(&blackboard, "gear")), "gear");
+        assert_eq!(blackboard.get_parameter_by_name("dash"), None);
+    }
+
+    #[test]
+    fn duplicate_parameter_names_are_an_error() {
+        let definitions = [
+            definition("speed", ParameterValue::Float(0.0)),
+            definition("speed", ParameterValue::Int(0)),
+        ];
+        let err = Blackboard::new(&definitions).err().unwrap();
+        assert_eq!(err.to_string(), "Duplicate parameter name: speed");
+    }
+
+    #[test]
+    fn set_refuses_values_of_another_type() {
+        let mut blackboard = blackboard();
+        let speed = idx(&blackboard, "speed");
+        blackboard.set(speed, ParameterValue::Float(3.0)).unwrap();
+        assert_eq!(blackboard.get(speed), ParameterValue::Float(3.0));
+        let err = blackboard.set(speed, ParameterValue::Int(3)).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot assign a int to a float parameter");
+        assert_eq!(blackboard.get(speed), ParameterValue::Float(3.0));
+        let jump = idx(&blackboard, "jump");
+        assert!(blackboard.set(jump, ParameterValue::Bool(true)).is_err());
+    }
+
+    #[test]
+    fn get_float_reads_floats_and_ints() {
+        let blackboard = blackboard();
+        assert_eq!(blackboard.get_float(idx(&blackboard, "speed")), Some(1.5));
+        assert_eq!(blackboard.get_float(idx(&blackboard, "gear")), Some(2.0));
+        assert_eq!(blackboard.get_float(idx(&blackboard, "grounded")), None);
+        assert_eq!(blackboard.get_float(idx(&blackboard, "direction")), None);
+        assert_eq!(blackboard.get_vec2(idx(&blackboard, "direction")), Some(glam::Vec2::X));
+        assert_eq!(blackboard.get_vec2(idx(&blackboard, "speed")), None);
+    }
+
+    #[test]
+    fn consuming_only_resets_triggers() {
+        let mut blackboard = blackboard();
+        let jump = idx(&blackboard, "jump");
+        blackboard.set(jump, ParameterValue::Trigger(true)).unwrap();
+        blackboard.consume_trigger(jump);
+        assert_eq!(blackboard.get(jump), ParameterValue::Trigger(false));
+        let grounded = idx(&blackboard, "grounded");
+        blackboard.consume_trigger(grounded);
+        assert_eq!(blackboard.get(grounded), ParameterValue::Bool(true));
+    }
+}
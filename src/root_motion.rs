@@ -0,0 +1,283 @@
+use crate::root_motion_definitions::RootMotionAxes;
+use ozz_animation_rs::SoaTransform;
+
+// A root motion delta: a translation expressed in the character's frame at the start of the delta, then a rotation.
+pub type RootMotion = (glam::Vec3, glam::Quat);
+
+pub const NO_ROOT_MOTION: RootMotion = (glam::Vec3::ZERO, glam::Quat::IDENTITY);
+
+// How root motion gets extracted, resolved against the skeleton.
+#[derive(Clone, Copy, Debug)]
+pub struct RootMotionSettings {
+    pub axes: RootMotionAxes,
+    pub remove_from_pose: bool,
+    joint: usize,
+}
+
+impl RootMotionSettings {
+    pub fn new(joint: usize, axes: RootMotionAxes, remove_from_pose: bool) -> Self {
+        RootMotionSettings {
+            axes,
+            remove_from_pose,
+            joint,
+        }
+    }
+
+    fn read(&self, pose: &[SoaTransform]) -> (glam::Vec3, glam::Quat) {
+        let transform = &pose[self.joint / 4];
+        let lane = self.joint % 4;
+        (transform.translation.vec3(lane), transform.rotation.quat(lane))
+    }
+
+    fn write(&self, pose: &mut [SoaTransform], translation: glam::Vec3, rotation: glam::Quat) {
+        let transform = &mut pose[self.joint / 4];
+        let lane = self.joint % 4;
+        transform.translation.set_vec3(lane, translation);
+        transform.rotation.set_quat(lane, rotation);
+    }
+}
+
+// Where the root joint of a single clip starts and ends, sampled once for every node playing the clip.
+#[derive(Clone, Copy, Debug)]
+pub struct RootMotionClip {
+    settings: RootMotionSettings,
+    start: (glam::Vec3, glam::Quat),
+    end: (glam::Vec3, glam::Quat),
+}
+
+impl RootMotionClip {
+    // `start` and `end` are the poses sampled at the very start and end of the clip.
+    pub fn new(settings: RootMotionSettings, start: &[SoaTransform], end: &[SoaTransform]) -> Self {
+        RootMotionClip {
+            settings,
+            start: settings.read(start),
+            end: settings.read(end),
+        }
+    }
+}
+
+// Follows the root joint of a single clip from one update to the next.
+pub struct RootMotionTrack {
+    settings: RootMotionSettings,
+    start: (glam::Vec3, glam::Quat),
+    end: (glam::Vec3, glam::Quat),
+    // None when the clip wasn't sampled last update, in which case there is nothing to measure against.
+    previous: Option<(glam::Vec3, glam::Quat)>,
+    pub delta: RootMotion,
+}
+
+impl RootMotionTrack {
+    pub fn new(clip: RootMotionClip) -> Self {
+        RootMotionTrack {
+            settings: clip.settings,
+            start: clip.start,
+            end: clip.end,
+            previous: Some(clip.start),
+            delta: NO_ROOT_MOTION,
+        }
+    }
+
+    // Measures the motion since the last call, then takes it out of `pose` if asked to. When the clip looped, the
+    // motion is that up to the end of the clip followed by that from its start.
+    pub fn extract(&mut self, pose: &mut [SoaTransform], wrapped: bool) {
+        let current = self.settings.read(pose);
+        self.delta = match self.previous {
+            Some(previous) if wrapped => compose(
+                self.motion_between(previous, self.end),
+                self.motion_between(self.start, current),
+            ),
+            Some(previous) => self.motion_between(previous, current),
+            None => NO_ROOT_MOTION,
+        };
+        self.previous = Some(current);
+
+        if self.settings.remove_from_pose {
+            // Removed components are pinned to where the clip starts, so any authored offset survives.
+            let axes = self.settings.axes;
+            let mut translation = current.0;
+            if axes.x {
+                translation.x = self.start.0.x;
+            }
+            if axes.y {
+                translation.y = self.start.0.y;
+            }
+            if axes.z {
+                translation.z = self.start.0.z;
+            }
+            let mut rotation = current.1;
+            if axes.yaw {
+                rotation = (yaw(self.start.1) * yaw(current.1).inverse() * current.1).normalize();
+            }
+            self.settings.write(pose, translation, rotation);
+        }
+    }
+
+    // For clips that weren't sampled this update.
+    pub fn skip(&mut self) {
+        self.previous = None;
+        self.delta = NO_ROOT_MOTION;
+    }
+
+    pub fn reset(&mut self) {
+        self.previous = Some(self.start);
+        self.delta = NO_ROOT_MOTION;
+    }
+
+    fn motion_between(&self, from: (glam::Vec3, glam::Quat), to: (glam::Vec3, glam::Quat)) -> RootMotion {
+        let axes = self.settings.axes;
+        let (from_yaw, to_yaw) = if axes.yaw {
+            (yaw(from.1), yaw(to.1))
+        } else {
+            (glam::Quat::IDENTITY, glam::Quat::IDENTITY)
+        };
+        let mut translation = to.0 - from.0;
+        if !axes.x {
+            translation.x = 0.0;
+        }
+        if !axes.y {
+            translation.y = 0.0;
+        }
+        if !axes.z {
+            translation.z = 0.0;
+        }
+        (from_yaw.inverse() * translation, (from_yaw.inverse() * to_yaw).normalize())
+    }
+}
+
+// `first` followed by `second`.
+pub fn compose(first: RootMotion, second: RootMotion) -> RootMotion {
+    (first.0 + first.1 * second.0, (first.1 * second.1).normalize())
+}
+
+// Weighted average of deltas, as done to the poses they come from. Weights are expected to add up to 1.
+pub fn blend_root_motion(deltas: impl IntoIterator<Item = (f32, RootMotion)>) -> RootMotion {
+    let mut translation = glam::Vec3::ZERO;
+    let mut rotation = glam::Vec4::ZERO;
+    for (weight, delta) in deltas {
+        if weight <= 0.0 {
+            continue;
+        }
+        translation += delta.0 * weight;
+        // Keep every rotation in the same hemisphere before summing.
+        let mut q = glam::Vec4::from(delta.1);
+        if q.w < 0.0 {
+            q = -q;
+        }
+        rotation += q * weight;
+    }
+    if rotation.length_squared() <= f32::EPSILON {
+        return (translation, glam::Quat::IDENTITY);
+    }
+    (translation, glam::Quat::from_vec4(rotation).normalize())
+}
+
+// The twist of `rotation` around the up axis.
+fn yaw(rotation: glam::Quat) -> glam::Quat {
+    let twist = glam::Quat::from_xyzw(0.0, rotation.y, 0.0, rotation.w);
+    if twist.length_squared() <= f32::EPSILON {
+        return glam::Quat::IDENTITY;
+    }
+    twist.normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::root_motion_definitions::{RootMotionDefinition, RootMotionMode};
+    use crate::test_fixtures::*;
+
+    fn pose(translation: glam::Vec3, rotation: glam::Quat) -> Vec<SoaTransform> {
+        let mut pose = vec![SoaTransform::IDENTITY];
+        pose[0].translation.set_vec3(0, translation);
+        pose[0].rotation.set_quat(0, rotation);
+        pose
+    }
+
+    fn at_x(x: f32) -> Vec<SoaTransform> {
+        pose(glam::Vec3::new(x, 0.0, 0.0), glam::Quat::IDENTITY)
+    }
+
+    // A clip walking the root from 0 to 4 along x.
+    fn walk(remove_from_pose: bool) -> RootMotionTrack {
+        let settings = RootMotionSettings::new(0, RootMotionAxes::default(), remove_from_pose);
+        RootMotionTrack::new(RootMotionClip::new(settings, &at_x(0.0), &at_x(4.0)))
+    }
+
+    fn assert_near(a: glam::Vec3, b: glam::Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn measures_the_motion_since_the_last_update() {
+        let mut track = walk(false);
+        track.extract(&mut at_x(1.0), false);
+        assert_near(track.delta.0, glam::Vec3::new(1.0, 0.0, 0.0));
+        track.extract(&mut at_x(2.5), false);
+        assert_near(track.delta.0, glam::Vec3::new(1.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn goes_through_the_end_of_the_clip_when_wrapping() {
+        let mut track = walk(false);
+        track.extract(&mut at_x(3.5), false);
+        // From 3.5 to the end at 4, then from the start at 0 to 0.5.
+        track.extract(&mut at_x(0.5), true);
+        assert_near(track.delta.0, glam::Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn wrapping_turns_with_the_clip() {
+        let quarter_turn = glam::Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let settings = RootMotionSettings::new(0, RootMotionAxes::default(), false);
+        // A clip walking 1 forward while turning left a quarter.
+        let start = pose(glam::Vec3::ZERO, glam::Quat::IDENTITY);
+        let end = pose(glam::Vec3::new(0.0, 0.0, 1.0), quarter_turn);
+        let mut track = RootMotionTrack::new(RootMotionClip::new(settings, &start, &end));
+        track.extract(&mut end.clone(), false);
+        // Looping right back to the start, then the whole clip again, happens in the turned frame of the first loop.
+        track.extract(&mut end.clone(), true);
+        assert_near(track.delta.0, glam::Vec3::new(0.0, 0.0, 1.0));
+        assert!(track.delta.1.abs_diff_eq(quarter_turn, 1e-4));
+        let (translation, rotation) = compose(compose(NO_ROOT_MOTION, (glam::Vec3::Z, quarter_turn)), track.delta);
+        assert_near(translation, glam::Vec3::new(1.0, 0.0, 1.0));
+        assert!(rotation.abs_diff_eq(quarter_turn * quarter_turn, 1e-4));
+    }
+
+    #[test]
+    fn removes_the_motion_from_the_pose() {
+        let mut track = walk(true);
+        let mut pose = pose(glam::Vec3::new(2.0, 1.0, 3.0), glam::Quat::IDENTITY);
+        track.extract(&mut pose, false);
+        // Vertical motion isn't root motion by default, so it stays.
+        assert_near(pose[0].translation.vec3(0), glam::Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn skipped_clips_report_no_motion() {
+        let mut track = walk(false);
+        track.extract(&mut at_x(1.0), false);
+        track.skip();
+        track.extract(&mut at_x(3.0), false);
+        assert_eq!(track.delta, NO_ROOT_MOTION);
+        track.extract(&mut at_x(3.5), false);
+        assert_near(track.delta.0, glam::Vec3::new(0.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn looping_samplers_accumulate_motion_across_the_wrap() {
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("walk", "a").looping(true)
+            .root("walk")
+            .root_motion(RootMotionDefinition {
+                mode: RootMotionMode::Extract,
+                ..Default::default()
+            });
+        let mut graph = graph(&builder.build().unwrap());
+        // The fixture clip moves 1 along x over a second.
+        step(&mut graph, 0.75);
+        assert_near(graph.take_root_motion().0, glam::Vec3::new(0.75, 0.0, 0.0));
+        step(&mut graph, 0.5);
+        assert_near(graph.take_root_motion().0, glam::Vec3::new(0.5, 0.0, 0.0));
+    }
+}
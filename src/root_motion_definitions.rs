@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum RootMotionMode {
+    // The root joint plays exactly as authored.
+    #[default]
+    Disabled,
+    // Root motion is measured and reported, the pose is left untouched.
+    Extract,
+    // Root motion is measured, reported and taken out of the pose, so the character animates in place.
+    ExtractAndRemove,
+}
+
+// Which components of the root joint's motion count as root motion. Yaw is the rotation around the up (Y) axis.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RootMotionAxes {
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
+    pub yaw: bool,
+}
+
+impl Default for RootMotionAxes {
+    // Vertical motion usually stays in the pose so that jumps and crouches still show.
+    fn default() -> Self {
+        RootMotionAxes {
+            x: true,
+            y: false,
+            z: true,
+            yaw: true,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct RootMotionDefinition {
+    #[serde(default)]
+    pub mode: RootMotionMode,
+    // The joint carrying the motion. Defaults to the skeleton's first joint.
+    #[serde(default)]
+    pub joint_name: Option<String>,
+    #[serde(default)]
+    pub axes: RootMotionAxes,
+}
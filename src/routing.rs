@@ -0,0 +1,220 @@
+use mapgraph::aliases::SlotMapGraph;
+use mapgraph::map::slotmap::EdgeIndex;
+use mapgraph::map::slotmap::NodeIndex;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+// Frontier entry for Dijkstra's search, ordered so that BinaryHeap pops the cheapest node first.
+struct Frontier {
+    cost: f32,
+    node: NodeIndex,
+}
+
+impl PartialEq for Frontier {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Frontier {}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+// Finds the cheapest chain of edges leading from `from` to `to`, using `edge_cost` for each edge's cost. Negative costs
+// are treated as free. Returns None if `to` can't be reached, and an empty path if `from` is `to`. Between routes of
+// the same cost, each node is arrived at through the edge first in `edge_order`.
+pub fn shortest_path<N, E, F, O>(
+    graph: &SlotMapGraph<N, E>,
+    from: NodeIndex,
+    to: NodeIndex,
+    edge_cost: F,
+    edge_order: O,
+) -> Option<VecDeque<EdgeIndex>>
+where
+    F: Fn(EdgeIndex, &E) -> f32,
+    O: Fn(&E, &E) -> Ordering,
+{
+    let arrived_through = shortest_path_tree(graph, from, Some(to), edge_cost, edge_order);
+    path_in_tree(graph, &arrived_through, from, to)
+}
+
+// The edge each node reachable from `from` is best arrived through, as in shortest_path(). Stops early once `to` is
+// settled, if given.
+pub fn shortest_path_tree<N, E, F, O>(
+    graph: &SlotMapGraph<N, E>,
+    from: NodeIndex,
+    to: Option<NodeIndex>,
+    edge_cost: F,
+    edge_order: O,
+) -> HashMap<NodeIndex, EdgeIndex>
+where
+    F: Fn(EdgeIndex, &E) -> f32,
+    O: Fn(&E, &E) -> Ordering,
+{
+    let mut best_costs = HashMap::<NodeIndex, f32>::new();
+    let mut arrived_through = HashMap::<NodeIndex, EdgeIndex>::new();
+    let mut frontier = BinaryHeap::<Frontier>::new();
+    best_costs.insert(from, 0.0);
+    frontier.push(Frontier {
+        cost: 0.0,
+        node: from,
+    });
+
+    while let Some(Frontier { cost, node }) = frontier.pop() {
+        if Some(node) == to {
+            break;
+        }
+        // Skip stale entries that were superseded by a cheaper route.
+        if cost > best_costs[&node] {
+            continue;
+        }
+        let mut outputs = graph.outputs(node).collect::<Vec<_>>();
+        outputs.sort_by(|(_, a), (_, b)| edge_order(a.weight(), b.weight()));
+        for (edge_idx, edge_ref) in outputs {
+            let next = edge_ref.to();
+            let step_cost = edge_cost(edge_idx, edge_ref.weight()).max(0.0);
+            let next_cost = cost + step_cost;
+            match best_costs.get(&next) {
+                Some(best) if next_cost > *best => {}
+                // Ties go to the edge first in `edge_order`. A free edge could lead back from below `next`, so those
+                // never take over.
+                Some(best) if next_cost == *best => {
+                    if let Some(current) = arrived_through.get(&next)
+                        && step_cost > 0.0
+                        && edge_order(edge_ref.weight(), graph.edge(*current).unwrap().weight()) == Ordering::Less
+                    {
+                        arrived_through.insert(next, edge_idx);
+                    }
+                }
+                _ => {
+                    best_costs.insert(next, next_cost);
+                    arrived_through.insert(next, edge_idx);
+                    frontier.push(Frontier {
+                        cost: next_cost,
+                        node: next,
+                    });
+                }
+            }
+        }
+    }
+    arrived_through
+}
+
+// Walks back from `to` to rebuild the path a shortest_path_tree() from `from` holds.
+pub fn path_in_tree<N, E>(
+    graph: &SlotMapGraph<N, E>,
+    arrived_through: &HashMap<NodeIndex, EdgeIndex>,
+    from: NodeIndex,
+    to: NodeIndex,
+) -> Option<VecDeque<EdgeIndex>> {
+    let mut path = VecDeque::<EdgeIndex>::new();
+    let mut node = to;
+    while node != from {
+        let edge_idx = *arrived_through.get(&node)?;
+        path.push_front(edge_idx);
+        node = graph.edge(edge_idx).unwrap().from();
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Edges carry their (cost, priority).
+    type Graph = SlotMapGraph<&'static str, (f32, i32)>;
+
+    fn route(graph: &Graph, from: NodeIndex, to: NodeIndex) -> Option<Vec<(f32, i32)>> {
+        let path = shortest_path(graph, from, to, |_, e| e.0, |a, b| b.1.cmp(&a.1))?;
+        Some(path.iter().map(|e| *graph.edge(*e).unwrap().weight()).collect())
+    }
+
+    fn nodes(graph: &mut Graph) -> [NodeIndex; 4] {
+        ["a", "b", "c", "d"].map(|name| graph.add_node(name))
+    }
+
+    #[test]
+    fn takes_the_cheapest_path_over_the_shortest() {
+        let mut graph = Graph::default();
+        let [a, b, c, d] = nodes(&mut graph);
+        graph.add_edge((5.0, 0), a, d).unwrap();
+        graph.add_edge((1.0, 1), a, b).unwrap();
+        graph.add_edge((1.0, 2), b, c).unwrap();
+        graph.add_edge((1.0, 3), c, d).unwrap();
+        assert_eq!(route(&graph, a, d), Some(vec![(1.0, 1), (1.0, 2), (1.0, 3)]));
+        assert_eq!(route(&graph, a, c), Some(vec![(1.0, 1), (1.0, 2)]));
+    }
+
+    #[test]
+    fn unreachable_targets_have_no_path() {
+        let mut graph = Graph::default();
+        let [a, b, c, d] = nodes(&mut graph);
+        graph.add_edge((1.0, 0), a, b).unwrap();
+        graph.add_edge((1.0, 0), c, a).unwrap();
+        assert_eq!(route(&graph, a, c), None);
+        assert_eq!(route(&graph, a, d), None);
+        assert_eq!(route(&graph, b, a), None);
+    }
+
+    #[test]
+    fn routing_to_the_start_is_an_empty_path() {
+        let mut graph = Graph::default();
+        let [a, b, ..] = nodes(&mut graph);
+        graph.add_edge((1.0, 0), a, b).unwrap();
+        graph.add_edge((1.0, 0), b, a).unwrap();
+        assert_eq!(route(&graph, a, a), Some(vec![]));
+    }
+
+    #[test]
+    fn negative_costs_are_free() {
+        let mut graph = Graph::default();
+        let [a, b, c, _] = nodes(&mut graph);
+        graph.add_edge((0.5, 2), a, c).unwrap();
+        graph.add_edge((-10.0, 0), a, b).unwrap();
+        graph.add_edge((0.5, 1), b, c).unwrap();
+        // Both routes cost 0.5, rather than the one through b coming out at -9.5.
+        assert_eq!(route(&graph, a, c), Some(vec![(0.5, 2)]));
+        assert_eq!(route(&graph, a, b), Some(vec![(-10.0, 0)]));
+    }
+
+    #[test]
+    fn equal_costs_arrive_through_the_highest_priority_edge() {
+        for priorities in [(1, 2), (2, 1)] {
+            let mut graph = Graph::default();
+            let [a, b, c, d] = nodes(&mut graph);
+            graph.add_edge((1.0, 0), a, b).unwrap();
+            graph.add_edge((1.0, 0), a, c).unwrap();
+            graph.add_edge((1.0, priorities.0), b, d).unwrap();
+            graph.add_edge((1.0, priorities.1), c, d).unwrap();
+            let path = route(&graph, a, d).unwrap();
+            assert_eq!(path.last(), Some(&(1.0, 2)));
+        }
+    }
+
+    #[test]
+    fn the_tree_holds_every_reachable_node() {
+        let mut graph = Graph::default();
+        let [a, b, c, d] = nodes(&mut graph);
+        graph.add_edge((1.0, 0), a, b).unwrap();
+        graph.add_edge((1.0, 0), b, c).unwrap();
+        let tree = shortest_path_tree(&graph, a, None, |_, e| e.0, |x, y| y.1.cmp(&x.1));
+        assert!(tree.contains_key(&b) && tree.contains_key(&c));
+        assert!(!tree.contains_key(&a) && !tree.contains_key(&d));
+        assert_eq!(path_in_tree(&graph, &tree, a, c).map(|p| p.len()), Some(2));
+    }
+}
@@ -0,0 +1,64 @@
+use ozz_animation_rs::*;
+use std::ops::{Deref, DerefMut};
+
+// The pointer, buffer and job types the runtime is built on. They are Rc and RefCell based by default. With the
+// "multi-threaded" feature they are Arc and RwLock based instead, so that an AnimGraph can be sent to another thread.
+
+#[cfg(not(feature = "multi-threaded"))]
+pub type Shared<T> = std::rc::Rc<T>;
+#[cfg(feature = "multi-threaded")]
+pub type Shared<T> = std::sync::Arc<T>;
+
+#[cfg(not(feature = "multi-threaded"))]
+pub type Buffer<T> = std::rc::Rc<std::cell::RefCell<Vec<T>>>;
+#[cfg(feature = "multi-threaded")]
+pub type Buffer<T> = std::sync::Arc<std::sync::RwLock<Vec<T>>>;
+
+#[cfg(not(feature = "multi-threaded"))]
+pub type SharedSamplingJob = SamplingJobRc;
+#[cfg(feature = "multi-threaded")]
+pub type SharedSamplingJob = SamplingJobArc;
+
+#[cfg(not(feature = "multi-threaded"))]
+pub type SharedBlendingJob = BlendingJobRc;
+#[cfg(feature = "multi-threaded")]
+pub type SharedBlendingJob = BlendingJobArc;
+
+#[cfg(not(feature = "multi-threaded"))]
+pub type SharedLocalToModelJob = LocalToModelJobRc;
+#[cfg(feature = "multi-threaded")]
+pub type SharedLocalToModelJob = LocalToModelJobArc;
+
+pub fn new_buffer<T>(contents: Vec<T>) -> Buffer<T> {
+    Shared::new(contents.into())
+}
+
+// Borrows a buffer the same way whichever types it is built on.
+pub trait BufferAccess<T> {
+    fn read_buf(&self) -> impl Deref<Target = Vec<T>> + '_;
+    fn write_buf(&self) -> impl DerefMut<Target = Vec<T>> + '_;
+}
+
+#[cfg(not(feature = "multi-threaded"))]
+impl<T> BufferAccess<T> for Buffer<T> {
+    fn read_buf(&self) -> impl Deref<Target = Vec<T>> + '_ {
+        self.borrow()
+    }
+
+    fn write_buf(&self) -> impl DerefMut<Target = Vec<T>> + '_ {
+        self.borrow_mut()
+    }
+}
+
+// A buffer is only ever poisoned by a panic halfway through writing a pose, which leaves nothing worse than a torn
+// pose behind.
+#[cfg(feature = "multi-threaded")]
+impl<T> BufferAccess<T> for Buffer<T> {
+    fn read_buf(&self) -> impl Deref<Target = Vec<T>> + '_ {
+        self.read().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn write_buf(&self) -> impl DerefMut<Target = Vec<T>> + '_ {
+        self.write().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
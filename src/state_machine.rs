@@ -0,0 +1,1488 @@
+use crate::animgraph_definition::DefinitionGraph;
+use crate::edge_definitions::{AnyStateTransitionDefinition, TransitionMode};
+use crate::edges::*;
+use crate::event_definitions::AnimationEventDefinition;
+use crate::events::*;
+use crate::export::*;
+use crate::inertialization::PoseHistory;
+use crate::node_definitions::*;
+use crate::nodes::*;
+use crate::parameters::*;
+use crate::root_motion::*;
+use crate::routing::{path_in_tree, shortest_path_tree};
+use crate::shared::*;
+use anyhow::anyhow;
+use mapgraph::aliases::SlotMapGraph;
+use mapgraph::map::slotmap::EdgeIndex;
+use mapgraph::map::slotmap::NodeIndex;
+use ozz_animation_rs::*;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+// What a StateMachineLayout resolves its definition against.
+pub struct LayoutResources<'a> {
+    pub skeleton: &'a Shared<Skeleton>,
+    pub animations_by_name: &'a HashMap<String, Shared<Animation>>,
+    // Parameter indices are the same on every blackboard built from the same definitions.
+    pub blackboard: &'a Blackboard,
+    pub root_motion: Option<RootMotionSettings>,
+}
+
+impl LayoutResources<'_> {
+    fn animation(&self, name: &str) -> Result<Shared<Animation>, anyhow::Error> {
+        match self.animations_by_name.get(name) {
+            Some(val) => Ok(val.clone()),
+            None => Err(anyhow!("Unknown animation: {}", name)),
+        }
+    }
+
+    fn clip(
+        &self,
+        node: &str,
+        animation_name: &str,
+        events: &[AnimationEventDefinition],
+    ) -> Result<Shared<ClipLayout>, anyhow::Error> {
+        Ok(Shared::new(ClipLayout::new(
+            self.skeleton,
+            self.animation(animation_name)?,
+            EventTrack::new(node, events),
+            self.root_motion,
+        )))
+    }
+
+    fn param_idx(&self, param_name: &Option<String>) -> Option<ParameterIndex> {
+        param_name.as_ref().and_then(|name| self.blackboard.get_parameter_by_name(name))
+    }
+}
+
+// The parts of a state machine that don't change as it plays: its graph, resolved nodes and transitions, node names,
+// transition order and routes. Built once per definition and shared by every StateMachine playing it.
+pub struct StateMachineLayout {
+    graph: SlotMapGraph<GenericNode, TransitionIndex>,
+    // Under the same indices as the nodes of each StateMachine.
+    samplers: SamplerNodesContainer<SamplerLayout>,
+    blend_trees_one_dim: BlendTreeOneDimNodesContainer<Shared<BlendTreeOneDimLayout>>,
+    blend_spaces_two_dim: BlendSpaceTwoDimNodesContainer<Shared<BlendSpaceTwoDimLayout>>,
+    additives: AdditiveNodesContainer<Shared<AdditiveLayout>>,
+    state_machines: StateMachineNodesContainer<Shared<StateMachineLayout>>,
+    transitions: TransitionsContainer<TransitionLayout>,
+    // The edge each transition sits on.
+    transition_edges: TransitionsContainer<EdgeIndex>,
+    // Whether some transition is inertialized, in which case each StateMachine keeps a pose history.
+    inertialized: bool,
+    root_node_idx: NodeIndex,
+    node_names: HashMap<String, NodeIndex>,
+    // Each node's own transitions, in transition_order().
+    outgoing: HashMap<NodeIndex, Vec<EdgeIndex>>,
+    // By descending priority.
+    any_state_transitions: Vec<AnyStateTransition>,
+    // The states a nested machine may leave from on its own. Any state when empty.
+    exits: Vec<NodeIndex>,
+    // For each node, the edge every node reachable from it is best arrived through.
+    routes: HashMap<NodeIndex, HashMap<NodeIndex, EdgeIndex>>,
+}
+
+impl StateMachineLayout {
+    pub fn new(
+        resources: &LayoutResources,
+        graph_definition: &DefinitionGraph,
+        root: Option<NodeIndex>,
+        any_state_transitions: &[AnyStateTransitionDefinition],
+        exits: &[String],
+    ) -> Result<Self, anyhow::Error> {
+        let mut graph = SlotMapGraph::<GenericNode, TransitionIndex>::with_capacities(
+            graph_definition.nodes_count(),
+            graph_definition.edges_count(),
+        );
+        let mut samplers = SamplerNodesContainer::<SamplerLayout>::new();
+        let mut blend_trees_one_dim = BlendTreeOneDimNodesContainer::<Shared<BlendTreeOneDimLayout>>::new();
+        let mut blend_spaces_two_dim = BlendSpaceTwoDimNodesContainer::<Shared<BlendSpaceTwoDimLayout>>::new();
+        let mut additives = AdditiveNodesContainer::<Shared<AdditiveLayout>>::new();
+        let mut state_machines = StateMachineNodesContainer::<Shared<StateMachineLayout>>::new();
+        // Go over each node in the definition and add it to the final graph, saving its definition node/final node pair in a map
+        let mut node_mappings = HashMap::<NodeIndex, NodeIndex>::new();
+        let mut node_names = HashMap::<String, NodeIndex>::new();
+        for (node_definition_idx, node_definition) in graph_definition.node_weights() {
+            let node = match node_definition {
+                GenericNodeDefinition::Sampler(val) => GenericNode::Sampler(samplers.push(SamplerLayout {
+                    clip: resources.clip(&val.name, &val.animation_name, &val.events)?,
+                    looping: val.looping,
+                    speed: val.speed,
+                })),
+                GenericNodeDefinition::BlendTreeOneDim(val) => {
+                    let mut clips = Vec::<(Shared<ClipLayout>, f32)>::new();
+                    for clip in &val.clips {
+                        clips.push((resources.clip(&val.name, &clip.animation_name, &clip.events)?, clip.threshold));
+                    }
+                    let layout = BlendTreeOneDimLayout::new(clips, resources.param_idx(&val.param_name), val.sync_phase);
+                    GenericNode::BlendTreeOneDim(blend_trees_one_dim.push(Shared::new(layout)))
+                }
+                GenericNodeDefinition::BlendSpaceTwoDim(val) => {
+                    let mut clips = Vec::<Shared<ClipLayout>>::new();
+                    for clip in &val.clips {
+                        clips.push(resources.clip(&val.name, &clip.animation_name, &clip.events)?);
+                    }
+                    let layout = BlendSpaceTwoDimLayout {
+                        clips,
+                        positions: val.clips.iter().map(|clip| clip.position).collect(),
+                        mode: val.mode,
+                        param_idx: resources.param_idx(&val.param_name),
+                        sync_phase: val.sync_phase,
+                    };
+                    GenericNode::BlendSpaceTwoDim(blend_spaces_two_dim.push(Shared::new(layout)))
+                }
+                GenericNodeDefinition::Additive(val) => {
+                    let reference = match &val.reference {
+                        AdditiveReference::FirstFrame => None,
+                        AdditiveReference::Animation(reference) => Some(resources.animation(reference)?),
+                    };
+                    // Additive layers don't move the character.
+                    let clip = ClipLayout::new(
+                        resources.skeleton,
+                        resources.animation(&val.animation_name)?,
+                        EventTrack::new(&val.name, &val.events),
+                        None,
+                    );
+                    let sampler = SamplerLayout {
+                        clip: Shared::new(clip),
+                        looping: val.looping,
+                        speed: 1.0,
+                    };
+                    let layout = AdditiveLayout::new(resources.skeleton, sampler, reference);
+                    GenericNode::Additive(additives.push(Shared::new(layout)))
+                }
+                GenericNodeDefinition::StateMachine(val) => {
+                    let (nested_graph, entry) = val.to_graph()?;
+                    let layout = StateMachineLayout::new(
+                        resources,
+                        &nested_graph,
+                        Some(entry),
+                        &val.any_state_transitions,
+                        &val.exits,
+                    )?;
+                    GenericNode::StateMachine(state_machines.push(Shared::new(layout)))
+                }
+            };
+            let node_idx = graph.add_node(node);
+            node_names.insert(node_definition.name().to_string(), node_idx);
+            node_mappings.insert(node_definition_idx, node_idx);
+        }
+
+        let mut transitions = TransitionsContainer::<TransitionLayout>::new();
+        let mut transition_edges = TransitionsContainer::<EdgeIndex>::new();
+        // Go over each edge in the definition and add it to the final graph, using the node mapping to find the appropriate final node.
+        for (edge_definition_idx, _) in graph_definition.edge_weights() {
+            let edge_definition = graph_definition.edge(edge_definition_idx);
+            match edge_definition {
+                Some(val) => {
+                    if !node_mappings.contains_key(&val.from()) {
+                        return Err(anyhow!("Invalid \"from\" node in edge"));
+                    }
+                    if !node_mappings.contains_key(&val.to()) {
+                        return Err(anyhow!("Invalid \"to\" node in edge"));
+                    }
+                    let from_idx = node_mappings[&val.from()];
+                    let to_idx = node_mappings[&val.to()];
+                    let transition_idx = transitions.push(TransitionLayout::new(val.weight(), resources.blackboard)?);
+                    transition_edges.push(graph.add_edge(transition_idx, from_idx, to_idx)?);
+                }
+                None => {
+                    return Err(anyhow!("Invalid edge found in graph definition"));
+                }
+            }
+        }
+        let mut outgoing = HashMap::<NodeIndex, Vec<EdgeIndex>>::new();
+        for (edge_idx, _) in graph.edge_weights() {
+            outgoing.entry(graph.edge(edge_idx).unwrap().from()).or_default().push(edge_idx);
+        }
+        for edges in outgoing.values_mut() {
+            edges.sort_by(|a, b| {
+                let (a, b) = (*graph.edge(*a).unwrap().weight(), *graph.edge(*b).unwrap().weight());
+                transition_order(&transitions[a], &transitions[b])
+            });
+        }
+        let root_node_idx = match root.and_then(|val| node_mappings.get(&val)) {
+            Some(val) => *val,
+            None => return Err(anyhow!("No root node found in animgraph definition")),
+        };
+
+        let mut layout = StateMachineLayout {
+            graph,
+            samplers,
+            blend_trees_one_dim,
+            blend_spaces_two_dim,
+            additives,
+            state_machines,
+            transitions,
+            transition_edges,
+            inertialized: false,
+            root_node_idx,
+            node_names,
+            outgoing,
+            any_state_transitions: Vec::new(),
+            exits: Vec::new(),
+            routes: HashMap::new(),
+        };
+        layout.add_any_state_transitions(any_state_transitions, resources.blackboard)?;
+        layout.inertialized = layout.transitions.iter().any(|t| t.mode != TransitionMode::CrossFade);
+        for exit in exits {
+            match layout.node_names.get(exit) {
+                Some(val) => layout.exits.push(*val),
+                // Dropping it would leave the machine free to exit from states it wasn't meant to.
+                None => return Err(anyhow!("Unknown exit state: {}", exit)),
+            }
+        }
+        layout.find_routes();
+        Ok(layout)
+    }
+
+    pub fn root_node(&self) -> NodeIndex {
+        self.root_node_idx
+    }
+
+    pub fn node_by_name(&self, node_name: &str) -> Option<NodeIndex> {
+        self.node_names.get(node_name).copied()
+    }
+
+    // The cheapest route between two nodes, as a chain of edges.
+    pub fn route(&self, from: NodeIndex, to: NodeIndex) -> Option<VecDeque<EdgeIndex>> {
+        path_in_tree(&self.graph, self.routes.get(&from)?, from, to)
+    }
+
+    // Adds transitions that may leave from any node but the excluded ones and their destination.
+    fn add_any_state_transitions(
+        &mut self,
+        definitions: &[AnyStateTransitionDefinition],
+        blackboard: &Blackboard,
+    ) -> Result<(), anyhow::Error> {
+        let node_indices = self.graph.node_weights().map(|(node_idx, _)| node_idx).collect::<Vec<NodeIndex>>();
+        // Declared after the graph's own transitions, in the order they are listed.
+        let own_transitions = self.transitions.len();
+        for (i, definition) in definitions.iter().enumerate() {
+            let to = match self.node_names.get(&definition.to) {
+                Some(val) => *val,
+                None => return Err(anyhow!("Any-state transition to unknown node {}", definition.to)),
+            };
+            let mut excluded = vec![to];
+            for name in &definition.exclude {
+                match self.node_names.get(name) {
+                    Some(val) => excluded.push(*val),
+                    None => return Err(anyhow!("Any-state transition excludes unknown node {}", name)),
+                }
+            }
+            let mut edges = HashMap::<NodeIndex, EdgeIndex>::new();
+            for from in node_indices.iter().copied().filter(|n| !excluded.contains(n)) {
+                let mut transition = TransitionLayout::new(&definition.transition, blackboard)?;
+                transition.declared = own_transitions + i;
+                let transition_idx = self.transitions.push(transition);
+                let edge_idx = self.graph.add_edge(transition_idx, from, to)?;
+                self.transition_edges.push(edge_idx);
+                edges.insert(from, edge_idx);
+            }
+            self.any_state_transitions.push(AnyStateTransition {
+                priority: definition.transition.priority,
+                during_transitions: definition.during_transitions,
+                edges,
+            });
+        }
+        // Stable, so that ties keep their declaration order as in transition_order().
+        self.any_state_transitions.sort_by_key(|t| std::cmp::Reverse(t.priority));
+        Ok(())
+    }
+
+    // Routing only depends on the transitions' costs, which never change, so every route is found up front.
+    fn find_routes(&mut self) {
+        let mut routes = HashMap::<NodeIndex, HashMap<NodeIndex, EdgeIndex>>::new();
+        for (from, _) in self.graph.node_weights() {
+            let tree = shortest_path_tree(
+                &self.graph,
+                from,
+                None,
+                |_, transition_idx| self.transitions[*transition_idx].cost,
+                |a, b| transition_order(&self.transitions[*a], &self.transitions[*b]),
+            );
+            routes.insert(from, tree);
+        }
+        self.routes = routes;
+    }
+}
+
+// A graph of nodes and the transitions between them, playing one node at a time or blending across a transition.
+// The AnimGraph runs one for its base and one per layer, and state machine nodes nest one inside another. Only the
+// playback state is its own, the rest is in its layout.
+pub struct StateMachine {
+    layout: Shared<StateMachineLayout>,
+    samplers: SamplerNodesContainer<SamplerNode>,
+    blend_trees_one_dim: BlendTreeOneDimNodesContainer<BlendTreeOneDimNode>,
+    blend_spaces_two_dim: BlendSpaceTwoDimNodesContainer<BlendSpaceTwoDimNode>,
+    additives: AdditiveNodesContainer<AdditiveNode>,
+    state_machines: StateMachineNodesContainer<StateMachine>,
+    transitions: TransitionsContainer<Transition>,
+    current_node_idx: Option<NodeIndex>,
+    current_edge_idx: Option<EdgeIndex>,
+    target: NodeIndex,
+    on_a_transition: bool,
+    path: VecDeque<EdgeIndex>,
+    // A route requested before the parent machine got here, taken on entry.
+    pending_target: Option<String>,
+    // Fired during the last evaluate().
+    events: Vec<AnimationEvent>,
+    // Root motion of the last evaluate().
+    root_motion: RootMotion,
+    // Only kept when some transition is inertialized.
+    pose_history: Option<PoseHistory>,
+    // The pose an interrupted transition had reached, which the interrupting transition blends from.
+    snapshot: Buffer<SoaTransform>,
+    // Either the current node's output or the current transition's.
+    output: Buffer<SoaTransform>,
+    // A copy of the output that stays in the same buffer, for the transitions of a parent machine to blend from.
+    pose: Buffer<SoaTransform>,
+}
+
+impl StateMachine {
+    pub fn new(skeleton: Shared<Skeleton>, layout: Shared<StateMachineLayout>) -> Self {
+        let samplers = SamplerNodesContainer::<SamplerNode>::from(
+            layout.samplers.iter().map(|val| SamplerNode::new(skeleton.clone(), val)).collect::<Vec<_>>(),
+        );
+        let blend_trees_one_dim = BlendTreeOneDimNodesContainer::<BlendTreeOneDimNode>::from(
+            layout
+                .blend_trees_one_dim
+                .iter()
+                .map(|val| BlendTreeOneDimNode::new(skeleton.clone(), val.clone()))
+                .collect::<Vec<_>>(),
+        );
+        let blend_spaces_two_dim = BlendSpaceTwoDimNodesContainer::<BlendSpaceTwoDimNode>::from(
+            layout
+                .blend_spaces_two_dim
+                .iter()
+                .map(|val| BlendSpaceTwoDimNode::new(skeleton.clone(), val.clone()))
+                .collect::<Vec<_>>(),
+        );
+        let additives = AdditiveNodesContainer::<AdditiveNode>::from(
+            layout.additives.iter().map(|val| AdditiveNode::new(skeleton.clone(), val.clone())).collect::<Vec<_>>(),
+        );
+        let state_machines = StateMachineNodesContainer::<StateMachine>::from(
+            layout
+                .state_machines
+                .iter()
+                .map(|val| StateMachine::new(skeleton.clone(), val.clone()))
+                .collect::<Vec<_>>(),
+        );
+
+        let output_of = |node_idx: NodeIndex| match layout.graph.node(node_idx).unwrap().weight() {
+            GenericNode::Sampler(val) => samplers[*val].output.clone(),
+            GenericNode::BlendTreeOneDim(val) => blend_trees_one_dim[*val].output.clone(),
+            GenericNode::BlendSpaceTwoDim(val) => blend_spaces_two_dim[*val].output.clone(),
+            GenericNode::Additive(val) => additives[*val].output.clone(),
+            GenericNode::StateMachine(val) => state_machines[*val].pose.clone(),
+        };
+        let mut transitions = TransitionsContainer::<Transition>::new();
+        for (transition_idx, transition_layout) in layout.transitions.index_iter() {
+            let edge = layout.graph.edge(layout.transition_edges[transition_idx]).unwrap();
+            transitions.push(Transition::new(
+                skeleton.clone(),
+                transition_layout,
+                output_of(edge.from()),
+                output_of(edge.to()),
+            ));
+        }
+        let pose_history = if layout.inertialized {
+            Some(PoseHistory::new(skeleton.num_soa_joints()))
+        } else {
+            None
+        };
+        let root_node_idx = layout.root_node_idx;
+        let output = output_of(root_node_idx);
+
+        StateMachine {
+            layout,
+            samplers,
+            blend_trees_one_dim,
+            blend_spaces_two_dim,
+            additives,
+            state_machines,
+            transitions,
+            current_node_idx: Some(root_node_idx),
+            current_edge_idx: None,
+            target: root_node_idx,
+            on_a_transition: false,
+            path: VecDeque::<EdgeIndex>::new(),
+            pending_target: None,
+            events: Vec::new(),
+            root_motion: NO_ROOT_MOTION,
+            pose_history,
+            snapshot: new_buffer(vec![SoaTransform::default(); skeleton.num_soa_joints()]),
+            output,
+            pose: new_buffer(vec![SoaTransform::default(); skeleton.num_soa_joints()]),
+        }
+    }
+
+    pub fn evaluate(&mut self, dt: web_time::Duration, blackboard: &mut Blackboard) -> Result<(), anyhow::Error> {
+        self.events.clear();
+        // Any-state transitions take over from whatever we are doing, queued routes included.
+        if let Some(edge_idx) = self.find_any_state_transition(blackboard) {
+            let edge = self.layout.graph.edge(edge_idx).unwrap();
+            let transition_idx = *edge.weight();
+            self.layout.transitions[transition_idx].consume_triggers(blackboard);
+            self.target = edge.to();
+            self.path.clear();
+            if self.on_a_transition {
+                self.interrupt_transition(edge_idx);
+            } else {
+                self.begin_transition(edge_idx);
+            }
+        }
+        // When resting on a node, either start the next leg of a requested route or fire the first automatic transition whose conditions hold.
+        if !self.on_a_transition {
+            match self.current_node_idx {
+                Some(val) => {
+                    if let Some(edge_idx) = self.path.pop_front() {
+                        self.begin_transition(edge_idx);
+                    } else if let Some(edge_idx) = self.find_automatic_transition(val, blackboard) {
+                        let transition_idx = *self.layout.graph.edge(edge_idx).unwrap().weight();
+                        self.layout.transitions[transition_idx].consume_triggers(blackboard);
+                        self.target = self.layout.graph.edge(edge_idx).unwrap().to();
+                        self.begin_transition(edge_idx);
+                    }
+                }
+                None => return Err(anyhow! {"Invalid current node during evaluation."}),
+            }
+        }
+        // Handle the transition case
+        if self.on_a_transition {
+            match self.current_edge_idx {
+                Some(val) => {
+                    let transition_idx = *self.layout.graph.edge(val).unwrap().weight();
+                    // Advance the transition and check whether or not it is finished.
+                    self.transitions[transition_idx].seek += dt;
+                    // If finished, move onto the next leg of the path, or arrive at the destination node.
+                    if self.transitions[transition_idx].is_finished(&self.layout.transitions[transition_idx]) {
+                        self.finish_transition();
+                    }
+                }
+                None => return Err(anyhow! {"Invalid current transition during evaluation."}),
+            }
+        }
+        if self.on_a_transition && self.is_inertializing() {
+            let edge = self.layout.graph.edge(self.current_edge_idx.unwrap()).unwrap();
+            let transition_idx = *edge.weight();
+            let to = edge.to();
+            self.update_node(to, dt, blackboard)?;
+            self.inertialize(transition_idx, to);
+            self.root_motion = self.node_root_motion(to);
+        } else if self.on_a_transition {
+            let edge = self.layout.graph.edge(self.current_edge_idx.unwrap()).unwrap();
+            let transition_idx = *edge.weight();
+            let (from, to) = (edge.from(), edge.to());
+            // A snapshot stays frozen, and has no motion of its own.
+            let from_snapshot = self.transitions[transition_idx].is_from_snapshot();
+            if !from_snapshot {
+                self.update_node(from, dt, blackboard)?;
+            }
+            self.update_node(to, dt, blackboard)?;
+            // Set the blend layer weights based on the normalized time elapsed.
+            self.transitions[transition_idx].update_weights(&self.layout.transitions[transition_idx]);
+            let from_motion = if from_snapshot {
+                NO_ROOT_MOTION
+            } else {
+                self.node_root_motion(from)
+            };
+
+            // Root motion follows the same weights as the poses.
+            let layers = self.transitions[transition_idx].blend_job.layers();
+            let (from_weight, to_weight) = (layers[0].weight, layers[1].weight);
+            let total = from_weight + to_weight;
+            self.root_motion = if total > 0.0 {
+                blend_root_motion([
+                    (from_weight / total, from_motion),
+                    (to_weight / total, self.node_root_motion(to)),
+                ])
+            } else {
+                self.node_root_motion(to)
+            };
+
+            let results = self.transitions[transition_idx].blend_job.run();
+            match results {
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(anyhow! {"Ozz error during transition blend: {}", e});
+                }
+            }
+        } else {
+            // If we are on a node. Far simpler to evaluate
+            let node_idx = self.current_node_idx.unwrap();
+            self.update_node(node_idx, dt, blackboard)?;
+            self.root_motion = self.node_root_motion(node_idx);
+        }
+        if let Some(pose_history) = &mut self.pose_history {
+            pose_history.record(&self.output.read_buf(), dt);
+        }
+
+        Ok(())
+    }
+
+    // The pose of the last evaluate().
+    pub fn output(&self) -> Buffer<SoaTransform> {
+        self.output.clone()
+    }
+
+    pub fn fired_events(&self) -> &[AnimationEvent] {
+        &self.events
+    }
+
+    pub fn root_motion(&self) -> RootMotion {
+        self.root_motion
+    }
+
+    pub fn layout(&self) -> &Shared<StateMachineLayout> {
+        &self.layout
+    }
+
+    pub fn root_node(&self) -> NodeIndex {
+        self.layout.root_node()
+    }
+
+    pub fn node_by_name(&self, node_name: &str) -> Option<NodeIndex> {
+        self.layout.node_by_name(node_name)
+    }
+
+    // Routes to a node by name, or to a node of a nested state machine by path, e.g. "combat/attack_1". The nested
+    // machine's route starts once we get to it.
+    pub fn set_target_node_by_name(&mut self, path: &str) -> Result<(), anyhow::Error> {
+        let (node_idx, rest) = self.split_path(path)?;
+        let rest = match rest {
+            Some(val) => val,
+            None => return self.route_to(node_idx),
+        };
+        let state_machine_idx = self.nested_state_machine(node_idx, path)?;
+        if self.destination() == node_idx {
+            self.route_to(node_idx)?;
+            self.state_machines[state_machine_idx].set_target_node_by_name(rest)
+        } else {
+            self.state_machines[state_machine_idx].check_path(rest)?;
+            // Set before routing, as an interrupted transition may enter the nested machine straight away.
+            let previous = self.state_machines[state_machine_idx].pending_target.replace(rest.to_string());
+            if let Err(err) = self.route_to(node_idx) {
+                self.state_machines[state_machine_idx].pending_target = previous;
+                return Err(err);
+            }
+            Ok(())
+        }
+    }
+
+    fn check_path(&self, path: &str) -> Result<(), anyhow::Error> {
+        let (node_idx, rest) = self.split_path(path)?;
+        match rest {
+            Some(val) => self.state_machines[self.nested_state_machine(node_idx, path)?].check_path(val),
+            None => Ok(()),
+        }
+    }
+
+    // The node named by the first segment of `path`, and the rest of the path if any.
+    fn split_path<'a>(&self, path: &'a str) -> Result<(NodeIndex, Option<&'a str>), anyhow::Error> {
+        let (name, rest) = match path.split_once('/') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (path, None),
+        };
+        match self.layout.node_names.get(name) {
+            Some(val) => Ok((*val, rest)),
+            None => Err(anyhow!("Unknown node: {}", name)),
+        }
+    }
+
+    fn nested_state_machine(&self, node_idx: NodeIndex, path: &str) -> Result<StateMachineNodeIndex, anyhow::Error> {
+        match self.layout.graph.node(node_idx).unwrap().weight() {
+            GenericNode::StateMachine(val) => Ok(*val),
+            _ => Err(anyhow!("Not a state machine in node path: {}", path)),
+        }
+    }
+
+    // The machine a node path leads to, and the name of the node in it.
+    fn resolve_path_mut<'a>(&mut self, path: &'a str) -> Option<(&mut StateMachine, &'a str)> {
+        match path.split_once('/') {
+            Some((name, rest)) => {
+                let node_idx = *self.layout.node_names.get(name)?;
+                match self.layout.graph.node(node_idx)?.weight() {
+                    GenericNode::StateMachine(val) => self.state_machines[*val].resolve_path_mut(rest),
+                    _ => None,
+                }
+            }
+            None => Some((self, path)),
+        }
+    }
+
+    // Where we are, or where the current transition lands.
+    fn destination(&self) -> NodeIndex {
+        match self.current_node_idx {
+            Some(val) => val,
+            None => self.layout.graph.edge(self.current_edge_idx.unwrap()).unwrap().to(),
+        }
+    }
+
+    // Back to the entry state, as when a parent machine enters this one.
+    pub fn reset(&mut self) {
+        if let Some(edge_idx) = self.current_edge_idx {
+            let transition_idx = *self.layout.graph.edge(edge_idx).unwrap().weight();
+            self.transitions[transition_idx].reset();
+        }
+        self.current_node_idx = Some(self.layout.root_node_idx);
+        self.current_edge_idx = None;
+        self.on_a_transition = false;
+        self.target = self.layout.root_node_idx;
+        self.path.clear();
+        self.root_motion = NO_ROOT_MOTION;
+        self.reset_node(self.layout.root_node_idx);
+        self.output = self.node_output(self.layout.root_node_idx);
+        if let Some(val) = self.pending_target.take() {
+            // Checked when it was requested.
+            let _ = self.set_target_node_by_name(&val);
+        }
+    }
+
+    // Whether the transitions leaving this machine in its parent may fire on their own.
+    fn can_exit(&self) -> bool {
+        match self.current_node_idx {
+            Some(val) if !self.on_a_transition => self.layout.exits.is_empty() || self.layout.exits.contains(&val),
+            _ => false,
+        }
+    }
+
+    fn current_exit_time_reached(&self, exit_time: f32) -> bool {
+        match self.current_node_idx {
+            Some(val) if !self.on_a_transition => self.exit_time_reached(val, exit_time),
+            _ => false,
+        }
+    }
+
+    // Replaces the queued path with the cheapest route to `target`. While on a transition, the route starts from
+    // wherever that transition lands, and cuts it short if it is interruptible.
+    pub fn route_to(&mut self, target: NodeIndex) -> Result<(), anyhow::Error> {
+        if self.layout.graph.node(target).is_none() {
+            return Err(anyhow!("Invalid target node"));
+        }
+        let start = self.destination();
+        let path = self.layout.route(start, target);
+        match path {
+            Some(val) => {
+                self.path = val;
+                self.target = target;
+                self.clear_pending_targets(Some(target));
+                if self.can_interrupt() && !self.path.is_empty() {
+                    let edge_idx = self.path.pop_front().unwrap();
+                    self.interrupt_transition(edge_idx);
+                }
+                Ok(())
+            }
+            None => Err(anyhow!("Target node is unreachable from the current node")),
+        }
+    }
+
+    // Drops the routes requested into nested machines, but for the one at `keep`. A new route makes them stale, and
+    // they would otherwise be taken on some later, unrelated entry.
+    fn clear_pending_targets(&mut self, keep: Option<NodeIndex>) {
+        let kept = match keep.and_then(|node_idx| self.layout.graph.node(node_idx)).map(|node| node.weight()) {
+            Some(GenericNode::StateMachine(val)) => Some(*val),
+            _ => None,
+        };
+        for (state_machine_idx, state_machine) in self.state_machines.index_iter_mut() {
+            if Some(state_machine_idx) != kept {
+                state_machine.pending_target = None;
+                state_machine.clear_pending_targets(None);
+            }
+        }
+    }
+
+    pub fn set_blend_param_by_name(&mut self, path: &str, param: f32) {
+        if let Some((state_machine, node_name)) = self.resolve_path_mut(path)
+            && state_machine.layout.node_names.contains_key(node_name)
+        {
+            let node_idx = state_machine.layout.node_names[node_name];
+            if let GenericNode::BlendTreeOneDim(val) = state_machine.layout.graph.node(node_idx).unwrap().weight() {
+                state_machine.blend_trees_one_dim[*val].param = param;
+            }
+        }
+    }
+
+    pub fn set_blend_param_2d_by_name(&mut self, path: &str, param: glam::Vec2) {
+        if let Some((state_machine, node_name)) = self.resolve_path_mut(path)
+            && state_machine.layout.node_names.contains_key(node_name)
+        {
+            let node_idx = state_machine.layout.node_names[node_name];
+            if let GenericNode::BlendSpaceTwoDim(val) = state_machine.layout.graph.node(node_idx).unwrap().weight() {
+                state_machine.blend_spaces_two_dim[*val].param = param;
+            }
+        }
+    }
+
+    fn is_inertializing(&self) -> bool {
+        match self.current_edge_idx {
+            Some(val) => self.transitions[*self.layout.graph.edge(val).unwrap().weight()]
+                .inertialization
+                .is_some(),
+            None => false,
+        }
+    }
+
+    // Offsets the destination's pose by what is left of the difference with the pose we left, measured on the first
+    // frame of the transition.
+    fn inertialize(&mut self, transition_idx: TransitionIndex, destination: NodeIndex) {
+        let destination_output = self.node_output(destination);
+        let destination_pose = destination_output.read_buf();
+        let duration = self.layout.transitions[transition_idx].duration.as_secs_f32();
+        let transition = &mut self.transitions[transition_idx];
+        let elapsed = transition.seek.as_secs_f32();
+        let inertialization = transition.inertialization.as_mut().unwrap();
+        if !inertialization.is_captured() {
+            inertialization.capture(self.pose_history.as_ref().unwrap(), &destination_pose);
+        }
+        inertialization.apply(&destination_pose, &mut transition.output.write_buf(), duration, elapsed);
+    }
+
+    // Takes the first any-state transition that can fire from the current node, or from where the current transition
+    // lands for those that may fire during transitions. Nested machines can be left from any of their states.
+    fn find_any_state_transition(&self, blackboard: &Blackboard) -> Option<EdgeIndex> {
+        let from = self.destination();
+        for any_state in &self.layout.any_state_transitions {
+            if self.on_a_transition && !(any_state.during_transitions && self.can_snapshot()) {
+                continue;
+            }
+            let edge_idx = match any_state.edges.get(&from) {
+                Some(val) => *val,
+                None => continue,
+            };
+            let transition = &self.layout.transitions[*self.layout.graph.edge(edge_idx).unwrap().weight()];
+            let exit_time_reached = match (transition.exit_time, self.current_node_idx) {
+                (Some(exit_time), Some(node_idx)) => self.exit_time_reached(node_idx, exit_time),
+                _ => false,
+            };
+            if transition.can_fire(blackboard, exit_time_reached) {
+                return Some(edge_idx);
+            }
+        }
+        None
+    }
+
+    // Takes the first outgoing transition of `node_idx`, in transition_order(), whose exit time and conditions currently
+    // hold.
+    fn find_automatic_transition(&self, node_idx: NodeIndex, blackboard: &Blackboard) -> Option<EdgeIndex> {
+        if let GenericNode::StateMachine(val) = self.layout.graph.node(node_idx).unwrap().weight()
+            && !self.state_machines[*val].can_exit()
+        {
+            return None;
+        }
+        for edge_idx in self.layout.outgoing.get(&node_idx)?.iter().copied() {
+            let transition = &self.layout.transitions[*self.layout.graph.edge(edge_idx).unwrap().weight()];
+            let exit_time_reached = match transition.exit_time {
+                Some(exit_time) => self.exit_time_reached(node_idx, exit_time),
+                None => false,
+            };
+            if transition.can_fire(blackboard, exit_time_reached) {
+                return Some(edge_idx);
+            }
+        }
+        None
+    }
+
+    fn exit_time_reached(&self, node_idx: NodeIndex, exit_time: f32) -> bool {
+        match self.layout.graph.node(node_idx).unwrap().weight() {
+            GenericNode::Sampler(val) => self.samplers[*val].exit_time_reached(exit_time),
+            GenericNode::BlendTreeOneDim(val) => self.blend_trees_one_dim[*val].exit_time_reached(exit_time),
+            GenericNode::BlendSpaceTwoDim(val) => self.blend_spaces_two_dim[*val].exit_time_reached(exit_time),
+            GenericNode::Additive(val) => self.additives[*val].exit_time_reached(exit_time),
+            GenericNode::StateMachine(val) => self.state_machines[*val].current_exit_time_reached(exit_time),
+        }
+    }
+
+    // Leaves the current node through `edge_idx`, switching our output to the transition's blended one.
+    fn begin_transition(&mut self, edge_idx: EdgeIndex) {
+        let edge = self.layout.graph.edge(edge_idx).unwrap();
+        let transition_idx = *edge.weight();
+        // The destination starts playing from the beginning, so that one-shot clips can be entered more than once.
+        self.reset_node(edge.to());
+        self.transitions[transition_idx].reset();
+        self.transitions[transition_idx].started = true;
+        self.current_edge_idx = Some(edge_idx);
+        self.current_node_idx = None;
+        self.on_a_transition = true;
+        self.output = self.transitions[transition_idx].output.clone();
+    }
+
+    // A transition that hasn't been evaluated yet has no pose to snapshot.
+    fn can_snapshot(&self) -> bool {
+        match self.current_edge_idx {
+            Some(val) if self.on_a_transition => !self.transitions[*self.layout.graph.edge(val).unwrap().weight()].seek.is_zero(),
+            _ => false,
+        }
+    }
+
+    fn can_interrupt(&self) -> bool {
+        self.can_snapshot() && self.layout.transitions[*self.layout.graph.edge(self.current_edge_idx.unwrap()).unwrap().weight()].interruptible
+    }
+
+    // Freezes the pose the current transition got to and blends from it into `edge_idx`. Inertialized transitions
+    // need no snapshot, as they always start from the last output pose.
+    fn interrupt_transition(&mut self, edge_idx: EdgeIndex) {
+        self.snapshot.write_buf().copy_from_slice(&self.output.read_buf());
+        let interrupted_idx = *self.layout.graph.edge(self.current_edge_idx.unwrap()).unwrap().weight();
+        self.transitions[interrupted_idx].reset();
+        self.begin_transition(edge_idx);
+        let transition_idx = *self.layout.graph.edge(edge_idx).unwrap().weight();
+        if self.transitions[transition_idx].inertialization.is_none() {
+            self.transitions[transition_idx].start_from_snapshot(self.snapshot.clone());
+        }
+    }
+
+    fn finish_transition(&mut self) {
+        let edge = self.layout.graph.edge(self.current_edge_idx.unwrap()).unwrap();
+        let transition_idx = *edge.weight();
+        let destination = edge.to();
+        self.transitions[transition_idx].reset();
+        match self.path.pop_front() {
+            Some(val) => {
+                self.begin_transition(val);
+            }
+            None => {
+                self.current_node_idx = Some(destination);
+                self.current_edge_idx = None;
+                self.on_a_transition = false;
+                self.output = self.node_output(destination);
+            }
+        }
+    }
+
+    fn update_node(
+        &mut self,
+        node_idx: NodeIndex,
+        dt: web_time::Duration,
+        blackboard: &mut Blackboard,
+    ) -> Result<(), anyhow::Error> {
+        match self.layout.graph.node(node_idx).unwrap().weight() {
+            GenericNode::Sampler(val) => {
+                let sampler = &mut self.samplers[*val];
+                sampler.update(dt);
+                self.events.extend_from_slice(sampler.fired_events());
+            }
+            GenericNode::BlendTreeOneDim(val) => {
+                let blend_tree = &mut self.blend_trees_one_dim[*val];
+                if let Some(param) = blend_tree.layout.param_idx.and_then(|idx| blackboard.get_float(idx)) {
+                    blend_tree.param = param;
+                }
+                blend_tree.update(dt);
+                self.events.extend_from_slice(blend_tree.fired_events());
+            }
+            GenericNode::BlendSpaceTwoDim(val) => {
+                let blend_space = &mut self.blend_spaces_two_dim[*val];
+                if let Some(param) = blend_space.layout.param_idx.and_then(|idx| blackboard.get_vec2(idx)) {
+                    blend_space.param = param;
+                }
+                blend_space.update(dt);
+                self.events.extend_from_slice(blend_space.fired_events());
+            }
+            GenericNode::Additive(val) => {
+                let additive = &mut self.additives[*val];
+                additive.update(dt);
+                self.events.extend_from_slice(additive.fired_events());
+            }
+            GenericNode::StateMachine(val) => {
+                let state_machine = &mut self.state_machines[*val];
+                state_machine.evaluate(dt, blackboard)?;
+                state_machine.pose.write_buf().copy_from_slice(&state_machine.output.read_buf());
+                self.events.extend_from_slice(state_machine.fired_events());
+            }
+        }
+        Ok(())
+    }
+
+    fn node_root_motion(&self, node_idx: NodeIndex) -> RootMotion {
+        match self.layout.graph.node(node_idx).unwrap().weight() {
+            GenericNode::Sampler(val) => self.samplers[*val].root_motion(),
+            GenericNode::BlendTreeOneDim(val) => self.blend_trees_one_dim[*val].root_motion(),
+            GenericNode::BlendSpaceTwoDim(val) => self.blend_spaces_two_dim[*val].root_motion(),
+            // Additive layers don't move the character.
+            GenericNode::Additive(_) => NO_ROOT_MOTION,
+            GenericNode::StateMachine(val) => self.state_machines[*val].root_motion,
+        }
+    }
+
+    fn reset_node(&mut self, node_idx: NodeIndex) {
+        match self.layout.graph.node(node_idx).unwrap().weight() {
+            GenericNode::Sampler(val) => self.samplers[*val].reset(),
+            GenericNode::BlendTreeOneDim(val) => self.blend_trees_one_dim[*val].reset(),
+            GenericNode::BlendSpaceTwoDim(val) => self.blend_spaces_two_dim[*val].reset(),
+            GenericNode::Additive(val) => self.additives[*val].reset(),
+            GenericNode::StateMachine(val) => self.state_machines[*val].reset(),
+        }
+    }
+
+    fn node_output(&self, node_idx: NodeIndex) -> Buffer<SoaTransform> {
+        match self.layout.graph.node(node_idx).unwrap().weight() {
+            GenericNode::Sampler(val) => self.samplers[*val].output.clone(),
+            GenericNode::BlendTreeOneDim(val) => self.blend_trees_one_dim[*val].output.clone(),
+            GenericNode::BlendSpaceTwoDim(val) => self.blend_spaces_two_dim[*val].output.clone(),
+            GenericNode::Additive(val) => self.additives[*val].output.clone(),
+            GenericNode::StateMachine(val) => self.state_machines[*val].pose.clone(),
+        }
+    }
+
+    // Adds the live state of the machine to `diagram`: the current node, the transition being blended and the queued
+    // route. `group` labels the machine when there are several, and nested machines are grouped under their path.
+    // Only the machines being played show a current node.
+    pub fn append_to_diagram(
+        &self,
+        diagram: &mut Diagram,
+        group: Option<&str>,
+        playing: bool,
+        blackboard: &Blackboard,
+    ) {
+        let mut names = HashMap::<NodeIndex, &str>::new();
+        for (name, node_idx) in &self.layout.node_names {
+            names.insert(*node_idx, name);
+        }
+        let mut node_ids = HashMap::<NodeIndex, usize>::new();
+        for (node_idx, node) in self.layout.graph.node_weights() {
+            let kind = match node {
+                GenericNode::Sampler(_) => DiagramNodeKind::Sampler,
+                GenericNode::BlendTreeOneDim(_) => DiagramNodeKind::BlendTreeOneDim,
+                GenericNode::BlendSpaceTwoDim(_) => DiagramNodeKind::BlendSpaceTwoDim,
+                GenericNode::Additive(_) => DiagramNodeKind::Additive,
+                GenericNode::StateMachine(_) => DiagramNodeKind::StateMachine,
+            };
+            node_ids.insert(node_idx, diagram.nodes.len());
+            diagram.nodes.push(DiagramNode {
+                name: names.get(&node_idx).copied().unwrap_or("<unnamed>").to_string(),
+                kind,
+                root: node_idx == self.layout.root_node_idx,
+                current: playing && !self.on_a_transition && self.current_node_idx == Some(node_idx),
+                group: group.map(|g| g.to_string()),
+            });
+        }
+        let queued = self.path.iter().copied().collect::<Vec<EdgeIndex>>();
+        let active = if self.on_a_transition { self.current_edge_idx } else { None };
+        // Any-state transitions are drawn once, from a node of their own, rather than from every node.
+        let any_state_edges = self
+            .layout.any_state_transitions
+            .iter()
+            .flat_map(|t| t.edges.values().copied())
+            .collect::<Vec<EdgeIndex>>();
+        for (edge_idx, transition_idx) in self.layout.graph.edge_weights() {
+            if any_state_edges.contains(&edge_idx) {
+                continue;
+            }
+            let edge = self.layout.graph.edge(edge_idx).unwrap();
+            let transition = &self.layout.transitions[*transition_idx];
+            let conditions = transition
+                .conditions
+                .iter()
+                .map(|c| c.describe(blackboard))
+                .collect::<Vec<String>>();
+            diagram.edges.push(DiagramEdge {
+                from: node_ids[&edge.from()],
+                to: node_ids[&edge.to()],
+                label: transition_label(transition.duration, transition.priority, transition.exit_time, &conditions),
+                state: edge_state(edge_idx, active, &queued),
+            });
+        }
+        if !any_state_edges.is_empty() {
+            let any_state_id = diagram.nodes.len();
+            diagram.nodes.push(DiagramNode {
+                name: String::from("any state"),
+                kind: DiagramNodeKind::AnyState,
+                root: false,
+                current: false,
+                group: group.map(|g| g.to_string()),
+            });
+            for any_state in &self.layout.any_state_transitions {
+                let edge_idx = match any_state.edges.values().next() {
+                    Some(val) => *val,
+                    None => continue,
+                };
+                let edge = self.layout.graph.edge(edge_idx).unwrap();
+                let transition = &self.layout.transitions[*edge.weight()];
+                let conditions = transition
+                    .conditions
+                    .iter()
+                    .map(|c| c.describe(blackboard))
+                    .collect::<Vec<String>>();
+                let state = if any_state.edges.values().any(|e| active == Some(*e)) {
+                    DiagramEdgeState::Active
+                } else if any_state.edges.values().any(|e| queued.contains(e)) {
+                    DiagramEdgeState::Queued
+                } else {
+                    DiagramEdgeState::Idle
+                };
+                diagram.edges.push(DiagramEdge {
+                    from: any_state_id,
+                    to: node_ids[&edge.to()],
+                    label: transition_label(transition.duration, transition.priority, transition.exit_time, &conditions),
+                    state,
+                });
+            }
+        }
+        for (node_idx, node) in self.layout.graph.node_weights() {
+            if let GenericNode::StateMachine(val) = node {
+                let name = names.get(&node_idx).copied().unwrap_or("<unnamed>");
+                let nested_group = match group {
+                    Some(g) => format!("{}/{}", g, name),
+                    None => name.to_string(),
+                };
+                let nested_playing = playing && self.destination() == node_idx;
+                self.state_machines[*val].append_to_diagram(diagram, Some(&nested_group), nested_playing, blackboard);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animgraph::AnimGraph;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::condition_definitions::ConditionDefinition;
+    use crate::export::DiagramEdgeState;
+    use crate::node_definitions::{GenericNodeDefinition, StateMachineDefinition};
+    use crate::parameter_definitions::ParameterValue;
+    use crate::parameters::Blackboard;
+    use crate::test_fixtures::*;
+    use super::{LayoutResources, StateMachineLayout};
+
+    fn go() -> ConditionDefinition {
+        ConditionDefinition::Trigger {
+            parameter: "go".to_string(),
+        }
+    }
+
+    // Nodes "a" to "e", starting on "a", with a "go" trigger for the transitions to wait on.
+    fn nodes() -> AnimGraphDefinitionBuilder {
+        ["a", "b", "c", "d", "e"]
+            .iter()
+            .fold(AnimGraphDefinitionBuilder::new(), |builder, name| builder.sampler(name, name).looping(true))
+            .parameter("go", ParameterValue::Trigger(false))
+            .root("a")
+    }
+
+    // Pulls the trigger and lets the (instant) transition it fires play out.
+    fn fire(builder: AnimGraphDefinitionBuilder) -> String {
+        let mut graph = graph(&builder.build().unwrap());
+        graph.set_trigger("go".to_string()).unwrap();
+        step(&mut graph, 0.1);
+        current_node(&graph)
+    }
+
+    fn current_node(graph: &AnimGraph) -> String {
+        let diagram = graph.to_diagram();
+        diagram.nodes.iter().find(|n| n.current).unwrap().name.clone()
+    }
+
+    #[test]
+    fn highest_priority_transition_fires() {
+        let builder = nodes()
+            .transition("a", "b").condition(go())
+            .transition("a", "c").condition(go()).priority(2)
+            .transition("a", "d").condition(go()).priority(1);
+        assert_eq!(fire(builder), "c");
+    }
+
+    #[test]
+    fn equal_priorities_fire_the_first_declared() {
+        let builder = nodes()
+            .transition("a", "d").condition(go()).priority(1)
+            .transition("a", "b").condition(go()).priority(1)
+            .transition("a", "c").condition(go()).priority(1);
+        assert_eq!(fire(builder), "d");
+    }
+
+    #[test]
+    fn ties_follow_declaration_order_after_edges_are_removed() {
+        let mut definition = nodes()
+            .transition("a", "b").condition(go())
+            .transition("a", "d").condition(go())
+            .transition("a", "c").condition(go())
+            .build()
+            .unwrap();
+        let node_named = |name: &str| definition.graph.node_weights().find(|(_, n)| n.name() == name).unwrap().0;
+        let (a, b, e) = (node_named("a"), node_named("b"), node_named("e"));
+        let to_b = definition.graph.outputs(a).find(|(_, edge)| edge.to() == b).unwrap().0;
+        let mut transition = definition.graph.remove_edge(to_b).unwrap();
+        // Declared last, though it takes the slot a to b left free.
+        transition.declared = 3;
+        definition.graph.add_edge(transition, a, e).unwrap();
+        let mut graph = graph(&definition);
+        graph.set_trigger("go".to_string()).unwrap();
+        step(&mut graph, 0.1);
+        assert_eq!(current_node(&graph), "d");
+    }
+
+    #[test]
+    fn highest_priority_any_state_transition_fires() {
+        let builder = nodes()
+            .any_state_transition("b").condition(go())
+            .any_state_transition("c").condition(go()).priority(2)
+            .any_state_transition("d").condition(go()).priority(1);
+        assert_eq!(fire(builder), "c");
+    }
+
+    #[test]
+    fn equal_priority_any_state_transitions_fire_the_first_declared() {
+        let builder = nodes()
+            .any_state_transition("d").condition(go())
+            .any_state_transition("b").condition(go())
+            .any_state_transition("c").condition(go());
+        assert_eq!(fire(builder), "d");
+    }
+
+    #[test]
+    fn any_state_transitions_fire_before_own_transitions() {
+        let builder = nodes()
+            .transition("a", "b").condition(go()).priority(10)
+            .any_state_transition("c").condition(go());
+        assert_eq!(fire(builder), "c");
+    }
+
+    #[test]
+    fn own_transitions_fire_when_no_any_state_transition_can() {
+        let builder = nodes()
+            .parameter("hit", ParameterValue::Trigger(false))
+            .transition("a", "b").condition(go())
+            .any_state_transition("c").condition(ConditionDefinition::Trigger {
+                parameter: "hit".to_string(),
+            });
+        assert_eq!(fire(builder), "b");
+    }
+
+    // The (from, to) names of the diagram's edges in `state`.
+    fn edges_in(graph: &AnimGraph, state: DiagramEdgeState) -> Vec<(String, String)> {
+        let diagram = graph.to_diagram();
+        diagram
+            .edges
+            .iter()
+            .filter(|e| e.state == state)
+            .map(|e| (diagram.nodes[e.from].name.clone(), diagram.nodes[e.to].name.clone()))
+            .collect()
+    }
+
+    fn edge(from: &str, to: &str) -> (String, String) {
+        (from.to_string(), to.to_string())
+    }
+
+    // "a" to "b" takes a second, then "b" and "a" lead on to "c" instantly.
+    fn mid_transition(interruptible: bool) -> AnimGraph {
+        let builder = nodes()
+            .transition("a", "b").duration(web_time::Duration::from_secs(1)).interruptible(interruptible)
+            .transition("b", "c")
+            .transition("a", "c");
+        let mut graph = graph(&builder.build().unwrap());
+        graph.set_target_node_by_name("b".to_string()).unwrap();
+        step(&mut graph, 0.5);
+        assert_eq!(edges_in(&graph, DiagramEdgeState::Active), vec![edge("a", "b")]);
+        graph
+    }
+
+    #[test]
+    fn rerouting_mid_transition_starts_where_it_lands() {
+        let mut graph = mid_transition(false);
+        graph.set_target_node_by_name("c".to_string()).unwrap();
+        // The cheaper a to c is behind us; the route goes on from b.
+        assert_eq!(edges_in(&graph, DiagramEdgeState::Active), vec![edge("a", "b")]);
+        assert_eq!(edges_in(&graph, DiagramEdgeState::Queued), vec![edge("b", "c")]);
+        step(&mut graph, 0.6);
+        step(&mut graph, 0.1);
+        assert_eq!(current_node(&graph), "c");
+    }
+
+    #[test]
+    fn rerouting_cuts_interruptible_transitions_short() {
+        let mut graph = mid_transition(true);
+        graph.set_target_node_by_name("c".to_string()).unwrap();
+        assert_eq!(edges_in(&graph, DiagramEdgeState::Active), vec![edge("b", "c")]);
+        assert!(edges_in(&graph, DiagramEdgeState::Queued).is_empty());
+        step(&mut graph, 0.1);
+        assert_eq!(current_node(&graph), "c");
+    }
+
+    #[test]
+    fn unreachable_reroutes_keep_the_current_route() {
+        let mut graph = mid_transition(false);
+        graph.set_target_node_by_name("c".to_string()).unwrap();
+        assert!(graph.set_target_node_by_name("e".to_string()).is_err());
+        assert_eq!(edges_in(&graph, DiagramEdgeState::Queued), vec![edge("b", "c")]);
+    }
+
+    // A nested machine entered on "ready", which attacks in two steps, only routed through.
+    fn combat(exits: &[&str]) -> StateMachineDefinition {
+        let file = AnimGraphDefinitionBuilder::new()
+            .sampler("ready", "a").looping(true)
+            .sampler("attack_1", "b").looping(true)
+            .sampler("attack_2", "c").looping(true)
+            .transition("ready", "attack_1")
+            .transition("attack_1", "attack_2")
+            .root("ready")
+            .build()
+            .unwrap()
+            .to_file()
+            .unwrap();
+        StateMachineDefinition {
+            name: "combat".to_string(),
+            entry: file.root,
+            nodes: file.nodes,
+            transitions: file.transitions,
+            exits: exits.iter().map(|e| e.to_string()).collect(),
+            any_state_transitions: Vec::new(),
+        }
+    }
+
+    // "a" leads to "b" in a second, and both lead to "combat" instantly. "combat" goes back to "a" on its own while
+    // "leave" is set.
+    fn with_combat(exits: &[&str]) -> AnimGraph {
+        let builder = nodes()
+            .node(GenericNodeDefinition::StateMachine(combat(exits)))
+            .parameter("leave", ParameterValue::Bool(false))
+            .transition("a", "b").duration(web_time::Duration::from_secs(1)).interruptible(true)
+            .transition("b", "combat")
+            .transition("a", "combat")
+            .transition("combat", "a").condition(ConditionDefinition::Bool {
+                parameter: "leave".to_string(),
+                value: true,
+            });
+        graph(&builder.build().unwrap())
+    }
+
+    // Every node the diagram marks as current, the parent's first.
+    fn current_nodes(graph: &AnimGraph) -> Vec<String> {
+        graph.to_diagram().nodes.iter().filter(|n| n.current).map(|n| n.name.clone()).collect()
+    }
+
+    #[test]
+    fn nested_paths_route_into_the_nested_machine() {
+        let mut graph = with_combat(&[]);
+        graph.set_target_node_by_name("combat/attack_2".to_string()).unwrap();
+        for _ in 0..4 {
+            step(&mut graph, 0.1);
+        }
+        assert_eq!(current_nodes(&graph), vec!["combat", "attack_2"]);
+        // Once inside, targets route within the nested machine, where nothing leads back from attack_2.
+        assert!(graph.set_target_node_by_name("combat/ready".to_string()).is_err());
+    }
+
+    #[test]
+    fn entering_without_a_nested_path_starts_on_the_entry() {
+        let mut graph = with_combat(&[]);
+        graph.set_target_node_by_name("combat".to_string()).unwrap();
+        step(&mut graph, 0.1);
+        step(&mut graph, 0.1);
+        assert_eq!(current_nodes(&graph), vec!["combat", "ready"]);
+    }
+
+    #[test]
+    fn invalid_nested_paths_are_refused() {
+        let mut graph = with_combat(&[]);
+        assert!(graph.set_target_node_by_name("combat/block".to_string()).is_err());
+        assert!(graph.set_target_node_by_name("b/ready".to_string()).is_err());
+        assert!(graph.set_target_node_by_name("fight/ready".to_string()).is_err());
+        step(&mut graph, 0.1);
+        assert_eq!(current_nodes(&graph), vec!["a"]);
+    }
+
+    #[test]
+    fn rerouting_mid_transition_into_a_nested_path() {
+        let mut graph = with_combat(&[]);
+        graph.set_target_node_by_name("b".to_string()).unwrap();
+        step(&mut graph, 0.5);
+        // Cuts a to b short, entering combat right away.
+        graph.set_target_node_by_name("combat/attack_1".to_string()).unwrap();
+        for _ in 0..30 {
+            step(&mut graph, 0.1);
+        }
+        assert_eq!(current_nodes(&graph), vec!["combat", "attack_1"]);
+        // Nothing is left pending for the next time combat is entered.
+        graph.set_target_node_by_name("a".to_string()).unwrap();
+        step(&mut graph, 0.1);
+        graph.set_target_node_by_name("combat".to_string()).unwrap();
+        for _ in 0..5 {
+            step(&mut graph, 0.1);
+        }
+        assert_eq!(current_nodes(&graph), vec!["combat", "ready"]);
+    }
+
+    #[test]
+    fn nested_machines_only_leave_on_their_own_from_an_exit() {
+        let mut graph = with_combat(&["attack_1"]);
+        graph.set_target_node_by_name("combat".to_string()).unwrap();
+        graph.set_bool("leave".to_string(), true).unwrap();
+        for _ in 0..5 {
+            step(&mut graph, 0.1);
+        }
+        assert_eq!(current_nodes(&graph), vec!["combat", "ready"]);
+        graph.set_target_node_by_name("combat/attack_1".to_string()).unwrap();
+        for _ in 0..5 {
+            step(&mut graph, 0.1);
+        }
+        assert_eq!(current_nodes(&graph), vec!["a"]);
+    }
+
+    #[test]
+    fn nested_machines_without_exits_leave_from_any_state() {
+        let mut graph = with_combat(&[]);
+        graph.set_target_node_by_name("combat".to_string()).unwrap();
+        step(&mut graph, 0.1);
+        graph.set_bool("leave".to_string(), true).unwrap();
+        for _ in 0..5 {
+            step(&mut graph, 0.1);
+        }
+        assert_eq!(current_nodes(&graph), vec!["a"]);
+    }
+
+    #[test]
+    fn unknown_exit_states_are_an_error() {
+        let definition = combat(&["attack_1", "attack_3"]);
+        let (graph, entry) = definition.to_graph().unwrap();
+        let resources = LayoutResources {
+            skeleton: &skeleton(),
+            animations_by_name: &animations(),
+            blackboard: &Blackboard::new(&[]).unwrap(),
+            root_motion: None,
+        };
+        let result = StateMachineLayout::new(&resources, &graph, Some(entry), &[], &definition.exits);
+        assert!(result.is_err_and(|e| e.to_string().contains("attack_3")));
+    }
+
+    // Steps `builder`'s graph by each of `steps` in turn, returning the current node after each. Transitions are
+    // checked before the nodes play, so an exit time crossed during one step fires on the next.
+    fn play(builder: AnimGraphDefinitionBuilder, steps: &[f32]) -> Vec<String> {
+        let mut graph = graph(&builder.build().unwrap());
+        steps
+            .iter()
+            .map(|seconds| {
+                step(&mut graph, *seconds);
+                current_node(&graph)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exit_time_fires_once_the_source_gets_there() {
+        let builder = nodes().transition("a", "b").exit_time(0.5);
+        assert_eq!(play(builder, &[0.3, 0.1, 0.2, 0.01]), vec!["a", "a", "a", "b"]);
+    }
+
+    #[test]
+    fn exit_time_fires_across_a_looping_wrap() {
+        // From 0.8 to 0.1 of the clip, going past 0.9 on the way.
+        let builder = nodes().transition("a", "b").exit_time(0.9);
+        assert_eq!(play(builder, &[0.8, 0.3, 0.01]), vec!["a", "a", "b"]);
+        // The end of a looping clip is reached as it wraps.
+        let builder = nodes().transition("a", "b").exit_time(1.0);
+        assert_eq!(play(builder, &[0.8, 0.3, 0.01]), vec!["a", "a", "b"]);
+        // From 0.5 to 0.8, short of 0.9.
+        let builder = nodes().transition("a", "b").exit_time(0.9);
+        assert_eq!(play(builder, &[0.5, 0.3, 0.01]), vec!["a", "a", "a"]);
+    }
+
+    #[test]
+    fn exit_time_fires_once_a_one_shot_clip_finishes() {
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("a", "a")
+            .sampler("b", "b")
+            .looping(true)
+            .root("a")
+            .transition("a", "b")
+            .exit_time(1.0);
+        assert_eq!(play(builder, &[0.6, 0.6, 0.01]), vec!["a", "a", "b"]);
+    }
+
+    #[test]
+    fn finished_one_shot_clips_fire_as_soon_as_the_conditions_hold() {
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("a", "a")
+            .sampler("b", "b")
+            .looping(true)
+            .parameter("go", ParameterValue::Trigger(false))
+            .root("a")
+            .transition("a", "b")
+            .exit_time(0.5)
+            .condition(go());
+        let mut graph = graph(&builder.build().unwrap());
+        step(&mut graph, 1.5);
+        step(&mut graph, 0.5);
+        assert_eq!(current_node(&graph), "a");
+        graph.set_trigger("go".to_string()).unwrap();
+        step(&mut graph, 0.1);
+        assert_eq!(current_node(&graph), "b");
+    }
+
+    #[test]
+    fn exit_time_and_conditions_must_both_hold() {
+        // The trigger is set from the start, but only the exit time lets it fire.
+        let mut graph = graph(&nodes().transition("a", "b").exit_time(0.5).condition(go()).build().unwrap());
+        graph.set_trigger("go".to_string()).unwrap();
+        step(&mut graph, 0.3);
+        step(&mut graph, 0.3);
+        assert_eq!(current_node(&graph), "a");
+        step(&mut graph, 0.01);
+        assert_eq!(current_node(&graph), "b");
+    }
+
+    #[test]
+    fn conditions_set_after_the_exit_time_wait_for_the_next_loop() {
+        let mut graph = graph(&nodes().transition("a", "b").exit_time(0.5).condition(go()).build().unwrap());
+        step(&mut graph, 0.6);
+        step(&mut graph, 0.1);
+        graph.set_trigger("go".to_string()).unwrap();
+        // Around the loop from 0.7, and past 0.5 again on the last step.
+        for seconds in [0.25, 0.3, 0.3] {
+            step(&mut graph, seconds);
+            assert_eq!(current_node(&graph), "a");
+        }
+        step(&mut graph, 0.01);
+        assert_eq!(current_node(&graph), "b");
+    }
+
+    fn flag() -> ConditionDefinition {
+        ConditionDefinition::Bool {
+            parameter: "flag".to_string(),
+            value: true,
+        }
+    }
+
+    #[test]
+    fn conditions_fire_without_a_target() {
+        let builder = nodes()
+            .parameter("flag", ParameterValue::Bool(false))
+            .transition("a", "b")
+            .condition(flag());
+        let mut graph = graph(&builder.build().unwrap());
+        step(&mut graph, 0.1);
+        assert_eq!(current_node(&graph), "a");
+        graph.set_bool("flag".to_string(), true).unwrap();
+        step(&mut graph, 0.1);
+        assert_eq!(current_node(&graph), "b");
+    }
+
+    #[test]
+    fn conditions_are_ignored_while_routing() {
+        // c would leave for e on its own, but the route to d goes through it.
+        let builder = nodes()
+            .parameter("flag", ParameterValue::Bool(true))
+            .transition("a", "c")
+            .condition(go())
+            .transition("c", "d")
+            .condition(go())
+            .transition("c", "e")
+            .condition(flag());
+        let definition = builder.build().unwrap();
+        let mut routed = graph(&definition);
+        routed.set_target_node_by_name("d".to_string()).unwrap();
+        for _ in 0..4 {
+            step(&mut routed, 0.1);
+            assert!(!current_nodes(&routed).contains(&"e".to_string()));
+            assert!(!edges_in(&routed, DiagramEdgeState::Active).contains(&edge("c", "e")));
+        }
+        assert_eq!(current_node(&routed), "d");
+
+        // Once the route ends on c, it does.
+        let mut resting = graph(&definition);
+        resting.set_target_node_by_name("c".to_string()).unwrap();
+        for _ in 0..4 {
+            step(&mut resting, 0.1);
+        }
+        assert_eq!(current_node(&resting), "e");
+    }
+}
@@ -0,0 +1,124 @@
+// Skeletons, clips and graphs small enough to write by hand, for the unit tests.
+use crate::animgraph::AnimGraph;
+use crate::animgraph_definition::AnimGraphDefinition;
+use crate::shared::*;
+use ozz_animation_rs::*;
+use serde_json::json;
+use std::collections::HashMap;
+
+// Half-precision 1.0, as ozz stores translation and scale keys.
+const F16_ONE: u16 = 15360;
+// The identity rotation, in ozz's compressed quaternion format.
+const IDENTITY_KEY: [u16; 3] = [65531, 65533, 32766];
+
+// A skeleton with a single joint, "root", resting at the origin.
+pub fn skeleton() -> Shared<Skeleton> {
+    let raw = json!({
+        "joint_rest_poses": [SoaTransform::IDENTITY],
+        "joint_parents": [-1],
+        "joint_names": { "root": 0 },
+    });
+    Shared::new(serde_json::from_value(raw).unwrap())
+}
+
+// A root with an arm along x, each bone one unit long, and a head above it:
+// root -> shoulder -> elbow -> hand, and root -> head.
+pub fn arm() -> Shared<Skeleton> {
+    let mut rest_poses = [SoaTransform::IDENTITY; 2];
+    rest_poses[0].translation.set_vec3(2, glam::Vec3::X);
+    rest_poses[0].translation.set_vec3(3, glam::Vec3::X);
+    rest_poses[1].translation.set_vec3(0, glam::Vec3::Y);
+    let raw = json!({
+        "joint_rest_poses": rest_poses,
+        "joint_parents": [-1, 0, 1, 2, 0],
+        "joint_names": { "root": 0, "shoulder": 1, "elbow": 2, "hand": 3, "head": 4 },
+    });
+    Shared::new(serde_json::from_value(raw).unwrap())
+}
+
+// A clip of `duration` seconds moving the root from the origin to 1 along x.
+pub fn clip(duration: f32) -> Shared<Animation> {
+    // One track, padded to four, with a key at the start and one at the end of each.
+    let ratios = [0u16, 0, 0, 0, 1, 1, 1, 1];
+    let previouses = [0u16, 0, 0, 0, 4, 4, 4, 4];
+    let mut translations = vec![[0u16, 0, 0]; 8];
+    translations[4] = [F16_ONE, 0, 0];
+    let raw = json!({
+        "duration": duration,
+        "num_tracks": 1,
+        "name": "clip",
+        "timepoints": [0.0, 1.0],
+        "translations": translations,
+        "t_ratios": ratios,
+        "t_previouses": previouses,
+        "t_iframe_interval": 0.0,
+        "t_iframe_entries": [],
+        "t_iframe_desc": [],
+        "rotations": vec![IDENTITY_KEY; 8],
+        "r_ratios": ratios,
+        "r_previouses": previouses,
+        "r_iframe_interval": 0.0,
+        "r_iframe_entries": [],
+        "r_iframe_desc": [],
+        "scales": vec![[F16_ONE; 3]; 8],
+        "s_ratios": ratios,
+        "s_previouses": previouses,
+        "s_iframe_interval": 0.0,
+        "s_iframe_entries": [],
+        "s_iframe_desc": [],
+    });
+    Shared::new(serde_json::from_value(raw).unwrap())
+}
+
+// A one second clip holding arm()'s rest pose.
+pub fn arm_clip() -> Shared<Animation> {
+    // Five tracks, padded to eight, with a key at the start and one at the end of each.
+    let ratios = [[0u16; 8], [1u16; 8]].concat();
+    let previouses = [[0u16; 8], [8u16; 8]].concat();
+    let mut rest_translations = [[0u16; 3]; 8];
+    rest_translations[2] = [F16_ONE, 0, 0];
+    rest_translations[3] = [F16_ONE, 0, 0];
+    rest_translations[4] = [0, F16_ONE, 0];
+    let translations = [rest_translations, rest_translations].concat();
+    let raw = json!({
+        "duration": 1.0,
+        "num_tracks": 5,
+        "name": "arm",
+        "timepoints": [0.0, 1.0],
+        "translations": translations,
+        "t_ratios": ratios,
+        "t_previouses": previouses,
+        "t_iframe_interval": 0.0,
+        "t_iframe_entries": [],
+        "t_iframe_desc": [],
+        "rotations": vec![IDENTITY_KEY; 16],
+        "r_ratios": ratios,
+        "r_previouses": previouses,
+        "r_iframe_interval": 0.0,
+        "r_iframe_entries": [],
+        "r_iframe_desc": [],
+        "scales": vec![[F16_ONE; 3]; 16],
+        "s_ratios": ratios,
+        "s_previouses": previouses,
+        "s_iframe_interval": 0.0,
+        "s_iframe_entries": [],
+        "s_iframe_desc": [],
+    });
+    Shared::new(serde_json::from_value(raw).unwrap())
+}
+
+// One second clips named "a" to "e".
+pub fn animations() -> HashMap<String, Shared<Animation>> {
+    ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|name| (name.to_string(), clip(1.0)))
+        .collect()
+}
+
+pub fn graph(definition: &AnimGraphDefinition) -> AnimGraph {
+    AnimGraph::new(skeleton(), definition, &animations()).unwrap()
+}
+
+pub fn step(graph: &mut AnimGraph, seconds: f32) {
+    graph.evaluate(web_time::Duration::from_secs_f32(seconds)).unwrap();
+}
@@ -0,0 +1,993 @@
+use crate::animgraph_definition::{AnimGraphDefinition, DefinitionGraph};
+use crate::edge_definitions::{AnyStateTransitionDefinition, TransitionDefinition};
+use crate::layer_definitions::LayerBlendMode;
+use crate::node_definitions::{GenericNodeDefinition, StateMachineDefinition};
+use crate::parameter_definitions::ParameterValue;
+use crate::shared::Shared;
+use anyhow::anyhow;
+use mapgraph::map::slotmap::NodeIndex;
+use ozz_animation_rs::Animation;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    // The graph can be built, but probably doesn't behave as intended.
+    Warning,
+    // The graph can't be built.
+    Error,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ValidationIssue {
+    MissingRoot,
+    InvalidRoot,
+    UnreachableNode { node: String },
+    DeadEndSampler { node: String },
+    DuplicateNodeName { name: String },
+    DuplicateParameterName { name: String },
+    MissingAnimation { node: String, animation: String },
+    SelfLoop { node: String },
+    // An any-state transition to or excluding a node that doesn't exist.
+    UnknownAnyStateNode { node: String },
+    EmptyBlendTree { node: String },
+    InvalidTransitionCurve { context: String, problem: String },
+    InvalidEventTime { node: String, event: String, time: f32 },
+    UnknownParameter { context: String, parameter: String },
+    ParameterTypeMismatch { context: String, parameter: String, expected: &'static str, found: &'static str },
+    DuplicateLayerName { name: String },
+    InvalidLayerWeight { layer: String, weight: f32 },
+    DuplicateIkChainName { name: String },
+    InvalidIkWeight { chain: String, weight: f32 },
+    InvalidIkSoften { chain: String, soften: f32 },
+    EmptyAimIkChain { chain: String },
+    // Additive nodes only make sense in additive layers, and vice versa.
+    AdditiveNodeOutsideAdditiveLayer { node: String },
+    NonAdditiveNodeInAdditiveLayer { node: String },
+    // A nested state machine whose nodes and transitions don't resolve, e.g. an unknown entry state.
+    InvalidStateMachine { machine: String, problem: String },
+    UnknownExitState { machine: String, state: String },
+    // An issue inside a nested state machine.
+    InStateMachine { machine: String, issue: Box<ValidationIssue> },
+    // An issue with the state machine of a layer.
+    InLayer { layer: String, issue: Box<ValidationIssue> },
+}
+
+impl ValidationIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ValidationIssue::InLayer { issue, .. } | ValidationIssue::InStateMachine { issue, .. } => issue.severity(),
+            ValidationIssue::UnreachableNode { .. }
+            | ValidationIssue::DeadEndSampler { .. }
+            | ValidationIssue::SelfLoop { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::MissingRoot => write!(f, "No root node found in animgraph definition"),
+            ValidationIssue::InvalidRoot => write!(f, "Invalid root node in animgraph definition"),
+            ValidationIssue::UnreachableNode { node } => write!(f, "Node {} is unreachable from the root", node),
+            ValidationIssue::DeadEndSampler { node } => {
+                write!(f, "Non-looping sampler {} has no outgoing transitions", node)
+            }
+            ValidationIssue::DuplicateNodeName { name } => write!(f, "Duplicate node name: {}", name),
+            ValidationIssue::DuplicateParameterName { name } => write!(f, "Duplicate parameter name: {}", name),
+            ValidationIssue::MissingAnimation { node, animation } => {
+                write!(f, "Could not find animation name {} used by {}", animation, node)
+            }
+            ValidationIssue::SelfLoop { node } => write!(f, "Node {} has a transition to itself", node),
+            ValidationIssue::UnknownAnyStateNode { node } => {
+                write!(f, "Any-state transition references unknown node {}", node)
+            }
+            ValidationIssue::EmptyBlendTree { node } => write!(f, "Blend node {} has no clips", node),
+            ValidationIssue::InvalidTransitionCurve { context, problem } => write!(f, "{}: {}", context, problem),
+            ValidationIssue::InvalidEventTime { node, event, time } => write!(
+                f,
+                "Event {} on {} is at normalized time {}, outside of [0, 1]",
+                event, node, time
+            ),
+            ValidationIssue::UnknownParameter { context, parameter } => {
+                write!(f, "{} references unknown parameter {}", context, parameter)
+            }
+            ValidationIssue::ParameterTypeMismatch {
+                context,
+                parameter,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} expects {} to be a {} parameter, found {}",
+                context, parameter, expected, found
+            ),
+            ValidationIssue::DuplicateLayerName { name } => write!(f, "Duplicate layer name: {}", name),
+            ValidationIssue::InvalidLayerWeight { layer, weight } => {
+                write!(f, "Layer {} has weight {}, outside of [0, 1]", layer, weight)
+            }
+            ValidationIssue::DuplicateIkChainName { name } => write!(f, "Duplicate IK chain name: {}", name),
+            ValidationIssue::InvalidIkWeight { chain, weight } => {
+                write!(f, "IK chain {} has weight {}, outside of [0, 1]", chain, weight)
+            }
+            ValidationIssue::InvalidIkSoften { chain, soften } => {
+                write!(f, "IK chain {} has soften {}, outside of [0, 1]", chain, soften)
+            }
+            ValidationIssue::EmptyAimIkChain { chain } => write!(f, "Aim IK chain {} has no joints", chain),
+            ValidationIssue::AdditiveNodeOutsideAdditiveLayer { node } => {
+                write!(f, "Additive node {} is outside of an additive layer", node)
+            }
+            ValidationIssue::NonAdditiveNodeInAdditiveLayer { node } => {
+                write!(f, "Node {} is in an additive layer but isn't additive", node)
+            }
+            ValidationIssue::InvalidStateMachine { machine, problem } => {
+                write!(f, "State machine {}: {}", machine, problem)
+            }
+            ValidationIssue::UnknownExitState { machine, state } => {
+                write!(f, "State machine {} has unknown exit state {}", machine, state)
+            }
+            ValidationIssue::InStateMachine { machine, issue } => write!(f, "State machine {}: {}", machine, issue),
+            ValidationIssue::InLayer { layer, issue } => write!(f, "Layer {}: {}", layer, issue),
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    // Whether the definition can be turned into an AnimGraph. Warnings don't count.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|i| i.severity() == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|i| i.severity() == Severity::Warning)
+    }
+
+    // Folds the errors, if any, into a single error.
+    pub fn to_result(&self) -> Result<(), anyhow::Error> {
+        if self.is_valid() {
+            return Ok(());
+        }
+        let errors = self.errors().map(|e| e.to_string()).collect::<Vec<String>>();
+        Err(anyhow!("Invalid animgraph definition: {}", errors.join("; ")))
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            let severity = match issue.severity() {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            writeln!(f, "{}: {}", severity, issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl AnimGraphDefinition {
+    // Checks the structure of the definition, reporting every problem found rather than stopping at the first.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        self.validate_parameter_names(&mut report);
+        self.validate_state_machine(&self.graph, self.root, &self.any_state_transitions, &mut report);
+        validate_blend_mode(self.graph.node_weights().map(|(_, n)| n), false, &mut report);
+        self.validate_layers(&mut report);
+        self.validate_ik_chains(&mut report);
+        report
+    }
+
+    fn validate_state_machine(
+        &self,
+        graph: &DefinitionGraph,
+        root: Option<NodeIndex>,
+        any_state_transitions: &[AnyStateTransitionDefinition],
+        report: &mut ValidationReport,
+    ) {
+        self.validate_root(graph, root, report);
+        self.validate_node_names(graph, report);
+        self.validate_nodes(graph, any_state_transitions, report);
+        self.validate_transitions(graph, report);
+        self.validate_any_state_transitions(graph, any_state_transitions, report);
+        self.validate_reachability(graph, root, any_state_transitions, report);
+    }
+
+    // Each layer is checked as a state machine of its own, its issues reported against it.
+    fn validate_layers(&self, report: &mut ValidationReport) {
+        let mut layer_names = HashSet::<&str>::new();
+        for layer in &self.layers {
+            if !layer_names.insert(layer.name.as_str()) {
+                report.issues.push(ValidationIssue::DuplicateLayerName {
+                    name: layer.name.clone(),
+                });
+            }
+            if !(0.0..=1.0).contains(&layer.weight) {
+                report.issues.push(ValidationIssue::InvalidLayerWeight {
+                    layer: layer.name.clone(),
+                    weight: layer.weight,
+                });
+            }
+            let mut layer_report = ValidationReport::default();
+            self.validate_state_machine(&layer.graph, layer.root, &[], &mut layer_report);
+            validate_blend_mode(
+                layer.graph.node_weights().map(|(_, n)| n),
+                layer.blend_mode == LayerBlendMode::Additive,
+                &mut layer_report,
+            );
+            for issue in layer_report.issues {
+                report.issues.push(ValidationIssue::InLayer {
+                    layer: layer.name.clone(),
+                    issue: Box::new(issue),
+                });
+            }
+        }
+    }
+
+    // Joint names are checked against the skeleton when building the AnimGraph.
+    fn validate_ik_chains(&self, report: &mut ValidationReport) {
+        let mut chain_names = HashSet::<&str>::new();
+        for chain in &self.two_bone_ik {
+            if !chain_names.insert(chain.name.as_str()) {
+                report.issues.push(ValidationIssue::DuplicateIkChainName {
+                    name: chain.name.clone(),
+                });
+            }
+            if !(0.0..=1.0).contains(&chain.weight) {
+                report.issues.push(ValidationIssue::InvalidIkWeight {
+                    chain: chain.name.clone(),
+                    weight: chain.weight,
+                });
+            }
+            if !(0.0..=1.0).contains(&chain.soften) {
+                report.issues.push(ValidationIssue::InvalidIkSoften {
+                    chain: chain.name.clone(),
+                    soften: chain.soften,
+                });
+            }
+        }
+        // Both kinds of chains are looked up by name on the AnimGraph, but separately.
+        let mut chain_names = HashSet::<&str>::new();
+        for chain in &self.aim_ik {
+            if !chain_names.insert(chain.name.as_str()) {
+                report.issues.push(ValidationIssue::DuplicateIkChainName {
+                    name: chain.name.clone(),
+                });
+            }
+            if chain.joints.is_empty() {
+                report.issues.push(ValidationIssue::EmptyAimIkChain {
+                    chain: chain.name.clone(),
+                });
+            }
+            let weights = std::iter::once(chain.weight).chain(chain.joints.iter().map(|j| j.weight));
+            for weight in weights {
+                if !(0.0..=1.0).contains(&weight) {
+                    report.issues.push(ValidationIssue::InvalidIkWeight {
+                        chain: chain.name.clone(),
+                        weight,
+                    });
+                }
+            }
+        }
+    }
+
+    // Same as validate(), also checking that every animation the nodes use is available.
+    pub fn validate_with_animations(
+        &self,
+        animations_by_name: &HashMap<String, Shared<Animation>>,
+    ) -> ValidationReport {
+        let mut report = self.validate();
+        let graphs = std::iter::once(&self.graph).chain(self.layers.iter().map(|l| &l.graph));
+        for graph in graphs {
+            for (_, node_definition) in graph.node_weights() {
+                for animation_name in node_definition.animation_names() {
+                    if !animations_by_name.contains_key(animation_name) {
+                        report.issues.push(ValidationIssue::MissingAnimation {
+                            node: node_definition.name().to_string(),
+                            animation: animation_name.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    fn validate_root(&self, graph: &DefinitionGraph, root: Option<NodeIndex>, report: &mut ValidationReport) {
+        match root {
+            Some(val) => {
+                if graph.node(val).is_none() {
+                    report.issues.push(ValidationIssue::InvalidRoot);
+                }
+            }
+            None => report.issues.push(ValidationIssue::MissingRoot),
+        }
+    }
+
+    fn validate_node_names(&self, graph: &DefinitionGraph, report: &mut ValidationReport) {
+        let mut node_names = HashSet::<&str>::new();
+        for (_, node_definition) in graph.node_weights() {
+            if !node_names.insert(node_definition.name()) {
+                report.issues.push(ValidationIssue::DuplicateNodeName {
+                    name: node_definition.name().to_string(),
+                });
+            }
+        }
+    }
+
+    fn validate_parameter_names(&self, report: &mut ValidationReport) {
+        let mut parameter_names = HashSet::<&str>::new();
+        for parameter in &self.parameters {
+            if !parameter_names.insert(parameter.name.as_str()) {
+                report.issues.push(ValidationIssue::DuplicateParameterName {
+                    name: parameter.name.clone(),
+                });
+            }
+        }
+    }
+
+    fn validate_nodes(
+        &self,
+        graph: &DefinitionGraph,
+        any_state_transitions: &[AnyStateTransitionDefinition],
+        report: &mut ValidationReport,
+    ) {
+        for (node_idx, node_definition) in graph.node_weights() {
+            let name = node_definition.name().to_string();
+            let dead_end =
+                graph.outputs(node_idx).next().is_none() && !leaves_through_any_state(&name, any_state_transitions);
+            // A nested state machine's events are checked along with the rest of it.
+            let events = match node_definition {
+                GenericNodeDefinition::StateMachine(_) => Vec::new(),
+                _ => node_definition.events(),
+            };
+            for event in events {
+                if !(0.0..=1.0).contains(&event.time) {
+                    report.issues.push(ValidationIssue::InvalidEventTime {
+                        node: name.clone(),
+                        event: event.name.clone(),
+                        time: event.time,
+                    });
+                }
+            }
+            match node_definition {
+                GenericNodeDefinition::Sampler(val) => {
+                    if !val.looping && dead_end {
+                        report.issues.push(ValidationIssue::DeadEndSampler { node: name });
+                    }
+                }
+                GenericNodeDefinition::Additive(val) => {
+                    if !val.looping && dead_end {
+                        report.issues.push(ValidationIssue::DeadEndSampler { node: name });
+                    }
+                }
+                GenericNodeDefinition::StateMachine(val) => self.validate_nested_state_machine(val, report),
+                GenericNodeDefinition::BlendTreeOneDim(val) => {
+                    if val.clips.is_empty() {
+                        report.issues.push(ValidationIssue::EmptyBlendTree { node: name.clone() });
+                    }
+                    if let Some(param_name) = &val.param_name {
+                        self.validate_parameter(
+                            report,
+                            format!("Blend tree {}", name),
+                            param_name,
+                            &[ParameterValue::Float(0.0), ParameterValue::Int(0)],
+                        );
+                    }
+                }
+                GenericNodeDefinition::BlendSpaceTwoDim(val) => {
+                    if val.clips.is_empty() {
+                        report.issues.push(ValidationIssue::EmptyBlendTree { node: name.clone() });
+                    }
+                    if let Some(param_name) = &val.param_name {
+                        self.validate_parameter(
+                            report,
+                            format!("Blend space {}", name),
+                            param_name,
+                            &[ParameterValue::Vec2(glam::Vec2::ZERO)],
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Checked as a state machine of its own, its issues reported against it.
+    fn validate_nested_state_machine(&self, machine: &StateMachineDefinition, report: &mut ValidationReport) {
+        let (graph, entry) = match machine.to_graph() {
+            Ok(val) => val,
+            Err(e) => {
+                report.issues.push(ValidationIssue::InvalidStateMachine {
+                    machine: machine.name.clone(),
+                    problem: e.to_string(),
+                });
+                return;
+            }
+        };
+        for exit in &machine.exits {
+            if !machine.nodes.iter().any(|n| n.name() == exit) {
+                report.issues.push(ValidationIssue::UnknownExitState {
+                    machine: machine.name.clone(),
+                    state: exit.clone(),
+                });
+            }
+        }
+        let mut machine_report = ValidationReport::default();
+        self.validate_state_machine(&graph, Some(entry), &machine.any_state_transitions, &mut machine_report);
+        for issue in machine_report.issues {
+            report.issues.push(ValidationIssue::InStateMachine {
+                machine: machine.name.clone(),
+                issue: Box::new(issue),
+            });
+        }
+    }
+
+    fn validate_transitions(&self, graph: &DefinitionGraph, report: &mut ValidationReport) {
+        for (edge_idx, transition_definition) in graph.edge_weights() {
+            let edge = graph.edge(edge_idx).unwrap();
+            let from_name = node_name(graph, edge.from());
+            let to_name = node_name(graph, edge.to());
+            if edge.from() == edge.to() {
+                report.issues.push(ValidationIssue::SelfLoop {
+                    node: from_name.clone(),
+                });
+            }
+            let context = format!("Transition {} -> {}", from_name, to_name);
+            self.validate_transition(transition_definition, context, report);
+        }
+    }
+
+    fn validate_any_state_transitions(
+        &self,
+        graph: &DefinitionGraph,
+        any_state_transitions: &[AnyStateTransitionDefinition],
+        report: &mut ValidationReport,
+    ) {
+        for any_state in any_state_transitions {
+            for node in std::iter::once(&any_state.to).chain(&any_state.exclude) {
+                if node_by_name(graph, node).is_none() {
+                    report.issues.push(ValidationIssue::UnknownAnyStateNode { node: node.clone() });
+                }
+            }
+            let context = format!("Any-state transition -> {}", any_state.to);
+            self.validate_transition(&any_state.transition, context, report);
+        }
+    }
+
+    fn validate_transition(
+        &self,
+        transition_definition: &TransitionDefinition,
+        context: String,
+        report: &mut ValidationReport,
+    ) {
+        if let Some(problem) = transition_definition.curve.problem() {
+            report.issues.push(ValidationIssue::InvalidTransitionCurve {
+                context: context.clone(),
+                problem,
+            });
+        }
+        for condition in &transition_definition.conditions {
+            for (parameter, expected) in condition.parameters() {
+                self.validate_parameter(report, context.clone(), parameter, &[expected]);
+            }
+        }
+    }
+
+    fn validate_reachability(
+        &self,
+        graph: &DefinitionGraph,
+        root: Option<NodeIndex>,
+        any_state_transitions: &[AnyStateTransitionDefinition],
+        report: &mut ValidationReport,
+    ) {
+        let root = match root {
+            Some(val) if graph.node(val).is_some() => val,
+            _ => return,
+        };
+        let mut visited = HashSet::<NodeIndex>::new();
+        let mut queue = VecDeque::<NodeIndex>::new();
+        visited.insert(root);
+        queue.push_back(root);
+        loop {
+            while let Some(node_idx) = queue.pop_front() {
+                for (_, edge_ref) in graph.outputs(node_idx) {
+                    if visited.insert(edge_ref.to()) {
+                        queue.push_back(edge_ref.to());
+                    }
+                }
+            }
+            // An any-state transition reaches its destination once any node it may leave from is reached.
+            for any_state in any_state_transitions {
+                if let Some(to) = node_by_name(graph, &any_state.to)
+                    && !visited.contains(&to)
+                    && visited.iter().any(|n| !any_state.exclude.iter().any(|e| *e == node_name(graph, *n)))
+                {
+                    visited.insert(to);
+                    queue.push_back(to);
+                }
+            }
+            if queue.is_empty() {
+                break;
+            }
+        }
+        for (node_idx, node_definition) in graph.node_weights() {
+            if !visited.contains(&node_idx) {
+                report.issues.push(ValidationIssue::UnreachableNode {
+                    node: node_definition.name().to_string(),
+                });
+            }
+        }
+    }
+
+    // Reports `parameter_name` if it isn't declared, or if it isn't of one of the `accepted` types.
+    fn validate_parameter(
+        &self,
+        report: &mut ValidationReport,
+        context: String,
+        parameter_name: &str,
+        accepted: &[ParameterValue],
+    ) {
+        match self.parameters.iter().find(|p| p.name == parameter_name) {
+            Some(parameter) => {
+                if !accepted.iter().any(|a| a.same_type(&parameter.default_value)) {
+                    report.issues.push(ValidationIssue::ParameterTypeMismatch {
+                        context,
+                        parameter: parameter_name.to_string(),
+                        expected: accepted[0].type_name(),
+                        found: parameter.default_value.type_name(),
+                    });
+                }
+            }
+            None => report.issues.push(ValidationIssue::UnknownParameter {
+                context,
+                parameter: parameter_name.to_string(),
+            }),
+        }
+    }
+}
+
+fn node_by_name(graph: &DefinitionGraph, name: &str) -> Option<NodeIndex> {
+    graph
+        .node_weights()
+        .find(|(_, node_definition)| node_definition.name() == name)
+        .map(|(node_idx, _)| node_idx)
+}
+
+// Whether some any-state transition may leave from the node named `name`.
+fn leaves_through_any_state(name: &str, any_state_transitions: &[AnyStateTransitionDefinition]) -> bool {
+    any_state_transitions
+        .iter()
+        .any(|t| t.to != name && !t.exclude.iter().any(|e| e == name))
+}
+
+fn node_name(graph: &DefinitionGraph, node_idx: NodeIndex) -> String {
+    match graph.node(node_idx) {
+        Some(node) => node.weight().name().to_string(),
+        None => String::from("<invalid>"),
+    }
+}
+
+// Nested state machines take the blend mode of the graph they are in.
+fn validate_blend_mode<'a>(
+    nodes: impl Iterator<Item = &'a GenericNodeDefinition>,
+    additive: bool,
+    report: &mut ValidationReport,
+) {
+    for node_definition in nodes {
+        let name = node_definition.name().to_string();
+        match (node_definition, additive) {
+            (GenericNodeDefinition::StateMachine(val), _) => {
+                let mut machine_report = ValidationReport::default();
+                validate_blend_mode(val.nodes.iter(), additive, &mut machine_report);
+                for issue in machine_report.issues {
+                    report.issues.push(ValidationIssue::InStateMachine {
+                        machine: name.clone(),
+                        issue: Box::new(issue),
+                    });
+                }
+            }
+            (GenericNodeDefinition::Additive(_), false) => {
+                report.issues.push(ValidationIssue::AdditiveNodeOutsideAdditiveLayer { node: name });
+            }
+            (GenericNodeDefinition::Additive(_), true) | (_, false) => {}
+            (_, true) => {
+                report.issues.push(ValidationIssue::NonAdditiveNodeInAdditiveLayer { node: name });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::AnimGraphDefinitionBuilder;
+    use crate::condition_definitions::ConditionDefinition;
+    use crate::curves::TransitionCurve;
+    use crate::definition_file::AnimGraphFile;
+    use crate::ik_definitions::{AimIkDefinition, TwoBoneIkDefinition};
+    use crate::parameter_definitions::ParameterDefinition;
+    use crate::test_fixtures::*;
+
+    // "idle" and "walk", looping and leading to one another.
+    fn base() -> AnimGraphDefinitionBuilder {
+        AnimGraphDefinitionBuilder::new()
+            .sampler("idle", "a").looping(true)
+            .sampler("walk", "b").looping(true)
+            .transition("idle", "walk")
+            .transition("walk", "idle")
+            .root("idle")
+    }
+
+    // Goes through the file form, so that the edit can break what the builder would refuse.
+    fn edited(builder: AnimGraphDefinitionBuilder, edit: impl FnOnce(&mut AnimGraphFile)) -> AnimGraphDefinition {
+        let mut file = builder.build().unwrap().to_file().unwrap();
+        edit(&mut file);
+        AnimGraphDefinition::from_file(file).unwrap()
+    }
+
+    fn issues(definition: &AnimGraphDefinition) -> Vec<ValidationIssue> {
+        definition.validate().issues
+    }
+
+    fn node(name: &str) -> String {
+        name.to_string()
+    }
+
+    fn float_parameter(name: &str) -> ParameterDefinition {
+        ParameterDefinition {
+            name: name.to_string(),
+            default_value: ParameterValue::Float(0.0),
+        }
+    }
+
+    fn two_bone_ik(name: &str) -> TwoBoneIkDefinition {
+        TwoBoneIkDefinition {
+            name: name.to_string(),
+            start_joint: "root".to_string(),
+            mid_joint: "root".to_string(),
+            end_joint: "root".to_string(),
+            mid_axis: glam::Vec3::Z,
+            soften: 1.0,
+            twist_angle: 0.0,
+            weight: 1.0,
+        }
+    }
+
+    fn aim_ik(name: &str) -> AimIkDefinition {
+        AimIkDefinition {
+            name: name.to_string(),
+            joints: Vec::new(),
+            forward: glam::Vec3::X,
+            up: glam::Vec3::Y,
+            offset: glam::Vec3::ZERO,
+            weight: 1.0,
+        }
+    }
+
+    #[test]
+    fn a_sound_definition_has_no_issues() {
+        let report = base().build().unwrap().validate();
+        assert!(report.issues.is_empty() && report.is_valid());
+        assert!(report.to_result().is_ok());
+    }
+
+    #[test]
+    fn missing_and_invalid_roots() {
+        let mut definition = base().build().unwrap();
+        definition.root = None;
+        assert_eq!(issues(&definition), vec![ValidationIssue::MissingRoot]);
+        let root = definition.graph.add_node(definition.graph.node_weights().next().unwrap().1.clone());
+        definition.graph.remove_node(root).unwrap();
+        definition.root = Some(root);
+        assert_eq!(issues(&definition), vec![ValidationIssue::InvalidRoot]);
+    }
+
+    #[test]
+    fn unreachable_nodes_are_warned_about() {
+        let definition = base().sampler("jump", "c").looping(true).transition("jump", "idle").build().unwrap();
+        let report = definition.validate();
+        assert_eq!(report.issues, vec![ValidationIssue::UnreachableNode { node: node("jump") }]);
+        assert!(report.is_valid());
+        assert_eq!(report.warnings().count(), 1);
+    }
+
+    #[test]
+    fn any_state_transitions_reach_their_destination() {
+        let definition = base().sampler("hit", "c").looping(true).any_state_transition("hit").build().unwrap();
+        assert!(issues(&definition).is_empty());
+        // Unless every node reached is excluded from leaving through it.
+        let definition = AnimGraphDefinitionBuilder::new()
+            .sampler("idle", "a").looping(true)
+            .sampler("hit", "c").looping(true)
+            .any_state_transition("hit").exclude("idle")
+            .root("idle")
+            .build()
+            .unwrap();
+        assert_eq!(issues(&definition), vec![ValidationIssue::UnreachableNode { node: node("hit") }]);
+    }
+
+    #[test]
+    fn non_looping_samplers_need_a_way_out() {
+        let definition = base().sampler("jump", "c").transition("idle", "jump").build().unwrap();
+        assert_eq!(issues(&definition), vec![ValidationIssue::DeadEndSampler { node: node("jump") }]);
+        let definition = base().sampler("jump", "c").transition("idle", "jump").transition("jump", "idle").build();
+        assert!(issues(&definition.unwrap()).is_empty());
+    }
+
+    #[test]
+    fn any_state_transitions_are_a_way_out() {
+        let builder = base().sampler("jump", "c").transition("idle", "jump");
+        let definition = builder.any_state_transition("idle").build().unwrap();
+        assert!(issues(&definition).is_empty());
+        // Not when excluded from it.
+        let builder = base().sampler("jump", "c").transition("idle", "jump");
+        let definition = builder.any_state_transition("idle").exclude("jump").build().unwrap();
+        assert_eq!(issues(&definition), vec![ValidationIssue::DeadEndSampler { node: node("jump") }]);
+    }
+
+    #[test]
+    fn self_loops_are_warned_about() {
+        let definition = base().transition("walk", "walk").build().unwrap();
+        assert_eq!(issues(&definition), vec![ValidationIssue::SelfLoop { node: node("walk") }]);
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let mut definition = base().build().unwrap();
+        let walk = definition.graph.node_weights().find(|(_, n)| n.name() == "walk").unwrap().1.clone();
+        let duplicate = definition.graph.add_node(walk);
+        let idle = definition.root.unwrap();
+        definition.graph.add_edge(definition.graph.edge_weights().next().unwrap().1.clone(), idle, duplicate).unwrap();
+        definition.parameters = vec![float_parameter("speed"), float_parameter("speed")];
+        assert_eq!(
+            issues(&definition),
+            vec![
+                ValidationIssue::DuplicateParameterName { name: "speed".to_string() },
+                ValidationIssue::DuplicateNodeName { name: node("walk") },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_animations_are_only_checked_against_the_animations() {
+        let definition = base().sampler("jump", "f").looping(true).transition("idle", "jump").build().unwrap();
+        assert!(definition.validate().is_valid());
+        let report = definition.validate_with_animations(&animations());
+        assert_eq!(
+            report.issues,
+            vec![ValidationIssue::MissingAnimation { node: node("jump"), animation: "f".to_string() }]
+        );
+        assert!(report.to_result().is_err_and(|e| e.to_string().contains("animation name f used by jump")));
+    }
+
+    #[test]
+    fn any_state_transitions_to_unknown_nodes() {
+        let definition = edited(base().any_state_transition("walk").exclude("idle"), |file| {
+            file.any_state_transitions[0].to = "run".to_string();
+            file.any_state_transitions[0].exclude[0] = "crouch".to_string();
+        });
+        assert_eq!(
+            issues(&definition),
+            vec![
+                ValidationIssue::UnknownAnyStateNode { node: node("run") },
+                ValidationIssue::UnknownAnyStateNode { node: node("crouch") },
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_blend_trees() {
+        let builder = base().blend_1d("run", &[("c", 0.0)]).transition("idle", "run").transition("run", "idle");
+        let definition = edited(builder, |file| match &mut file.nodes[2] {
+            GenericNodeDefinition::BlendTreeOneDim(val) => val.clips.clear(),
+            _ => unreachable!(),
+        });
+        assert_eq!(issues(&definition), vec![ValidationIssue::EmptyBlendTree { node: node("run") }]);
+    }
+
+    #[test]
+    fn invalid_transition_curves() {
+        let definition = edited(base(), |file| file.transitions[0].transition.curve = TransitionCurve::Sampled(vec![]));
+        assert_eq!(
+            issues(&definition),
+            vec![ValidationIssue::InvalidTransitionCurve {
+                context: "Transition idle -> walk".to_string(),
+                problem: "sampled curve has no samples".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn events_out_of_range() {
+        let builder = AnimGraphDefinitionBuilder::new()
+            .sampler("walk", "b").looping(true)
+            .event("step", 0.5, None)
+            .root("walk");
+        let definition = edited(builder, |file| match &mut file.nodes[0] {
+            GenericNodeDefinition::Sampler(val) => val.events[0].time = 1.5,
+            _ => unreachable!(),
+        });
+        assert_eq!(
+            issues(&definition),
+            vec![ValidationIssue::InvalidEventTime { node: node("walk"), event: "step".to_string(), time: 1.5 }]
+        );
+    }
+
+    #[test]
+    fn conditions_on_unknown_parameters() {
+        let condition = ConditionDefinition::Trigger { parameter: "jump".to_string() };
+        let definition = edited(base(), |file| file.transitions[0].transition.conditions.push(condition));
+        assert_eq!(
+            issues(&definition),
+            vec![ValidationIssue::UnknownParameter {
+                context: "Transition idle -> walk".to_string(),
+                parameter: "jump".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn conditions_on_parameters_of_another_type() {
+        let condition = ConditionDefinition::Bool { parameter: "speed".to_string(), value: true };
+        let definition = edited(base(), |file| {
+            file.parameters.push(float_parameter("speed"));
+            file.transitions[0].transition.conditions.push(condition);
+        });
+        assert_eq!(
+            issues(&definition),
+            vec![ValidationIssue::ParameterTypeMismatch {
+                context: "Transition idle -> walk".to_string(),
+                parameter: "speed".to_string(),
+                expected: "bool",
+                found: "float",
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_layers() {
+        let definition = edited(base().layer("upper").root("wave").sampler("wave", "c").looping(true), |file| {
+            file.layers.push(file.layers[0].clone());
+            file.layers[0].weight = 1.5;
+        });
+        assert_eq!(
+            issues(&definition),
+            vec![
+                ValidationIssue::InvalidLayerWeight { layer: "upper".to_string(), weight: 1.5 },
+                ValidationIssue::DuplicateLayerName { name: "upper".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn issues_inside_layers_name_their_layer() {
+        let builder = base().layer("upper").root("wave").sampler("wave", "c").looping(true).sampler("nod", "d");
+        let definition = builder.build().unwrap();
+        assert_eq!(
+            issues(&definition),
+            vec![
+                ValidationIssue::InLayer {
+                    layer: "upper".to_string(),
+                    issue: Box::new(ValidationIssue::DeadEndSampler { node: node("nod") }),
+                },
+                ValidationIssue::InLayer {
+                    layer: "upper".to_string(),
+                    issue: Box::new(ValidationIssue::UnreachableNode { node: node("nod") }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn additive_nodes_and_layers_go_together() {
+        let builder = base().layer("breathe").blend_mode(LayerBlendMode::Additive).root("lean").additive("lean", "c");
+        let definition = edited(builder.looping(true), |file| {
+            let lean = file.layers[0].nodes.pop().unwrap();
+            file.layers[0].nodes.push(file.nodes[1].clone());
+            file.layers[0].root = "walk".to_string();
+            file.nodes.push(lean);
+            file.transitions.push(file.transitions[0].clone());
+            file.transitions[2].to = "lean".to_string();
+        });
+        assert_eq!(
+            issues(&definition),
+            vec![
+                ValidationIssue::AdditiveNodeOutsideAdditiveLayer { node: node("lean") },
+                ValidationIssue::InLayer {
+                    layer: "breathe".to_string(),
+                    issue: Box::new(ValidationIssue::NonAdditiveNodeInAdditiveLayer { node: node("walk") }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_ik_chains() {
+        let mut definition = base().build().unwrap();
+        let mut soft = two_bone_ik("leg");
+        soft.soften = -0.5;
+        soft.weight = 2.0;
+        definition.two_bone_ik = vec![two_bone_ik("leg"), soft];
+        // Aim chains are looked up apart from two-bone ones, so they may share a name.
+        definition.aim_ik = vec![aim_ik("leg")];
+        assert_eq!(
+            issues(&definition),
+            vec![
+                ValidationIssue::DuplicateIkChainName { name: "leg".to_string() },
+                ValidationIssue::InvalidIkWeight { chain: "leg".to_string(), weight: 2.0 },
+                ValidationIssue::InvalidIkSoften { chain: "leg".to_string(), soften: -0.5 },
+                ValidationIssue::EmptyAimIkChain { chain: "leg".to_string() },
+            ]
+        );
+    }
+
+    // A nested machine entered on "ready" and leaving from "done".
+    fn combat() -> StateMachineDefinition {
+        let file = AnimGraphDefinitionBuilder::new()
+            .sampler("ready", "c").looping(true)
+            .sampler("done", "d").looping(true)
+            .transition("ready", "done")
+            .root("ready")
+            .build()
+            .unwrap()
+            .to_file()
+            .unwrap();
+        StateMachineDefinition {
+            name: "combat".to_string(),
+            entry: file.root,
+            nodes: file.nodes,
+            transitions: file.transitions,
+            exits: vec!["done".to_string()],
+            any_state_transitions: Vec::new(),
+        }
+    }
+
+    fn with_combat(edit: impl FnOnce(&mut StateMachineDefinition)) -> AnimGraphDefinition {
+        let builder = base().node(GenericNodeDefinition::StateMachine(combat()));
+        edited(builder.transition("idle", "combat").transition("combat", "idle"), |file| match &mut file.nodes[2] {
+            GenericNodeDefinition::StateMachine(val) => edit(val),
+            _ => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn nested_state_machines() {
+        assert!(issues(&with_combat(|_| {})).is_empty());
+        let definition = with_combat(|combat| combat.entry = "block".to_string());
+        assert_eq!(
+            issues(&definition),
+            vec![ValidationIssue::InvalidStateMachine {
+                machine: "combat".to_string(),
+                problem: "Unknown root node block".to_string(),
+            }]
+        );
+        let definition = with_combat(|combat| combat.exits.push("flee".to_string()));
+        assert_eq!(
+            issues(&definition),
+            vec![ValidationIssue::UnknownExitState { machine: "combat".to_string(), state: "flee".to_string() }]
+        );
+    }
+
+    #[test]
+    fn issues_inside_nested_state_machines_name_their_machine() {
+        let definition = with_combat(|combat| combat.transitions.clear());
+        assert_eq!(
+            issues(&definition),
+            vec![ValidationIssue::InStateMachine {
+                machine: "combat".to_string(),
+                issue: Box::new(ValidationIssue::UnreachableNode { node: node("done") }),
+            }]
+        );
+    }
+}